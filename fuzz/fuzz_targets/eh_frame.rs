@@ -0,0 +1,21 @@
+//! Feeds arbitrary bytes to the `.eh_frame` parser, which should return an error rather than
+//! panic or hang on malformed input.
+//!
+//! Run with `cargo +nightly fuzz run eh_frame` from the repository root.
+
+#![no_main]
+
+use byteorder::LittleEndian;
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use tfbindiff::eh_frame::{get_fdes, EhFrameBases};
+
+fuzz_target!(|data: &[u8]| {
+    let bases = EhFrameBases {
+        eh_frame: 0x1000,
+        ..Default::default()
+    };
+    for pointer_size in [4, 8] {
+        let _ = get_fdes::<LittleEndian, _>(&mut Cursor::new(data), pointer_size, &bases);
+    }
+});
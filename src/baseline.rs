@@ -0,0 +1,188 @@
+use crate::compare::ProgramComparison;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FunctionStatus {
+    Changed,
+    Same,
+    OnlyInPrimary,
+    OnlyInSecondary,
+}
+
+impl FunctionStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FunctionStatus::Changed => "changed",
+            FunctionStatus::Same => "same",
+            FunctionStatus::OnlyInPrimary => "only-in-primary",
+            FunctionStatus::OnlyInSecondary => "only-in-secondary",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FunctionState {
+    pub status: FunctionStatus,
+    /// Only set for matched functions.
+    pub similarity: Option<f32>,
+}
+
+/// The status of every function in a known good run, to check later runs against. Holds one map
+/// per comparison, keyed by the function's symbol.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Baseline {
+    comparisons: Vec<BTreeMap<String, FunctionState>>,
+}
+
+/// A function whose status changed since the baseline.
+pub struct StatusChange {
+    pub name: String,
+    pub before: FunctionState,
+    pub after: FunctionState,
+}
+
+/// How a comparison differs from its baseline. Functions that aren't in the baseline are left
+/// out.
+#[derive(Default)]
+pub struct BaselineDelta {
+    /// Functions that were the same in the baseline, but aren't anymore.
+    pub regressed: Vec<StatusChange>,
+    /// Functions that weren't the same in the baseline, but are now.
+    pub fixed: Vec<StatusChange>,
+}
+
+impl Baseline {
+    /// Identical functions are only listed if they were kept, see
+    /// `CompareOptions::keep_identical`.
+    pub fn from_comparisons(comparisons: &[ProgramComparison]) -> Self {
+        let comparisons = comparisons
+            .iter()
+            .map(|comparison| {
+                let mut functions = BTreeMap::new();
+                let mut insert = |name: &str, status, similarity| {
+                    functions.insert(name.to_string(), FunctionState { status, similarity });
+                };
+
+                for change in &comparison.changes {
+                    insert(
+                        change.name(),
+                        FunctionStatus::Changed,
+                        Some(change.similarity()),
+                    );
+                }
+                for function in &comparison.identical {
+                    insert(function.name(), FunctionStatus::Same, Some(1.0));
+                }
+                for function in &comparison.only_in_primary {
+                    insert(function.name(), FunctionStatus::OnlyInPrimary, None);
+                }
+                for function in &comparison.only_in_secondary {
+                    insert(function.name(), FunctionStatus::OnlyInSecondary, None);
+                }
+
+                functions
+            })
+            .collect();
+
+        Self { comparisons }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)?;
+
+        out.flush()
+    }
+
+    /// Number of comparisons in the baseline, which should match the run it's checked against.
+    pub fn comparison_count(&self) -> usize {
+        self.comparisons.len()
+    }
+
+    /// Compares the `idx`th comparison of `current` against the same one in the baseline.
+    pub fn delta(&self, current: &Baseline, idx: usize) -> BaselineDelta {
+        let mut delta = BaselineDelta::default();
+        let (Some(baseline), Some(current)) =
+            (self.comparisons.get(idx), current.comparisons.get(idx))
+        else {
+            return delta;
+        };
+
+        for (name, after) in current {
+            let Some(before) = baseline.get(name) else {
+                continue;
+            };
+
+            let was_same = before.status == FunctionStatus::Same;
+            let is_same = after.status == FunctionStatus::Same;
+            let list = match (was_same, is_same) {
+                (true, false) => &mut delta.regressed,
+                (false, true) => &mut delta.fixed,
+                _ => continue,
+            };
+            list.push(StatusChange {
+                name: name.clone(),
+                before: *before,
+                after: *after,
+            });
+        }
+
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comparison(functions: &[(&str, FunctionStatus)]) -> BTreeMap<String, FunctionState> {
+        functions
+            .iter()
+            .map(|&(name, status)| {
+                let similarity = (status == FunctionStatus::Same).then_some(1.0);
+                (name.to_string(), FunctionState { status, similarity })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_regressed_and_fixed_functions() {
+        use FunctionStatus::*;
+
+        let baseline = Baseline {
+            comparisons: vec![comparison(&[
+                ("a", Same),
+                ("b", Changed),
+                ("c", Same),
+                ("d", OnlyInPrimary),
+            ])],
+        };
+        let current = Baseline {
+            comparisons: vec![comparison(&[
+                ("a", Changed),
+                ("b", Same),
+                ("c", Same),
+                ("d", Same),
+                ("e", Changed),
+            ])],
+        };
+
+        let delta = baseline.delta(&current, 0);
+        fn names(changes: &[StatusChange]) -> Vec<&str> {
+            changes.iter().map(|change| change.name.as_str()).collect()
+        }
+        assert_eq!(names(&delta.regressed), ["a"]);
+        assert_eq!(names(&delta.fixed), ["b", "d"]);
+    }
+}
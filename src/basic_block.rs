@@ -0,0 +1,139 @@
+use crate::instruction_wrapper::InstructionWrapper;
+use iced_x86::{FlowControl, OpKind};
+use similar::algorithms::{Capture, Replace};
+use similar::DiffOp;
+use std::ops::Range;
+
+/// A run of instructions with a single entry and exit. Two blocks are equal if all of their
+/// instructions are.
+#[derive(PartialEq)]
+struct BasicBlock<'a>(&'a [InstructionWrapper]);
+
+/// Splits a function into basic blocks, returned as index ranges into `instructions`. Blocks end
+/// after any instruction that changes control flow, and start at every in-function branch target.
+pub fn split(instructions: &[InstructionWrapper]) -> Vec<Range<usize>> {
+    let mut targets: Vec<u64> = instructions
+        .iter()
+        .map(InstructionWrapper::get)
+        .filter(|instr| {
+            matches!(
+                instr.op0_kind(),
+                OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+            )
+        })
+        .map(|instr| instr.near_branch_target())
+        .collect();
+    targets.sort_unstable();
+
+    let mut blocks = vec![];
+    let mut block_start = 0;
+    for (idx, instr) in instructions.iter().enumerate() {
+        let starts_block = idx != block_start && targets.binary_search(&instr.get().ip()).is_ok();
+        if starts_block {
+            blocks.push(block_start..idx);
+            block_start = idx;
+        }
+
+        if instr.get().flow_control() != FlowControl::Next {
+            blocks.push(block_start..idx + 1);
+            block_start = idx + 1;
+        }
+    }
+
+    if block_start != instructions.len() {
+        blocks.push(block_start..instructions.len());
+    }
+
+    blocks
+}
+
+fn myers_diff<Old, New>(
+    old: &Old,
+    old_range: Range<usize>,
+    new: &New,
+    new_range: Range<usize>,
+) -> Vec<DiffOp>
+where
+    Old: std::ops::Index<usize> + ?Sized,
+    New: std::ops::Index<usize> + ?Sized,
+    New::Output: PartialEq<Old::Output>,
+{
+    let mut hook = Replace::new(Capture::new());
+    similar::algorithms::myers::diff(&mut hook, old, old_range, new, new_range)
+        .expect("capturing a diff can't fail");
+
+    hook.into_inner().into_ops()
+}
+
+fn block_range(blocks: &[Range<usize>], first: usize, len: usize) -> Range<usize> {
+    if len == 0 {
+        // Empty block runs still need a position, use the start of the next block
+        let position = blocks.get(first).map_or_else(
+            || blocks.last().map_or(0, |block| block.end),
+            |block| block.start,
+        );
+        return position..position;
+    }
+
+    blocks[first].start..blocks[first + len - 1].end
+}
+
+/// Diffs two functions by first aligning their basic blocks, and then diffing the instructions
+/// within blocks that didn't match exactly. The resulting ops index into the instruction slices,
+/// just like a flat instruction diff.
+pub fn diff(
+    instructions1: &[InstructionWrapper],
+    instructions2: &[InstructionWrapper],
+) -> Vec<DiffOp> {
+    let blocks1 = split(instructions1);
+    let blocks2 = split(instructions2);
+
+    let block_slices1: Vec<_> = blocks1
+        .iter()
+        .map(|range| BasicBlock(&instructions1[range.clone()]))
+        .collect();
+    let block_slices2: Vec<_> = blocks2
+        .iter()
+        .map(|range| BasicBlock(&instructions2[range.clone()]))
+        .collect();
+
+    let block_ops = myers_diff(
+        block_slices1.as_slice(),
+        0..block_slices1.len(),
+        block_slices2.as_slice(),
+        0..block_slices2.len(),
+    );
+
+    let mut ops = vec![];
+    for op in block_ops {
+        let (_, old_blocks, new_blocks) = op.as_tag_tuple();
+        let old_range = block_range(&blocks1, old_blocks.start, old_blocks.len());
+        let new_range = block_range(&blocks2, new_blocks.start, new_blocks.len());
+
+        match op {
+            DiffOp::Equal { .. } => ops.push(DiffOp::Equal {
+                old_index: old_range.start,
+                new_index: new_range.start,
+                len: old_range.len(),
+            }),
+            DiffOp::Delete { .. } => ops.push(DiffOp::Delete {
+                old_index: old_range.start,
+                old_len: old_range.len(),
+                new_index: new_range.start,
+            }),
+            DiffOp::Insert { .. } => ops.push(DiffOp::Insert {
+                old_index: old_range.start,
+                new_index: new_range.start,
+                new_len: new_range.len(),
+            }),
+            DiffOp::Replace { .. } => ops.extend(myers_diff(
+                instructions1,
+                old_range,
+                instructions2,
+                new_range,
+            )),
+        }
+    }
+
+    ops
+}
@@ -0,0 +1,98 @@
+//! Times loading, decoding and comparing a pair of binaries, to have numbers to compare
+//! performance changes against.
+//!
+//! Usage: bench <primary> <secondary>
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tfbindiff::compare::{compare_programs, CompareOptions};
+use tfbindiff::error::TfbindiffError;
+use tfbindiff::program::{LoadOptions, Program};
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+/// Decodes every function, returning the number of instructions.
+fn decode_all(program: &Program) -> usize {
+    program
+        .functions
+        .values()
+        .filter_map(|function| {
+            let data = program.get_data_for_function(function)?;
+            let instructions = program.decode(
+                function.address(),
+                data,
+                function.length(),
+                Default::default(),
+            )?;
+            Some(instructions.count())
+        })
+        .sum()
+}
+
+/// Peak resident memory, only available on Linux.
+fn peak_memory() -> Option<String> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .map(|peak| peak.trim().to_string())
+}
+
+fn per_second(count: usize, duration: Duration) -> f64 {
+    count as f64 / duration.as_secs_f64()
+}
+
+fn main() -> Result<(), TfbindiffError> {
+    let args: Vec<_> = std::env::args().collect();
+    if args.len() != 3 {
+        println!("Usage: {} <primary> <secondary>", args[0]);
+        return Ok(());
+    }
+
+    let total_start = Instant::now();
+    let load = |filename: &str| -> Result<&'static Program, TfbindiffError> {
+        let program = Program::load_path(Path::new(filename), &LoadOptions::default())?;
+        Ok(Box::leak(Box::new(program)))
+    };
+    let ((program1, program2), load_time) = time(|| (load(&args[1]), load(&args[2])));
+    let (program1, program2) = (program1?, program2?);
+    let function_count = program1.functions.len() + program2.functions.len();
+
+    let (instruction_count, decode_time) = time(|| decode_all(program1) + decode_all(program2));
+
+    let (comparison, compare_time) =
+        time(|| compare_programs(program1, program2, &CompareOptions::default(), None));
+    let comparison = comparison?;
+    let total_time = total_start.elapsed();
+
+    println!("functions:    {function_count}");
+    println!("instructions: {instruction_count}");
+    println!("changes:      {}", comparison.changes.len());
+    println!(
+        "load:    {load_time:>10.2?} {:>12.0} functions/s",
+        per_second(function_count, load_time)
+    );
+    println!(
+        "decode:  {decode_time:>10.2?} {:>12.0} functions/s {:>12.0} instructions/s",
+        per_second(function_count, decode_time),
+        per_second(instruction_count, decode_time)
+    );
+    println!(
+        "compare: {compare_time:>10.2?} {:>12.0} functions/s",
+        per_second(function_count, compare_time)
+    );
+    println!(
+        "total:   {total_time:>10.2?} {:>12.0} functions/s",
+        per_second(function_count, total_time)
+    );
+    println!(
+        "peak memory: {}",
+        peak_memory().unwrap_or_else(|| "unknown".to_string())
+    );
+
+    Ok(())
+}
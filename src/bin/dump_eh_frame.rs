@@ -1,9 +1,9 @@
-use byteorder::LittleEndian;
-use object::{Object, ObjectSection};
+use byteorder::{BigEndian, LittleEndian};
+use object::{Endianness, Object, ObjectSection};
 use std::fs;
 use std::io::Cursor;
 use std::path::Path;
-use tfbindiff::eh_frame::get_fdes;
+use tfbindiff::eh_frame::{get_fdes, EhFrameBases};
 
 fn load_file(filename: &str) -> memmap2::Mmap {
     let file = fs::File::open(Path::new(filename)).unwrap();
@@ -11,6 +11,8 @@ fn load_file(filename: &str) -> memmap2::Mmap {
 }
 
 fn main() {
+    env_logger::init();
+
     let args: Vec<_> = std::env::args().collect();
 
     if args.len() != 2 {
@@ -26,15 +28,18 @@ fn main() {
     let eh_frame = object.section_by_name(".eh_frame").unwrap();
     let eh_frame_data = eh_frame.uncompressed_data().unwrap();
 
-    // FIXME: not that it actually matters, but endian shouldn't be hardcoded
-    let fdes = get_fdes::<LittleEndian, _>(
-        &mut Cursor::new(eh_frame_data),
-        pointer_size,
-        eh_frame.address(),
-    )
+    let mut data = Cursor::new(eh_frame_data);
+    let bases = EhFrameBases::new(&object, eh_frame.address());
+    let eh_frame = match object.endianness() {
+        Endianness::Little => get_fdes::<LittleEndian, _>(&mut data, pointer_size, &bases),
+        Endianness::Big => get_fdes::<BigEndian, _>(&mut data, pointer_size, &bases),
+    }
     .unwrap();
 
-    for fde in fdes {
+    for fde in eh_frame.fdes {
         println!("{:08X} len {:04x}", fde.begin, fde.length);
     }
+    for offset in eh_frame.skipped {
+        eprintln!("skipped malformed entry @ {offset:08X}");
+    }
 }
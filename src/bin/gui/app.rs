@@ -1,21 +1,65 @@
 use eframe::egui;
 use egui::RichText;
 use egui_extras::TableBuilder;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::cell::OnceCell;
 
 use tfbindiff::{
-    compare::FunctionChange, instruction_wrapper::InstructionWrapper, program::Program,
-    util::ProgramInstructionFormatter,
+    call_graph::CallGraph,
+    compare::{FunctionChange, IdenticalFunction, ProgramComparison},
+    diff_model::{collapsed_text, DiffCell, DiffLines, DiffModel, DiffViewOptions, LineAddresses},
+    instruction_wrapper::InstructionWrapper,
+    program::Program,
 };
 
-use crate::split_diff::DiffCell;
+const MONOSPACE_SIZE_KEY: &str = "monospace_size";
+const MONOSPACE_SIZE_RANGE: std::ops::RangeInclusive<f32> = 6.0..=48.0;
+const SAVED_VIEW_KEY: &str = "saved_view";
+const SHOW_MANGLED_KEY: &str = "show_mangled";
+const SPLIT_RATIO_KEY: &str = "split_ratio";
+const SPLIT_RATIO_RANGE: std::ops::RangeInclusive<f32> = 0.1..=0.9;
 
+fn demangle_all(names: &[String]) -> Vec<String> {
+    names
+        .iter()
+        .map(|name| tfbindiff::util::demangle_symbol(name).unwrap_or_else(|| name.clone()))
+        .collect()
+}
+
+#[derive(Clone)]
 struct CachedFunctionChange {
     name: String,
     mangled_name: String,
+    mangled_name2: String,
     address1: u64,
     address2: u64,
+    register_renamed: bool,
+    tail_call: bool,
+    stack_protector: bool,
+    truncated: bool,
+    instruction_counts: (usize, usize),
+    sizes: (usize, usize),
+    sections: (String, String),
+    // Demangled name of each function that was inlined, the side it was inlined into and where
+    inlined: Vec<(String, usize, u64)>,
+    // Demangled names that each function was folded together with
+    folded_with: (Vec<String>, Vec<String>),
 
+    // The lines that are shown, with any expanded regions spliced in
     lines: Vec<(DiffCell<String>, DiffCell<String>)>,
+    addresses: Vec<LineAddresses>,
+    // The lines as built, with every long unchanged run collapsed
+    collapsed_lines: DiffLines,
+    // What each collapsed line hides, and whether it's been expanded
+    collapsed: Vec<(DiffLines, bool)>,
+    // Index into `collapsed` of each collapsed line that's shown, keyed by its index in `lines`
+    collapsed_rows: FxHashMap<usize, usize>,
+
+    programs: (&'static Program, &'static Program),
+    instructions: (Vec<InstructionWrapper>, Vec<InstructionWrapper>),
+    // Side (0 or 1) and address of the instruction shown in the inspector
+    inspected: Option<(usize, u64)>,
 }
 
 impl CachedFunctionChange {
@@ -24,134 +68,1188 @@ impl CachedFunctionChange {
         program2: &'static Program,
         change: &FunctionChange,
         name: &str,
+        options: &DiffViewOptions,
     ) -> Self {
-        Self {
+        let model = DiffModel::build(program1, program2, change, options);
+        let (instructions1, instructions2) = change.instructions();
+
+        let mut cached = Self {
             name: name.to_string(),
             mangled_name: change.name().to_string(),
+            mangled_name2: change.name2().to_string(),
             address1: change.address1(),
             address2: change.address2(),
-            lines: Self::build_split_diff_lines(program1, program2, change),
-        }
+            register_renamed: change.register_renamed(),
+            tail_call: change.tail_call(),
+            stack_protector: change.stack_protector(),
+            truncated: change.truncated(),
+            instruction_counts: change.instruction_counts(),
+            sizes: change.sizes(),
+            sections: (change.sections().0.into(), change.sections().1.into()),
+            inlined: model
+                .inlined
+                .iter()
+                .map(|inlined| {
+                    let name = tfbindiff::util::demangle_symbol(&inlined.name)
+                        .unwrap_or_else(|| inlined.name.clone());
+                    (name, inlined.side, inlined.address)
+                })
+                .collect(),
+            folded_with: (
+                demangle_all(&model.folded_with.0),
+                demangle_all(&model.folded_with.1),
+            ),
+            lines: vec![],
+            addresses: vec![],
+            collapsed_lines: DiffLines {
+                lines: model.lines,
+                addresses: model.addresses,
+            },
+            collapsed: model
+                .collapsed
+                .into_iter()
+                .map(|lines| (lines, false))
+                .collect(),
+            collapsed_rows: FxHashMap::default(),
+            programs: (program1, program2),
+            instructions: (instructions1.to_vec(), instructions2.to_vec()),
+            inspected: None,
+        };
+        cached.update_lines();
+
+        cached
     }
 
-    fn build_split_diff_lines(
-        program1: &'static Program,
-        program2: &'static Program,
-        change: &FunctionChange,
-    ) -> Vec<(DiffCell<String>, DiffCell<String>)> {
-        let (instructions1, instructions2) = change.instructions();
-        // NOTE: Lcs panics on oob, wtf?
-        let diff_ops =
-            similar::capture_diff_slices(similar::Algorithm::Myers, instructions1, instructions2);
+    /// Rebuilds the lines that are shown, expanding the collapsed regions that were clicked.
+    fn update_lines(&mut self) {
+        self.lines.clear();
+        self.addresses.clear();
+        self.collapsed_rows.clear();
 
-        let split_diff = crate::split_diff::build(instructions1, instructions2, &diff_ops);
+        let mut region = 0;
+        let rows = self
+            .collapsed_lines
+            .lines
+            .iter()
+            .zip(&self.collapsed_lines.addresses);
+        for (line, addresses) in rows {
+            if !matches!(line.0, DiffCell::Collapsed(_)) {
+                self.lines.push(line.clone());
+                self.addresses.push(*addresses);
+                continue;
+            }
 
-        let mut formatter1 = ProgramInstructionFormatter::new(program1);
-        let mut formatter2 = ProgramInstructionFormatter::new(program2);
+            let (hidden, expanded) = &self.collapsed[region];
+            if *expanded {
+                self.lines.extend(hidden.lines.iter().cloned());
+                self.addresses.extend(&hidden.addresses);
+            } else {
+                self.collapsed_rows.insert(self.lines.len(), region);
+                self.lines.push(line.clone());
+                self.addresses.push(*addresses);
+            }
+            region += 1;
+        }
+    }
 
-        let fmt_line =
-            |formatter: &mut ProgramInstructionFormatter, instr: &InstructionWrapper| -> String {
-                format!("{:08x}\t{}", instr.get().ip(), formatter.format(instr))
-            };
+    /// Shows the lines hidden behind the collapsed line at `row`.
+    fn expand_region(&mut self, row: usize) {
+        if let Some(&region) = self.collapsed_rows.get(&row) {
+            self.collapsed[region].1 = true;
+            self.update_lines();
+        }
+    }
+
+    /// The name to show, `mangled_name` if `mangled` is set.
+    fn display_name(&self, mangled: bool) -> &str {
+        if mangled {
+            &self.mangled_name
+        } else {
+            &self.name
+        }
+    }
+
+    /// Shows the name as a heading, with the other form of the name on hover and a context menu
+    /// to copy either form.
+    fn draw_name_heading(&self, ui: &mut egui::Ui, prefix: &str, mangled: bool) {
+        let mangled_names = if self.mangled_name == self.mangled_name2 {
+            self.mangled_name.clone()
+        } else {
+            format!("{}\n{}", self.mangled_name, self.mangled_name2)
+        };
+        let hover_text = if mangled { &self.name } else { &mangled_names };
 
-        let fmt_cell = |formatter: &mut ProgramInstructionFormatter,
-                        cell: &DiffCell<InstructionWrapper>| {
-            match cell {
-                DiffCell::Hidden => DiffCell::Hidden,
-                DiffCell::Collapsed => DiffCell::Collapsed,
-                DiffCell::Default(i) => DiffCell::Default(fmt_line(formatter, i)),
-                DiffCell::Insert(i) => DiffCell::Insert(fmt_line(formatter, i)),
-                DiffCell::Delete(i) => DiffCell::Delete(fmt_line(formatter, i)),
+        let heading = ui.add(
+            egui::Label::new(
+                RichText::new(format!("{prefix}{}", self.display_name(mangled))).heading(),
+            )
+            .sense(egui::Sense::click()),
+        );
+        heading.on_hover_text(hover_text).context_menu(|ui| {
+            if ui.button("Copy mangled name").clicked() {
+                ui.output_mut(|output| output.copied_text = self.mangled_name.clone());
+                ui.close_menu();
             }
+            if ui.button("Copy demangled name").clicked() {
+                ui.output_mut(|output| output.copied_text = self.name.clone());
+                ui.close_menu();
+            }
+        });
+    }
+
+    /// A Ghidra Python snippet that goes to the function in the primary (`side` 0) or the
+    /// secondary (`side` 1) program.
+    fn ghidra_script(&self, side: usize) -> String {
+        let (name, address) = if side == 0 {
+            (&self.mangled_name, self.address1)
+        } else {
+            (&self.mangled_name2, self.address2)
         };
+        format!("# {name}\ngoTo(toAddr({address:#x}))\n")
+    }
+
+    /// Both addresses as JSON, for tools that drive Ghidra from outside. The addresses are hex
+    /// strings, since JSON numbers can't hold every 64-bit address.
+    fn addresses_json(&self) -> String {
+        serde_json::json!({
+            "name": self.mangled_name,
+            "name2": self.mangled_name2,
+            "address1": format!("{:#x}", self.address1),
+            "address2": format!("{:#x}", self.address2),
+        })
+        .to_string()
+    }
+}
+
+impl CachedFunctionChange {
+    /// Draws the side-by-side lines as a table, scrolled to `scroll_to_row` if given. Returns the
+    /// topmost row that was drawn, and the fraction of the width taken by the first column if
+    /// any row was drawn.
+    fn draw_lines(
+        &mut self,
+        ui: &mut egui::Ui,
+        monospace_size: f32,
+        split_ratio: f32,
+        scroll_to_row: Option<usize>,
+    ) -> (Option<usize>, Option<f32>) {
+        let mut inspect = None;
+        let mut expand_row = None;
+        let mut top_row = None;
+
+        let split_ratio = ui.scope(|ui| {
+            let text_style = egui::TextStyle::Monospace;
+            ui.style_mut()
+                .text_styles
+                .insert(text_style.clone(), egui::FontId::monospace(monospace_size));
+            let text_height = ui.text_style_height(&text_style);
+            ui.style_mut().override_text_style = Some(text_style);
+
+            let available_width = ui.available_width();
+            let mut first_column_width = None;
+            let available_height = ui.available_height();
 
-        let formatted_lines: Vec<_> = split_diff
+            let id = ui.id().with(self.address1);
+            ui.push_id(id, |ui| {
+                let mut table = TableBuilder::new(ui);
+                if let Some(row) = scroll_to_row {
+                    table = table.scroll_to_row(row, Some(egui::Align::TOP));
+                }
+
+                table
+                    .striped(false)
+                    .cell_layout(egui::Layout::left_to_right(egui::Align::Min))
+                    .resizable(true)
+                    .auto_shrink([false, false])
+                    .column(
+                        egui_extras::Column::initial(available_width * split_ratio)
+                            .range(
+                                available_width * SPLIT_RATIO_RANGE.start()
+                                    ..=available_width * SPLIT_RATIO_RANGE.end(),
+                            )
+                            .resizable(true),
+                    )
+                    .column(egui_extras::Column::remainder())
+                    .min_scrolled_height(available_height)
+                    .body(|body| {
+                        body.rows(text_height, self.lines.len(), |mut row| {
+                            // Rows are laid out top to bottom, so the first one is the topmost
+                            top_row.get_or_insert(row.index());
+
+                            let (line1, line2) = &self.lines[row.index()];
+                            let build_line = |line: &DiffCell<String>| match line {
+                                DiffCell::Hidden => RichText::new(""),
+                                DiffCell::Collapsed(count) => {
+                                    RichText::new(collapsed_text(*count)).weak()
+                                }
+
+                                DiffCell::Default(line) => RichText::new(line),
+                                DiffCell::Insert(line) => {
+                                    RichText::new(line).color(egui::Color32::GREEN)
+                                }
+                                DiffCell::Delete(line) => {
+                                    RichText::new(line).color(egui::Color32::RED)
+                                }
+                            };
+
+                            // Clicking an instruction shows it in the inspector, clicking a
+                            // collapsed line shows what it hides
+                            let row_idx = row.index();
+                            let (address1, address2) = self.addresses[row_idx];
+                            let mut draw_cell =
+                                |ui: &mut egui::Ui,
+                                 side: usize,
+                                 line: &DiffCell<String>,
+                                 address: Option<u64>| {
+                                    let selected = address.is_some_and(|address| {
+                                        self.inspected == Some((side, address))
+                                    });
+                                    let mut text = build_line(line);
+                                    if selected {
+                                        text =
+                                            text.background_color(ui.visuals().selection.bg_fill);
+                                    }
+
+                                    let label =
+                                        ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                                    if let Some(address) = address {
+                                        if label.clicked() {
+                                            inspect = Some((!selected).then_some((side, address)));
+                                        }
+                                    } else if let DiffCell::Collapsed(_) = line {
+                                        if label.on_hover_text("Click to expand").clicked() {
+                                            expand_row = Some(row_idx);
+                                        }
+                                    }
+                                };
+
+                            row.col(|ui| {
+                                first_column_width.get_or_insert(ui.max_rect().width());
+                                draw_cell(ui, 0, line1, address1);
+                            });
+                            row.col(|ui| {
+                                draw_cell(ui, 1, line2, address2);
+                            });
+                        });
+                    });
+            });
+
+            // Only known if a row was drawn
+            first_column_width
+                .filter(|_| available_width > 0.0)
+                .map(|width| width / available_width)
+        });
+
+        if let Some(inspected) = inspect {
+            self.inspected = inspected;
+        }
+        if let Some(row) = expand_row {
+            self.expand_region(row);
+        }
+
+        (top_row, split_ratio.inner)
+    }
+
+    /// Shows the raw encoding and decoded details of the instruction that was clicked.
+    fn draw_inspector(&mut self, ui: &mut egui::Ui) {
+        let Some((side, address)) = self.inspected else {
+            return;
+        };
+        let (program, instructions) = if side == 0 {
+            (self.programs.0, &self.instructions.0)
+        } else {
+            (self.programs.1, &self.instructions.1)
+        };
+        let Some(instr) = instructions
             .iter()
-            .map(|(a, b)| (fmt_cell(&mut formatter1, a), fmt_cell(&mut formatter2, b)))
-            .collect();
+            .map(InstructionWrapper::get)
+            .find(|instr| instr.ip() == address)
+        else {
+            self.inspected = None;
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            ui.heading("Instruction");
+            if ui.button("Close").clicked() {
+                self.inspected = None;
+            }
+        });
+        ui.separator();
+
+        let bytes = program.code_bytes(address, instr.len()).map_or_else(
+            || "unavailable".to_string(),
+            |bytes| {
+                bytes
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            },
+        );
+        let operands = (0..instr.op_count())
+            .map(|idx| match instr.op_kind(idx) {
+                iced_x86::OpKind::Register => format!("{:?}", instr.op_register(idx)),
+                kind => format!("{kind:?}"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        egui::Grid::new("instruction_details")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                let mut row = |label: &str, value: String| {
+                    ui.label(label);
+                    ui.monospace(value);
+                    ui.end_row();
+                };
 
-        formatted_lines
+                row("Address", format!("{address:08x}"));
+                row("Length", instr.len().to_string());
+                row("Bytes", bytes);
+                row("Code", format!("{:?}", instr.code()));
+                row("Opcode", instr.op_code().op_code_string().to_string());
+                row("Encoding", format!("{:?}", instr.encoding()));
+                row("Mnemonic", format!("{:?}", instr.mnemonic()));
+                row("Operands", operands);
+                row("Flow control", format!("{:?}", instr.flow_control()));
+                row("Flags read", format!("{:#x}", instr.rflags_read()));
+                row("Flags written", format!("{:#x}", instr.rflags_written()));
+            });
+
+        egui::CollapsingHeader::new("Debug").show(ui, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.monospace(format!("{instr:#?}"));
+            });
+        });
     }
 }
 
 enum DiffViewerMode {
     FunctionList,
     Diff,
+    Overview,
+    Reachable,
 }
 
-struct DiffViewerApp {
+/// Where the user was when the app was closed. Functions are stored by their mangled name rather
+/// than their index, as the indices change whenever the compared programs do.
+#[derive(Serialize, Deserialize)]
+struct SavedView {
+    comparison: String,
+    mode: SavedMode,
+    function_list_offset: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SavedMode {
+    FunctionList,
+    Diff { function: String, top_row: usize },
+    Overview,
+    Reachable { function: String, name: String },
+}
+
+/// One pairwise comparison between two consecutive programs.
+struct Comparison {
+    label: String,
     program1: &'static Program,
     program2: &'static Program,
 
     changes: Vec<(String, FunctionChange)>,
-    current_cached_change: Option<CachedFunctionChange>,
-    mode: DiffViewerMode,
+    identical: Vec<(String, IdenticalFunction)>,
+    ignored_changes: usize,
+    minor_changes: usize,
+    // Changes and identical functions interleaved in address order
+    entries: Vec<ListEntry>,
+
+    // Call graph of `program1`, only built once it's needed
+    call_graph: OnceCell<CallGraph>,
+    // How much of each change differs, from 0.0 to 1.0, only computed once it's needed
+    change_ratios: OnceCell<Vec<f32>>,
 }
 
-impl DiffViewerApp {
+#[derive(Clone, Copy)]
+enum ListEntry {
+    Change(usize),
+    Identical(usize),
+}
+
+impl Comparison {
     fn new(
-        _cc: &eframe::CreationContext<'_>,
+        label: String,
         program1: &'static Program,
         program2: &'static Program,
-        changes: Vec<FunctionChange>,
+        comparison: ProgramComparison,
     ) -> Self {
-        // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
-        // Restore app state using cc.storage (requires the "persistence" feature).
-        // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
-        // for e.g. egui::PaintCallback.
+        let demangle =
+            |name: &str| tfbindiff::util::demangle_symbol(name).unwrap_or_else(|| name.to_string());
+
+        let changes: Vec<_> = comparison
+            .changes
+            .into_iter()
+            .map(|change| (demangle(change.name()), change))
+            .collect();
+        let identical: Vec<_> = comparison
+            .identical
+            .into_iter()
+            .map(|function| (demangle(function.name()), function))
+            .collect();
+
+        let mut entries: Vec<_> = (0..changes.len())
+            .map(ListEntry::Change)
+            .chain((0..identical.len()).map(ListEntry::Identical))
+            .collect();
+        entries.sort_by_key(|entry| match *entry {
+            ListEntry::Change(idx) => changes[idx].1.address1(),
+            ListEntry::Identical(idx) => identical[idx].1.address1(),
+        });
+
         Self {
+            label,
             program1,
             program2,
+            changes,
+            identical,
+            ignored_changes: comparison.ignored_changes,
+            minor_changes: comparison.minor_changes,
+            entries,
+            call_graph: OnceCell::new(),
+            change_ratios: OnceCell::new(),
+        }
+    }
 
-            changes: changes
-                .into_iter()
-                .map(|change| {
-                    (
-                        tfbindiff::util::demangle_symbol(change.name())
-                            .unwrap_or_else(|| change.name().to_string()),
-                        change,
-                    )
-                })
-                .collect(),
+    /// Index of the change containing `address` in the first program.
+    fn change_at(&self, address: u64) -> Result<usize, String> {
+        let Some((name, _)) = self.program1.function_at(address) else {
+            return Err(format!("no function contains {address:#x}"));
+        };
+
+        if let Some(idx) = self
+            .changes
+            .iter()
+            .position(|(_, change)| change.name() == name)
+        {
+            Ok(idx)
+        } else if self
+            .identical
+            .iter()
+            .any(|(_, function)| function.name() == name)
+        {
+            Err(format!("{address:#x} is in an unchanged function"))
+        } else {
+            Err(format!(
+                "{address:#x} is in an unchanged or unmatched function"
+            ))
+        }
+    }
+
+    /// True if less than `min_change` of the change's instructions differ.
+    fn is_minor_change(&self, idx: usize, min_change: f32) -> bool {
+        if min_change <= 0.0 {
+            return false;
+        }
+
+        let change_ratios = self.change_ratios.get_or_init(|| {
+            self.changes
+                .iter()
+                .map(|(_, change)| 1.0 - change.similarity())
+                .collect()
+        });
+        change_ratios[idx] < min_change
+    }
+
+    /// Indices of the changes that `root` (transitively) calls, nearest first.
+    fn reachable_changes(&self, root: &str) -> Vec<usize> {
+        let call_graph = self
+            .call_graph
+            .get_or_init(|| CallGraph::build(self.program1));
+
+        let change_indices: FxHashMap<&str, usize> = self
+            .changes
+            .iter()
+            .enumerate()
+            .map(|(idx, (_, change))| (change.name(), idx))
+            .collect();
+
+        call_graph
+            .reachable_from(root)
+            .into_iter()
+            .filter_map(|name| change_indices.get(name).copied())
+            .collect()
+    }
+}
+
+struct DiffViewerApp {
+    comparisons: Vec<Comparison>,
+    current_comparison: usize,
+
+    // For every function that exists in all programs and changed at least once, the index into
+    // each comparison's `changes` (or None if it didn't change in that comparison)
+    overview: Vec<(String, Vec<Option<usize>>)>,
+
+    // (comparison, change) indices of the currently opened change
+    current_change: (usize, usize),
+    current_cached_change: Option<CachedFunctionChange>,
+    // A change kept open in a side panel while browsing others
+    pinned_change: Option<CachedFunctionChange>,
+    mode: DiffViewerMode,
+    show_identical: bool,
+    show_thunks: bool,
+    // Hides changes where less than this percentage of the instructions differ
+    min_change: f32,
+    // Show mangled names instead of demangled ones, persisted across sessions
+    show_mangled: bool,
+
+    // Contents of the "jump to address" box, and why the last jump failed
+    jump_address: String,
+    jump_error: Option<String>,
+
+    // Demangled name of the function whose reachable changes are shown, and those changes
+    reachable: (String, Vec<usize>),
+    // Mangled name of that function, only needed to persist the view
+    reachable_root: String,
+
+    // Scroll positions, persisted across sessions. The pending values are restored on the next
+    // frame the corresponding view is drawn.
+    function_list_offset: f32,
+    pending_function_list_offset: Option<f32>,
+    diff_top_row: usize,
+    pending_diff_top_row: Option<usize>,
+
+    // Font size of the disassembly, persisted across sessions
+    monospace_size: f32,
+    default_monospace_size: f32,
+    // Fraction of the diff view taken by the primary column, persisted across sessions
+    split_ratio: f32,
+
+    view_options: DiffViewOptions,
+}
+
+impl DiffViewerApp {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        programs: Vec<(String, &'static Program)>,
+        comparisons: Vec<ProgramComparison>,
+        view_options: DiffViewOptions,
+    ) -> Self {
+        // Ctrl +/- zooms the disassembly instead of the whole UI
+        cc.egui_ctx
+            .options_mut(|options| options.zoom_with_keyboard = false);
+
+        let default_monospace_size = cc
+            .egui_ctx
+            .style()
+            .text_styles
+            .get(&egui::TextStyle::Monospace)
+            .map_or(12.0, |font| font.size);
+        let monospace_size = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, MONOSPACE_SIZE_KEY))
+            .unwrap_or(default_monospace_size);
+        let split_ratio = cc
+            .storage
+            .and_then(|storage| eframe::get_value::<f32>(storage, SPLIT_RATIO_KEY))
+            .map_or(0.5, |ratio| {
+                ratio.clamp(*SPLIT_RATIO_RANGE.start(), *SPLIT_RATIO_RANGE.end())
+            });
+
+        let show_mangled = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, SHOW_MANGLED_KEY))
+            .unwrap_or(false);
+
+        let comparisons: Vec<Comparison> = programs
+            .windows(2)
+            .zip(comparisons)
+            .map(|(pair, comparison)| {
+                Comparison::new(
+                    format!("{} -> {}", pair[0].0, pair[1].0),
+                    pair[0].1,
+                    pair[1].1,
+                    comparison,
+                )
+            })
+            .collect();
+
+        let overview = Self::build_overview(&programs, &comparisons);
+
+        let mut app = Self {
+            comparisons,
+            current_comparison: 0,
+            overview,
+            current_change: (0, 0),
             current_cached_change: None,
+            pinned_change: None,
             mode: DiffViewerMode::FunctionList,
+            show_identical: true,
+            show_thunks: true,
+            min_change: 0.0,
+            show_mangled,
+            jump_address: String::new(),
+            jump_error: None,
+            reachable: (String::new(), vec![]),
+            reachable_root: String::new(),
+            function_list_offset: 0.0,
+            pending_function_list_offset: None,
+            diff_top_row: 0,
+            pending_diff_top_row: None,
+            monospace_size,
+            default_monospace_size,
+            split_ratio,
+            view_options,
+        };
+
+        if let Some(saved_view) = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, SAVED_VIEW_KEY))
+        {
+            app.restore_view(saved_view);
+        }
+
+        app
+    }
+
+    fn save_view(&self) -> SavedView {
+        let mode = match self.mode {
+            DiffViewerMode::FunctionList => SavedMode::FunctionList,
+            DiffViewerMode::Diff => {
+                let (comparison_idx, change_idx) = self.current_change;
+                SavedMode::Diff {
+                    function: self.comparisons[comparison_idx].changes[change_idx]
+                        .1
+                        .name()
+                        .to_string(),
+                    top_row: self.diff_top_row,
+                }
+            }
+            DiffViewerMode::Overview => SavedMode::Overview,
+            DiffViewerMode::Reachable => SavedMode::Reachable {
+                function: self.reachable_root.clone(),
+                name: self.reachable.0.clone(),
+            },
+        };
+
+        SavedView {
+            comparison: self.comparisons[self.current_comparison].label.clone(),
+            mode,
+            function_list_offset: self.function_list_offset,
+        }
+    }
+
+    /// Restores as much of a saved view as still applies to the programs being compared.
+    fn restore_view(&mut self, saved_view: SavedView) {
+        let Some(comparison_idx) = self
+            .comparisons
+            .iter()
+            .position(|comparison| comparison.label == saved_view.comparison)
+        else {
+            return;
+        };
+
+        self.current_comparison = comparison_idx;
+        self.pending_function_list_offset = Some(saved_view.function_list_offset);
+
+        match saved_view.mode {
+            SavedMode::FunctionList => (),
+            SavedMode::Diff { function, top_row } => {
+                let change_idx = self.comparisons[comparison_idx]
+                    .changes
+                    .iter()
+                    .position(|(_, change)| change.name() == function);
+
+                if let Some(change_idx) = change_idx {
+                    self.open_change(comparison_idx, change_idx);
+                    self.pending_diff_top_row = Some(top_row);
+                }
+            }
+            SavedMode::Overview => self.mode = DiffViewerMode::Overview,
+            SavedMode::Reachable { function, name } => {
+                if self.comparisons[comparison_idx]
+                    .program1
+                    .functions
+                    .contains_key(&function)
+                {
+                    self.open_reachable(&function, &name);
+                }
+            }
+        }
+    }
+
+    fn build_overview(
+        programs: &[(String, &'static Program)],
+        comparisons: &[Comparison],
+    ) -> Vec<(String, Vec<Option<usize>>)> {
+        let mut overview: FxHashMap<&str, (&str, Vec<Option<usize>>)> = FxHashMap::default();
+        for (comparison_idx, comparison) in comparisons.iter().enumerate() {
+            for (change_idx, (name, change)) in comparison.changes.iter().enumerate() {
+                if !programs
+                    .iter()
+                    .all(|(_, program)| program.functions.contains_key(change.name()))
+                {
+                    continue;
+                }
+
+                overview
+                    .entry(change.name())
+                    .or_insert_with(|| (name, vec![None; comparisons.len()]))
+                    .1[comparison_idx] = Some(change_idx);
+            }
+        }
+
+        let mut overview: Vec<_> = overview
+            .into_values()
+            .map(|(name, changed_in)| (name.to_string(), changed_in))
+            .collect();
+        overview.sort();
+
+        overview
+    }
+
+    fn open_change(&mut self, comparison_idx: usize, change_idx: usize) {
+        let comparison = &self.comparisons[comparison_idx];
+        let (name, change) = &comparison.changes[change_idx];
+
+        self.current_comparison = comparison_idx;
+        self.current_change = (comparison_idx, change_idx);
+        self.current_cached_change = Some(CachedFunctionChange::new(
+            comparison.program1,
+            comparison.program2,
+            change,
+            name,
+            &self.view_options,
+        ));
+        self.mode = DiffViewerMode::Diff;
+    }
+
+    fn open_reachable(&mut self, mangled_name: &str, name: &str) {
+        let comparison = &self.comparisons[self.current_comparison];
+        self.reachable = (name.to_string(), comparison.reachable_changes(mangled_name));
+        self.reachable_root = mangled_name.to_string();
+        self.mode = DiffViewerMode::Reachable;
+    }
+
+    /// Rebuilds the lines of the open change after the view options changed, keeping it and its
+    /// collapsed regions expanded if they were.
+    fn rebuild_current_change(&mut self) {
+        let (comparison_idx, change_idx) = self.current_change;
+        let comparison = &self.comparisons[comparison_idx];
+        let (name, change) = &comparison.changes[change_idx];
+
+        let expanded = self
+            .current_cached_change
+            .as_ref()
+            .is_some_and(|cached| change.truncated() && !cached.truncated);
+        let expanded_regions: Vec<bool> = self
+            .current_cached_change
+            .as_ref()
+            .map(|cached| {
+                cached
+                    .collapsed
+                    .iter()
+                    .map(|(_, expanded)| *expanded)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if expanded {
+            self.expand_current_change();
+        } else {
+            self.current_cached_change = Some(CachedFunctionChange::new(
+                comparison.program1,
+                comparison.program2,
+                change,
+                name,
+                &self.view_options,
+            ));
+        }
+
+        // The diff is the same, only the way it's formatted changed
+        if let Some(cached) = &mut self.current_cached_change {
+            if cached.collapsed.len() == expanded_regions.len() {
+                for ((_, expanded), was_expanded) in
+                    cached.collapsed.iter_mut().zip(expanded_regions)
+                {
+                    *expanded = was_expanded;
+                }
+                cached.update_lines();
+            }
+        }
+    }
+
+    fn expand_current_change(&mut self) {
+        let (comparison_idx, change_idx) = self.current_change;
+        let comparison = &self.comparisons[comparison_idx];
+        let (name, change) = &comparison.changes[change_idx];
+
+        let full_change = change.with_full_instructions(comparison.program1, comparison.program2);
+        self.current_cached_change = Some(CachedFunctionChange::new(
+            comparison.program1,
+            comparison.program2,
+            &full_change,
+            name,
+            &self.view_options,
+        ));
+    }
+
+    fn handle_zoom_shortcuts(&mut self, ctx: &egui::Context) {
+        ctx.input_mut(|input| {
+            if input.consume_key(egui::Modifiers::COMMAND, egui::Key::Plus)
+                || input.consume_key(egui::Modifiers::COMMAND, egui::Key::Equals)
+            {
+                self.monospace_size += 1.0;
+            }
+            if input.consume_key(egui::Modifiers::COMMAND, egui::Key::Minus) {
+                self.monospace_size -= 1.0;
+            }
+            if input.consume_key(egui::Modifiers::COMMAND, egui::Key::Num0) {
+                self.monospace_size = self.default_monospace_size;
+            }
+        });
+
+        self.monospace_size = self
+            .monospace_size
+            .clamp(*MONOSPACE_SIZE_RANGE.start(), *MONOSPACE_SIZE_RANGE.end());
+    }
+
+    fn draw_comparison_tabs(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for (idx, comparison) in self.comparisons.iter().enumerate() {
+                let selected = idx == self.current_comparison
+                    && !matches!(self.mode, DiffViewerMode::Overview);
+                if ui.selectable_label(selected, &comparison.label).clicked() {
+                    self.current_comparison = idx;
+                    self.mode = DiffViewerMode::FunctionList;
+                }
+            }
+
+            let overview_selected = matches!(self.mode, DiffViewerMode::Overview);
+            if ui.selectable_label(overview_selected, "Overview").clicked() {
+                self.mode = DiffViewerMode::Overview;
+            }
+        });
+    }
+
+    fn draw_overview(&mut self, ui: &mut egui::Ui) {
+        ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
+            ui.heading("Functions changed across builds");
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
+                ui.heading(format!("{} functions", self.overview.len()));
+            });
+        });
+        ui.separator();
+
+        let mut clicked = None;
+        let text_height = ui.text_style_height(&egui::TextStyle::Button);
+        TableBuilder::new(ui)
+            .striped(true)
+            .auto_shrink([false, false])
+            .column(egui_extras::Column::remainder())
+            .columns(egui_extras::Column::auto(), self.comparisons.len())
+            .header(text_height, |mut header| {
+                header.col(|ui| {
+                    ui.strong("Function");
+                });
+                for comparison in &self.comparisons {
+                    header.col(|ui| {
+                        ui.strong(&comparison.label);
+                    });
+                }
+            })
+            .body(|body| {
+                body.rows(text_height, self.overview.len(), |mut row| {
+                    let (name, changed_in) = &self.overview[row.index()];
+                    row.col(|ui| {
+                        ui.label(name);
+                    });
+
+                    for (comparison_idx, change_idx) in changed_in.iter().enumerate() {
+                        row.col(|ui| match change_idx {
+                            Some(change_idx) => {
+                                let text = RichText::new("changed").color(egui::Color32::RED);
+                                if ui.add(egui::Button::new(text).frame(false)).clicked() {
+                                    clicked = Some((comparison_idx, *change_idx));
+                                }
+                            }
+                            None => {
+                                ui.label(RichText::new("same").weak());
+                            }
+                        });
+                    }
+                });
+            });
+
+        if let Some((comparison_idx, change_idx)) = clicked {
+            self.open_change(comparison_idx, change_idx);
         }
     }
 
     fn draw_function_list(&mut self, ui: &mut egui::Ui) {
+        let comparison = &self.comparisons[self.current_comparison];
+        let show_identical = &mut self.show_identical;
+        let show_thunks = &mut self.show_thunks;
+        let thunk_count = comparison
+            .changes
+            .iter()
+            .filter(|(_, change)| change.thunk())
+            .count();
+        let min_change = &mut self.min_change;
+        let show_mangled = &mut self.show_mangled;
+        // Including the ones `--min-change` already dropped
+        let minor_count = comparison.minor_changes
+            + (0..comparison.changes.len())
+                .filter(|&idx| comparison.is_minor_change(idx, *min_change / 100.0))
+                .count();
+
+        let mut jump = false;
         ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
             ui.heading("Functions");
+
+            let address_box = ui.add(
+                egui::TextEdit::singleline(&mut self.jump_address)
+                    .hint_text("Jump to address")
+                    .desired_width(120.0),
+            );
+            jump =
+                address_box.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+            if let Some(error) = &self.jump_error {
+                ui.label(RichText::new(error).color(egui::Color32::RED));
+            }
+
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
-                ui.heading(format!("{} changes found", self.changes.len()));
+                ui.heading(format!(
+                    "{} changes found",
+                    comparison.changes.len() + comparison.minor_changes
+                ));
+                if minor_count != 0 {
+                    ui.label(RichText::new(format!("{minor_count} minor")).weak())
+                        .on_hover_text("Changes below the minimum change, which aren't listed");
+                }
+                if comparison.ignored_changes != 0 {
+                    ui.label(
+                        RichText::new(format!("{} ignored", comparison.ignored_changes)).weak(),
+                    )
+                    .on_hover_text("Functions on the ignore list that changed");
+                }
+                if !comparison.identical.is_empty() {
+                    ui.checkbox(
+                        show_identical,
+                        format!("Show {} identical", comparison.identical.len()),
+                    );
+                }
+                if thunk_count != 0 {
+                    ui.checkbox(show_thunks, format!("Show {thunk_count} thunks"));
+                }
+                ui.checkbox(show_mangled, "Mangled names");
+                ui.add(
+                    egui::Slider::new(min_change, 0.0..=100.0)
+                        .suffix("%")
+                        .text("Min change"),
+                )
+                .on_hover_text("Hide changes where less of the instructions differ");
             });
         });
         ui.separator();
 
+        if jump {
+            let address = self.jump_address.trim();
+            let address = address
+                .strip_prefix("0x")
+                .or_else(|| address.strip_prefix("0X"))
+                .unwrap_or(address);
+
+            let change_idx = u64::from_str_radix(address, 16)
+                .map_err(|_| format!("invalid address: {}", self.jump_address))
+                .and_then(|address| comparison.change_at(address));
+            match change_idx {
+                Ok(change_idx) => {
+                    self.jump_error = None;
+                    self.open_change(self.current_comparison, change_idx);
+                    return;
+                }
+                Err(error) => self.jump_error = Some(error),
+            }
+        }
+
+        let entries: Vec<ListEntry> = comparison
+            .entries
+            .iter()
+            .copied()
+            .filter(|entry| match *entry {
+                ListEntry::Change(idx) => {
+                    (*show_thunks || !comparison.changes[idx].1.thunk())
+                        && !comparison.is_minor_change(idx, *min_change / 100.0)
+                }
+                ListEntry::Identical(_) => *show_identical,
+            })
+            .collect();
+
+        let mut scroll_area = egui::ScrollArea::vertical().auto_shrink([false, true]);
+        if let Some(offset) = self.pending_function_list_offset.take() {
+            scroll_area = scroll_area.vertical_scroll_offset(offset);
+        }
+
+        let mut clicked = None;
+        let mut reachable_clicked = None;
+        let output = scroll_area.show_rows(
+            ui,
+            ui.text_style_height(&egui::TextStyle::Button),
+            entries.len(),
+            |ui, range| {
+                for entry in &entries[range] {
+                    ui.with_layout(egui::Layout::top_down_justified(egui::Align::Min), |ui| {
+                        match *entry {
+                            ListEntry::Change(idx) => {
+                                let (demangled_name, change) = &comparison.changes[idx];
+                                let name = if *show_mangled {
+                                    change.name()
+                                } else {
+                                    demangled_name
+                                };
+                                let text = if change.register_renamed() {
+                                    RichText::new(format!("{name} [regalloc]")).weak()
+                                } else if change.tail_call() {
+                                    RichText::new(format!("{name} [tail call]")).weak()
+                                } else if change.stack_protector() {
+                                    RichText::new(format!("{name} [stack protector]")).weak()
+                                } else if change.thunk() {
+                                    RichText::new(format!("{name} [thunk]")).weak()
+                                } else if change.too_large() {
+                                    RichText::new(format!("{name} [too large]"))
+                                } else {
+                                    RichText::new(name)
+                                };
+
+                                let button = ui.add(egui::Button::new(text).frame(false));
+                                if button.clicked() {
+                                    clicked = Some(idx);
+                                }
+                                button.context_menu(|ui| {
+                                    if ui.button("Show reachable changes").clicked() {
+                                        reachable_clicked = Some((
+                                            change.name().to_string(),
+                                            demangled_name.clone(),
+                                        ));
+                                        ui.close_menu();
+                                    }
+                                    if ui.button("Copy mangled name").clicked() {
+                                        ui.output_mut(|output| {
+                                            output.copied_text = change.name().to_string()
+                                        });
+                                        ui.close_menu();
+                                    }
+                                });
+                            }
+                            ListEntry::Identical(idx) => {
+                                let (demangled_name, function) = &comparison.identical[idx];
+                                let name = if *show_mangled {
+                                    function.name()
+                                } else {
+                                    demangled_name
+                                };
+                                let label = ui.add(
+                                    egui::Label::new(
+                                        RichText::new(format!("{name} [same]")).weak(),
+                                    )
+                                    .sense(egui::Sense::click()),
+                                );
+                                label.context_menu(|ui| {
+                                    if ui.button("Show reachable changes").clicked() {
+                                        reachable_clicked = Some((
+                                            function.name().to_string(),
+                                            demangled_name.clone(),
+                                        ));
+                                        ui.close_menu();
+                                    }
+                                    if ui.button("Copy mangled name").clicked() {
+                                        ui.output_mut(|output| {
+                                            output.copied_text = function.name().to_string()
+                                        });
+                                        ui.close_menu();
+                                    }
+                                });
+                            }
+                        }
+                    });
+                }
+            },
+        );
+        self.function_list_offset = output.state.offset.y;
+
+        if let Some(idx) = clicked {
+            self.open_change(self.current_comparison, idx);
+        }
+        if let Some((mangled_name, name)) = reachable_clicked {
+            self.open_reachable(&mangled_name, &name);
+        }
+    }
+
+    fn draw_reachable(&mut self, ui: &mut egui::Ui) {
+        let comparison = &self.comparisons[self.current_comparison];
+        let (root, reachable) = &self.reachable;
+
+        let mut back = false;
+        ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
+            back = ui.button("Back").clicked();
+            ui.heading(format!("Changes reachable from {root}"));
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
+                ui.heading(format!("{} changes found", reachable.len()));
+            });
+        });
+        ui.separator();
+
+        let mut clicked = None;
         egui::ScrollArea::vertical()
             .auto_shrink([false, true])
             .show_rows(
                 ui,
                 ui.text_style_height(&egui::TextStyle::Button),
-                self.changes.len(),
+                reachable.len(),
                 |ui, range| {
-                    for idx in range {
-                        let (name, change) = &self.changes[idx];
-
+                    for &idx in &reachable[range] {
                         ui.with_layout(egui::Layout::top_down_justified(egui::Align::Min), |ui| {
-                            let button = ui.add(egui::Button::new(name).frame(false));
-                            if button.clicked() {
-                                self.current_cached_change = Some(CachedFunctionChange::new(
-                                    self.program1,
-                                    self.program2,
-                                    change,
-                                    name,
-                                ));
-                                self.mode = DiffViewerMode::Diff;
+                            let (name, change) = &comparison.changes[idx];
+                            let name = if self.show_mangled {
+                                change.name()
+                            } else {
+                                name
+                            };
+                            if ui.add(egui::Button::new(name).frame(false)).clicked() {
+                                clicked = Some(idx);
                             }
                         });
                     }
                 },
             );
+
+        if back {
+            self.mode = DiffViewerMode::FunctionList;
+        }
+        if let Some(idx) = clicked {
+            self.open_change(self.current_comparison, idx);
+        }
+    }
+
+    fn draw_pinned_change(&mut self, ui: &mut egui::Ui) {
+        let Some(change) = &mut self.pinned_change else {
+            return;
+        };
+
+        let mut unpin = false;
+        ui.horizontal(|ui| {
+            unpin = ui.button("Unpin").clicked();
+            change.draw_name_heading(ui, "", self.show_mangled);
+        });
+        ui.separator();
+
+        // The inspector panel belongs to the current change, this one gets its own
+        if change.inspected.is_some() {
+            egui::TopBottomPanel::bottom("pinned_instruction_inspector")
+                .show_inside(ui, |ui| change.draw_inspector(ui));
+        }
+        change.draw_lines(ui, self.monospace_size, self.split_ratio, None);
+
+        if unpin {
+            self.pinned_change = None;
+        }
     }
 
     fn draw_diff_view(&mut self, ui: &mut egui::Ui) {
@@ -160,84 +1258,208 @@ impl DiffViewerApp {
             .as_ref()
             .expect("current cached change should never be None here");
 
+        let mut expand = false;
+        let mut rebuild = false;
+        let mut pin = false;
+        let mut show_reachable = None;
+        let pending_top_row = self.pending_diff_top_row.take();
         ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
             let back_button = ui.button("Back");
             if back_button.clicked() {
                 self.mode = DiffViewerMode::FunctionList;
             }
 
-            ui.heading(format!("Comparing {}", &change.name))
-                .on_hover_text(&change.mangled_name);
+            change.draw_name_heading(ui, "Comparing ", self.show_mangled);
+            if change.register_renamed {
+                ui.label(RichText::new("identical up to register allocation").weak());
+            }
+            if change.tail_call {
+                ui.label(RichText::new("only a call and return turned into a tail call").weak());
+            }
+            if change.stack_protector {
+                ui.label(RichText::new("only the stack protector was added or removed").weak());
+            }
+            for (name, side, address) in &change.inlined {
+                let program = if *side == 0 { "primary" } else { "secondary" };
+                ui.label(RichText::new(format!("inlined {name}")).weak())
+                    .on_hover_text(format!("at {address:08x} in the {program}"));
+            }
+            for (folded_with, program) in [
+                (&change.folded_with.0, "primary"),
+                (&change.folded_with.1, "secondary"),
+            ] {
+                if !folded_with.is_empty() {
+                    ui.label(
+                        RichText::new(format!(
+                            "folded with {} in the {program}",
+                            folded_with.join(", ")
+                        ))
+                        .weak(),
+                    );
+                }
+            }
+            if change.truncated && ui.button("Show full function").clicked() {
+                expand = true;
+            }
+            if ui.button("Copy diff").clicked() {
+                let text = format!(
+                    "--- {:08x} {}\n+++ {:08x} {}\n{}",
+                    change.address1,
+                    change.mangled_name,
+                    change.address2,
+                    change.mangled_name2,
+                    tfbindiff::diff_model::to_unified_text(&change.lines)
+                );
+                ui.output_mut(|output| output.copied_text = text);
+            }
+            ui.menu_button("Copy for Ghidra", |ui| {
+                let copied = if ui.button("Primary goTo script").clicked() {
+                    Some(change.ghidra_script(0))
+                } else if ui.button("Secondary goTo script").clicked() {
+                    Some(change.ghidra_script(1))
+                } else if ui.button("Both addresses as JSON").clicked() {
+                    Some(change.addresses_json())
+                } else {
+                    None
+                };
+                if let Some(text) = copied {
+                    ui.output_mut(|output| output.copied_text = text);
+                    ui.close_menu();
+                }
+            });
+            if ui.button("Reachable changes").clicked() {
+                show_reachable = Some((change.mangled_name.clone(), change.name.clone()));
+            }
+            pin = ui
+                .button("Pin")
+                .on_hover_text("Keep this diff open next to the others")
+                .clicked();
+            rebuild |= ui
+                .checkbox(
+                    &mut self.view_options.relative_addresses,
+                    "Relative addresses",
+                )
+                .changed();
+            ui.checkbox(&mut self.show_mangled, "Mangled names");
+            ui.add(
+                egui::DragValue::new(&mut self.monospace_size)
+                    .clamp_range(MONOSPACE_SIZE_RANGE)
+                    .prefix("font size: "),
+            )
+            .on_hover_text("Ctrl +/- to zoom, Ctrl 0 to reset");
             // TODO: Make the addresses copyable
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
                 ui.heading(format!(
                     "{:08x} vs {:08x}",
                     change.address1, change.address2
                 ));
+                if change.sections.0 == change.sections.1 {
+                    ui.label(&change.sections.0);
+                } else {
+                    ui.label(
+                        RichText::new(format!(
+                            "section changed: {} -> {}",
+                            change.sections.0, change.sections.1
+                        ))
+                        .color(egui::Color32::YELLOW),
+                    );
+                }
+                ui.label(format!(
+                    "{} -> {} instrs, {:#x} -> {:#x} bytes",
+                    change.instruction_counts.0,
+                    change.instruction_counts.1,
+                    change.sizes.0,
+                    change.sizes.1
+                ));
             })
         });
         ui.separator();
 
-        ui.scope(|ui| {
-            let text_style = egui::TextStyle::Monospace;
-            let text_height = ui.text_style_height(&text_style);
-            ui.style_mut().override_text_style = Some(text_style);
-
-            let column_width = ui.available_width() / 2.0;
-            let available_height = ui.available_height();
+        if change.lines.is_empty() {
+            ui.label("The function bytes differ, but this architecture can't be disassembled.");
+        }
 
-            let id = ui.id().with(change.address1);
-            ui.push_id(id, |ui| {
-                TableBuilder::new(ui)
-                    .striped(false)
-                    .cell_layout(egui::Layout::left_to_right(egui::Align::Min))
-                    .resizable(false)
-                    .auto_shrink([false, false])
-                    .columns(egui_extras::Column::exact(column_width), 2)
-                    .min_scrolled_height(available_height)
-                    .body(|body| {
-                        body.rows(text_height, change.lines.len(), |mut row| {
-                            let (line1, line2) = &change.lines[row.index()];
-                            let build_line = |line: &DiffCell<String>| match line {
-                                DiffCell::Hidden => RichText::new(""),
-                                DiffCell::Collapsed => RichText::new("..."),
+        let change = self
+            .current_cached_change
+            .as_mut()
+            .expect("current cached change should never be None here");
+        let (top_row, split_ratio) =
+            change.draw_lines(ui, self.monospace_size, self.split_ratio, pending_top_row);
 
-                                DiffCell::Default(line) => RichText::new(line),
-                                DiffCell::Insert(line) => {
-                                    RichText::new(line).color(egui::Color32::GREEN)
-                                }
-                                DiffCell::Delete(line) => {
-                                    RichText::new(line).color(egui::Color32::RED)
-                                }
-                            };
+        self.diff_top_row = top_row.unwrap_or(0);
+        // New tables start at the last ratio the user dragged the columns to
+        if let Some(ratio) = split_ratio {
+            self.split_ratio = ratio.clamp(*SPLIT_RATIO_RANGE.start(), *SPLIT_RATIO_RANGE.end());
+        }
 
-                            row.col(|ui| {
-                                ui.label(build_line(line1));
-                            });
-                            row.col(|ui| {
-                                ui.label(build_line(line2));
-                            });
-                        });
-                    });
-            })
-        });
+        if pin {
+            self.pinned_change = self.current_cached_change.clone();
+        }
+        if expand {
+            self.expand_current_change();
+        }
+        if rebuild {
+            self.rebuild_current_change();
+        }
+        if let Some((mangled_name, name)) = show_reachable {
+            self.open_reachable(&mangled_name, &name);
+        }
     }
 }
 
 impl eframe::App for DiffViewerApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, MONOSPACE_SIZE_KEY, &self.monospace_size);
+        eframe::set_value(storage, SAVED_VIEW_KEY, &self.save_view());
+        eframe::set_value(storage, SPLIT_RATIO_KEY, &self.split_ratio);
+        eframe::set_value(storage, SHOW_MANGLED_KEY, &self.show_mangled);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_zoom_shortcuts(ctx);
+
+        if self.comparisons.len() > 1 {
+            egui::TopBottomPanel::top("comparisons").show(ctx, |ui| self.draw_comparison_tabs(ui));
+        }
+
+        if self.pinned_change.is_some() {
+            egui::SidePanel::left("pinned_change")
+                .resizable(true)
+                .default_width(ctx.available_rect().width() / 2.0)
+                .show(ctx, |ui| self.draw_pinned_change(ui));
+        }
+
+        if let (DiffViewerMode::Diff, Some(change)) = (&self.mode, &mut self.current_cached_change)
+        {
+            if change.inspected.is_some() {
+                egui::SidePanel::right("instruction_inspector")
+                    .show(ctx, |ui| change.draw_inspector(ui));
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| match self.mode {
             DiffViewerMode::FunctionList => self.draw_function_list(ui),
             DiffViewerMode::Diff => self.draw_diff_view(ui),
+            DiffViewerMode::Overview => self.draw_overview(ui),
+            DiffViewerMode::Reachable => self.draw_reachable(ui),
         });
     }
 }
 
-pub fn run(program1: &'static Program, program2: &'static Program, changes: Vec<FunctionChange>) {
+/// `comparisons[i]` is the comparison between `programs[i]` and `programs[i + 1]`.
+pub fn run(
+    programs: Vec<(String, &'static Program)>,
+    comparisons: Vec<ProgramComparison>,
+    view_options: DiffViewOptions,
+) {
     eframe::run_native(
         "tfbindiff viewer",
-        eframe::NativeOptions::default(),
-        Box::new(move |cc| Box::new(DiffViewerApp::new(cc, program1, program2, changes))),
+        eframe::NativeOptions {
+            // Reopen at the last window size and position
+            persist_window: true,
+            ..Default::default()
+        },
+        Box::new(move |cc| Box::new(DiffViewerApp::new(cc, programs, comparisons, view_options))),
     )
     .unwrap();
 }
@@ -0,0 +1,53 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Config file that's read from the working directory if `--config` isn't given.
+pub const DEFAULT_PATH: &str = "tfbindiff.toml";
+
+/// Defaults for the command line options. Keys are flag names without the dashes:
+/// `ignore-regalloc = true` is `--ignore-regalloc`, `head = 100` is `--head 100` and
+/// `ignore-line = ["nop", "int3"]` is `--ignore-line nop --ignore-line int3`. Options that only
+/// make sense for one run, like the programs and their symbols, can't be set here.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub ignore_regalloc: bool,
+    pub compare_displacements: bool,
+    pub compare_immediates: bool,
+    pub size_changed_only: bool,
+    pub hide_thunks: bool,
+    pub hide_stack_protector: bool,
+    pub mask_relocations: bool,
+    pub mask_build_id: bool,
+    pub allow_reordering: bool,
+    pub show_identical: bool,
+    pub only_ignored: bool,
+    pub block_diff: bool,
+    pub relative_addresses: bool,
+    pub print: bool,
+    pub section_stats: bool,
+    pub cache: bool,
+    pub sqlite_lines: bool,
+    pub head: Option<usize>,
+    pub max_diff_size: Option<usize>,
+    pub name_width: Option<usize>,
+    pub min_change: Option<f32>,
+    pub bits: Option<u32>,
+    pub name_suffix: Option<Vec<String>>,
+    pub ignore_line: Vec<String>,
+    pub code_section: Vec<String>,
+    pub ignore_list: Option<String>,
+    pub functions_file: Option<String>,
+}
+
+pub fn parse(text: &str) -> Result<Config, String> {
+    toml::from_str(text).map_err(|error| error.to_string())
+}
+
+pub fn load(path: &Path) -> Result<Config, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|error| format!("couldn't read {}: {error}", path.display()))?;
+
+    parse(&text).map_err(|error| format!("{}: {error}", path.display()))
+}
@@ -0,0 +1,112 @@
+use std::io::{self, Write};
+use tfbindiff::{baseline::FunctionStatus, compare::ProgramComparison, program::Program};
+
+const HEADER: &str = "primary,secondary,name,demangled_name,address1,address2,status,similarity,semantic_hash1,semantic_hash2";
+
+/// Quotes a field if it contains anything that would break the row, e.g. the commas in demangled
+/// parameter lists.
+fn field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes one row per function: changed, identical or only in one of the programs. Addresses and
+/// semantic hashes are left blank for the program a function isn't in, and the similarity is only
+/// given for matched functions. `comparisons[i]` is the comparison between `programs[i]` and `programs[i + 1]`.
+pub fn write_csv(
+    out: &mut impl Write,
+    programs: &[(String, &'static Program)],
+    comparisons: &[ProgramComparison],
+) -> io::Result<()> {
+    writeln!(out, "{HEADER}")?;
+
+    for (pair, comparison) in programs.windows(2).zip(comparisons) {
+        let (program1, program2) = (pair[0].1, pair[1].1);
+        // Identical functions matched by their demangled names only have the primary's symbol
+        let semantic_hash = |program: &Program, name: &str| {
+            program
+                .functions
+                .get(name)
+                .and_then(|function| function.semantic_hash(program))
+        };
+
+        let mut write_row =
+            |name: &str,
+             address1: Option<u64>,
+             address2: Option<u64>,
+             status: &str,
+             similarity: Option<f32>,
+             (hash1, hash2): (Option<u64>, Option<u64>)| {
+                let demangled = tfbindiff::util::demangle_symbol(name).unwrap_or_default();
+                let address = |address: Option<u64>| {
+                    address.map_or_else(String::new, |address| format!("{address:#x}"))
+                };
+                let hash = |hash: Option<u64>| {
+                    hash.map_or_else(String::new, |hash| format!("{hash:016x}"))
+                };
+
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{status},{},{},{}",
+                    field(&pair[0].0),
+                    field(&pair[1].0),
+                    field(name),
+                    field(&demangled),
+                    address(address1),
+                    address(address2),
+                    similarity.map_or_else(String::new, |similarity| format!("{similarity:.3}")),
+                    hash(hash1),
+                    hash(hash2)
+                )
+            };
+
+        for change in &comparison.changes {
+            write_row(
+                change.name(),
+                Some(change.address1()),
+                Some(change.address2()),
+                FunctionStatus::Changed.as_str(),
+                Some(change.similarity()),
+                change.semantic_hashes(program1, program2),
+            )?;
+        }
+        for function in &comparison.identical {
+            write_row(
+                function.name(),
+                Some(function.address1()),
+                Some(function.address2()),
+                FunctionStatus::Same.as_str(),
+                Some(1.0),
+                (
+                    semantic_hash(program1, function.name()),
+                    semantic_hash(program2, function.name()),
+                ),
+            )?;
+        }
+        for function in &comparison.only_in_primary {
+            write_row(
+                function.name(),
+                Some(function.address()),
+                None,
+                FunctionStatus::OnlyInPrimary.as_str(),
+                None,
+                (semantic_hash(program1, function.name()), None),
+            )?;
+        }
+        for function in &comparison.only_in_secondary {
+            write_row(
+                function.name(),
+                None,
+                Some(function.address()),
+                FunctionStatus::OnlyInSecondary.as_str(),
+                None,
+                (None, semantic_hash(program2, function.name())),
+            )?;
+        }
+    }
+
+    Ok(())
+}
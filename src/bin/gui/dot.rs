@@ -0,0 +1,144 @@
+use rustc_hash::FxHashMap;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use tfbindiff::baseline::FunctionStatus;
+use tfbindiff::call_graph::CallGraph;
+use tfbindiff::compare::ProgramComparison;
+use tfbindiff::program::Program;
+
+/// Escapes a string for use as a quoted DOT id.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn node_color(status: FunctionStatus) -> &'static str {
+    match status {
+        FunctionStatus::Changed => "#f0c060",
+        FunctionStatus::Same => "#ffffff",
+        FunctionStatus::OnlyInPrimary => "#f0a0a0",
+        FunctionStatus::OnlyInSecondary => "#a0e0a0",
+    }
+}
+
+/// Every call in `graph`, with the functions renamed by `names` where they have an entry.
+fn calls<'a>(
+    program: &'a Program,
+    graph: &'a CallGraph,
+    names: &FxHashMap<&'a str, &'a str>,
+) -> BTreeSet<(&'a str, &'a str)> {
+    let rename = |name: &'a str| names.get(name).copied().unwrap_or(name);
+
+    program
+        .functions
+        .keys()
+        .flat_map(|caller| {
+            graph
+                .callees(caller)
+                .map(move |callee| (rename(caller), rename(callee)))
+        })
+        .collect()
+}
+
+/// Writes the call graphs of each pair of programs as a GraphViz digraph, one cluster per
+/// comparison. Nodes are colored by their status, and calls that only exist in one of the
+/// programs are drawn in that program's color. Unchanged functions are left out unless they call
+/// or get called by something, to keep the graph readable. `comparisons[i]` is the comparison
+/// between `programs[i]` and `programs[i + 1]`.
+pub fn write_dot(
+    out: &mut impl Write,
+    programs: &[(String, &'static Program)],
+    comparisons: &[ProgramComparison],
+) -> io::Result<()> {
+    writeln!(out, "digraph calls {{")?;
+    writeln!(
+        out,
+        "    node [shape=box, style=filled, fontname=monospace];"
+    )?;
+
+    // Each program's graph is shared by the comparisons on either side of it
+    let graphs: Vec<_> = programs
+        .iter()
+        .map(|(_, program)| CallGraph::build(program))
+        .collect();
+
+    for (idx, ((pair, graphs), comparison)) in programs
+        .windows(2)
+        .zip(graphs.windows(2))
+        .zip(comparisons)
+        .enumerate()
+    {
+        // Matched functions can have a different name in the secondary, e.g. `foo.part.0`,
+        // they're shown by their primary name
+        let primary_names: FxHashMap<&str, &str> = comparison
+            .changes
+            .iter()
+            .filter(|change| change.name() != change.name2())
+            .map(|change| (change.name2(), change.name()))
+            .collect();
+        let calls1 = calls(pair[0].1, &graphs[0], &FxHashMap::default());
+        let calls2 = calls(pair[1].1, &graphs[1], &primary_names);
+
+        let mut statuses: FxHashMap<&str, FunctionStatus> = FxHashMap::default();
+        for change in &comparison.changes {
+            statuses.insert(change.name(), FunctionStatus::Changed);
+        }
+        for function in &comparison.only_in_primary {
+            statuses.insert(function.name(), FunctionStatus::OnlyInPrimary);
+        }
+        for function in &comparison.only_in_secondary {
+            statuses.insert(function.name(), FunctionStatus::OnlyInSecondary);
+        }
+
+        let nodes: BTreeSet<&str> = statuses
+            .keys()
+            .copied()
+            .chain(
+                calls1
+                    .union(&calls2)
+                    .flat_map(|&(caller, callee)| [caller, callee]),
+            )
+            .collect();
+        let node_id = |name: &str| quote(&format!("{idx}:{name}"));
+
+        writeln!(out, "    subgraph {} {{", quote(&format!("cluster_{idx}")))?;
+        writeln!(
+            out,
+            "        label={};",
+            quote(&format!("{} -> {}", pair[0].0, pair[1].0))
+        )?;
+
+        for name in nodes {
+            let status = statuses.get(name).copied().unwrap_or(FunctionStatus::Same);
+            let label = tfbindiff::util::demangle_symbol(name).unwrap_or_else(|| name.to_string());
+            writeln!(
+                out,
+                "        {} [label={}, fillcolor=\"{}\", tooltip={}];",
+                node_id(name),
+                quote(&label),
+                node_color(status),
+                quote(status.as_str())
+            )?;
+        }
+
+        for &(caller, callee) in calls1.union(&calls2) {
+            let style = match (
+                calls1.contains(&(caller, callee)),
+                calls2.contains(&(caller, callee)),
+            ) {
+                (true, false) => " [color=\"#c03030\", style=dashed]",
+                (false, true) => " [color=\"#30a030\"]",
+                _ => "",
+            };
+            writeln!(
+                out,
+                "        {} -> {}{style};",
+                node_id(caller),
+                node_id(callee)
+            )?;
+        }
+
+        writeln!(out, "    }}")?;
+    }
+
+    writeln!(out, "}}")
+}
@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+use tfbindiff::compare::ProgramComparison;
+use tfbindiff::diff_model::{collapsed_text, DiffCell, DiffModel, DiffViewOptions};
+use tfbindiff::program::Program;
+
+const STYLE: &str = "
+body { margin: 0; display: flex; height: 100vh; font-family: sans-serif; }
+nav { width: 25%; overflow-y: auto; border-right: 1px solid #ccc; padding: 0.5em; }
+nav input { width: 100%; box-sizing: border-box; }
+nav ul { list-style: none; padding: 0; font-size: 0.9em; }
+nav a { text-decoration: none; }
+main { flex: 1; overflow-y: auto; padding: 0.5em; }
+summary { cursor: pointer; font-weight: bold; }
+table { border-collapse: collapse; width: 100%; table-layout: fixed; font-family: monospace; }
+td { white-space: pre; overflow: hidden; padding: 0 0.5em; }
+.insert { background: #dfd; }
+.delete { background: #fdd; }
+.collapsed { color: #888; }
+";
+
+const SCRIPT: &str = "
+function filterFunctions(query) {
+    query = query.toLowerCase();
+    for (const item of document.querySelectorAll('nav li')) {
+        item.hidden = !item.textContent.toLowerCase().includes(query);
+    }
+}
+
+// Expand a function when it's jumped to from the list
+window.addEventListener('hashchange', () => {
+    const target = document.getElementById(location.hash.slice(1));
+    if (target) target.open = true;
+});
+";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_cell(out: &mut impl Write, cell: &DiffCell<String>) -> io::Result<()> {
+    match cell {
+        DiffCell::Hidden => write!(out, "<td></td>"),
+        DiffCell::Collapsed(count) => write!(
+            out,
+            "<td class=\"collapsed\">{}</td>",
+            escape(&collapsed_text(*count))
+        ),
+        DiffCell::Default(line) => write!(out, "<td>{}</td>", escape(line)),
+        DiffCell::Insert(line) => write!(out, "<td class=\"insert\">{}</td>", escape(line)),
+        DiffCell::Delete(line) => write!(out, "<td class=\"delete\">{}</td>", escape(line)),
+    }
+}
+
+/// Writes a self-contained HTML page with every change, as a searchable function list and one
+/// collapsible side-by-side diff per function. `comparisons[i]` is the comparison between
+/// `programs[i]` and `programs[i + 1]`.
+pub fn write_html_report(
+    out: &mut impl Write,
+    programs: &[(String, &'static Program)],
+    comparisons: &[ProgramComparison],
+    view_options: &DiffViewOptions,
+) -> io::Result<()> {
+    let demangle =
+        |name: &str| tfbindiff::util::demangle_symbol(name).unwrap_or_else(|| name.to_string());
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>tfbindiff report</title>")?;
+    writeln!(out, "<style>{STYLE}</style><script>{SCRIPT}</script>")?;
+    writeln!(out, "</head><body>")?;
+
+    writeln!(out, "<nav>")?;
+    writeln!(
+        out,
+        "<input type=\"search\" placeholder=\"Search\" oninput=\"filterFunctions(this.value)\">"
+    )?;
+    for (comparison_idx, (pair, comparison)) in programs.windows(2).zip(comparisons).enumerate() {
+        if comparisons.len() > 1 {
+            writeln!(
+                out,
+                "<h4>{} -&gt; {}</h4>",
+                escape(&pair[0].0),
+                escape(&pair[1].0)
+            )?;
+        }
+        writeln!(out, "<ul>")?;
+        for (change_idx, change) in comparison.changes.iter().enumerate() {
+            writeln!(
+                out,
+                "<li><a href=\"#f{comparison_idx}-{change_idx}\">{}</a></li>",
+                escape(&demangle(change.name()))
+            )?;
+        }
+        writeln!(out, "</ul>")?;
+    }
+    writeln!(out, "</nav>")?;
+
+    writeln!(out, "<main>")?;
+    for (comparison_idx, (pair, comparison)) in programs.windows(2).zip(comparisons).enumerate() {
+        let (program1, program2) = (pair[0].1, pair[1].1);
+        writeln!(
+            out,
+            "<h2>{} -&gt; {} ({} changes, {} ignored, {} minor)</h2>",
+            escape(&pair[0].0),
+            escape(&pair[1].0),
+            comparison.changes.len(),
+            comparison.ignored_changes,
+            comparison.minor_changes
+        )?;
+
+        for (change_idx, change) in comparison.changes.iter().enumerate() {
+            let model = DiffModel::build(program1, program2, change, view_options);
+            writeln!(out, "<details id=\"f{comparison_idx}-{change_idx}\">")?;
+            writeln!(
+                out,
+                "<summary>{} <small>{:08x} vs {:08x}</small></summary>",
+                escape(&model.demangled_name),
+                model.address1,
+                model.address2
+            )?;
+
+            writeln!(out, "<table>")?;
+            for (line1, line2) in &model.lines {
+                write!(out, "<tr>")?;
+                write_cell(out, line1)?;
+                write_cell(out, line2)?;
+                writeln!(out, "</tr>")?;
+            }
+            writeln!(out, "</table>")?;
+            writeln!(out, "</details>")?;
+        }
+    }
+    writeln!(out, "</main>")?;
+
+    writeln!(out, "</body></html>")
+}
@@ -1,30 +1,291 @@
 mod app;
-mod split_diff;
+mod config;
+mod csv;
+mod dot;
+mod html_report;
+mod markdown;
+mod options;
+mod output;
+mod raw;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stage;
 
+use options::Options;
+use std::cell::Cell;
 use std::fs;
+use std::io::{self, IsTerminal};
+use std::ops::ControlFlow;
 use std::path::Path;
-use tfbindiff::compare::compare_programs;
-use tfbindiff::program::Program;
+use tfbindiff::baseline::Baseline;
+use tfbindiff::cache::ComparisonCache;
+use tfbindiff::compare::{
+    build_stamp_notes, compare_function, compare_programs, comparison_warnings,
+    missing_listed_functions, CompareError, ComparedFunction,
+};
+use tfbindiff::program::{archive_member, LoadOptions, LoadProgress, Program};
 
 fn load_file(filename: &str) -> memmap2::Mmap {
     let file = fs::File::open(Path::new(filename)).unwrap();
     unsafe { memmap2::Mmap::map(&file).unwrap() }
 }
 
+/// Splits `libfoo.a:bar.o` into the archive and the member to load from it. Paths that exist
+/// are taken as they are, even if they contain a colon.
+fn split_archive_member(filename: &str) -> (&str, Option<&str>) {
+    if Path::new(filename).exists() {
+        return (filename, None);
+    }
+
+    match filename.rsplit_once(':') {
+        Some((archive, member)) if Path::new(archive).is_file() => (archive, Some(member)),
+        _ => (filename, None),
+    }
+}
+
+fn comparison_failed(label1: &str, label2: &str, error: CompareError) -> ! {
+    eprintln!("can't compare {label1} and {label2}: {error}");
+    std::process::exit(1);
+}
+
+/// Compares and prints a single function, for `diff-func`.
+fn diff_function(programs: &[(String, &'static Program)], name: &str, options: &Options) {
+    let ((label1, program1), (label2, program2)) = (&programs[0], &programs[1]);
+    stage::set(format!("comparing {name} in {label1} and {label2}"));
+    let result = compare_function(program1, program2, name, &options.compare);
+    match result.unwrap_or_else(|error| comparison_failed(label1, label2, error)) {
+        Some(
+            ComparedFunction::Changed(change)
+            | ComparedFunction::Ignored(change)
+            | ComparedFunction::Minor(change),
+        ) => output::print_change(
+            (label1, program1),
+            (label2, program2),
+            &change,
+            &options.view,
+            options.name_width,
+        )
+        .unwrap(),
+        Some(ComparedFunction::Identical(_)) => eprintln!("{name} is identical"),
+        None => {
+            eprintln!("{name} isn't in both programs");
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
+    env_logger::init();
+
     let args: Vec<_> = std::env::args().collect();
 
-    if args.len() != 3 {
-        println!("Usage: {} <primary> <secondary>", args[0]);
+    let options = match Options::parse(&args[1..]) {
+        Ok(options) if options.list || options.programs.len() >= 2 => options,
+        Ok(_) => {
+            println!(
+                "Usage: {} [--config <file.toml>] [--ignore-regalloc] [--compare-displacements] [--compare-immediates] [--head N] [--max-diff-size N] [--block-diff] [--relative-addresses] [--ignore-line <regex>...] [--show-identical] [--hide-thunks] [--hide-stack-protector] [--mask-relocations] [--mask-build-id] [--allow-reordering] [--name-suffix <suffix>...] [--ignore-list <file> [--only-ignored]] [--functions-file <file>] [--min-change <percent>] [--size-changed-only] [--cache] [--baseline <in.json>] [--save-baseline <out.json>] [--symsN <file>] [--symbolsN <file.map>] [--code-section <name>...] [--bits 16|32|64] [--html <out.html>] [--markdown <out.md>] [--csv <out.csv>] [--dot <out.dot>] [--sqlite <out.db> [--sqlite-lines]] [--print [--name-width N]] [--section-stats] <primary> <secondary> [<secondary>...]\n       {} diff-func [options] <primary> <secondary> <function>\n       {} diff-raw [--bits 16|32|64] [--baseN <address>] [--block-diff] [--relative-addresses] [--ignore-line <regex>...] <primary.hex> <secondary.hex>\n       {} list [--coverage] [--symsN <file>] [--symbolsN <file.map>] [--code-section <name>...] <program>\nPrograms can be archive members, e.g. libfoo.a:bar.o\nDefaults for the options can be set in tfbindiff.toml, e.g. `print = true` or `head = 100`, and turned off with --no-<option>",
+                args[0], args[0], args[0], args[0]
+            );
+            return;
+        }
+        Err(error) => {
+            println!("{error}");
+            return;
+        }
+    };
+
+    if options.diff_raw {
+        raw::diff_files(&options);
+        return;
+    }
+
+    stage::install_panic_hook(options.programs.clone());
+
+    let programs: Vec<(String, &'static Program)> = options
+        .programs
+        .iter()
+        .enumerate()
+        .map(|(idx, filename)| {
+            stage::set(format!("loading program {} ({filename})", idx + 1));
+            let label = Path::new(filename).file_name().map_or_else(
+                || filename.clone(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            let symbols = options.symbol_files.get(&idx).map(|path| load_file(path));
+
+            // Large programs take a while to load, show how far along it is
+            let show_progress = io::stderr().is_terminal();
+            let last_percent = Cell::new(None);
+            let progress = |progress: LoadProgress| {
+                let percent = progress.discovered * 100 / progress.total;
+                if last_percent.replace(Some(percent)) != Some(percent) {
+                    eprint!("\rloading {label}: {percent}%");
+                }
+                ControlFlow::Continue(())
+            };
+
+            let load_options = LoadOptions {
+                symbols: symbols.as_deref(),
+                code_sections: &options.code_sections,
+                symbol_overrides: options.symbol_maps.get(&idx),
+                on_function: show_progress.then_some(&progress),
+                bitness: options.bitness,
+            };
+            let (path, member) = split_archive_member(filename);
+            let data = load_file(path);
+            let result = member
+                .map_or(Ok(&data[..]), |member| archive_member(&data, member))
+                .and_then(|data| Program::load_with_options(data, &load_options));
+            if last_percent.get().is_some() {
+                // Clear the progress line
+                eprint!("\r\x1b[K");
+            }
+
+            let program = match result {
+                Ok(program) => program,
+                Err(error) => {
+                    eprintln!("failed to load {filename}: {error}");
+                    std::process::exit(1);
+                }
+            };
+            let program: &'static Program = Box::leak(Box::new(program));
+
+            (label, program)
+        })
+        .collect();
+
+    if options.list {
+        if options.coverage {
+            output::print_coverage(&programs[0].0, programs[0].1).unwrap();
+        } else {
+            output::print_functions(programs[0].1).unwrap();
+        }
+        return;
+    }
+
+    for pair in programs.windows(2) {
+        for warning in comparison_warnings(pair[0].1, pair[1].1) {
+            eprintln!("warning: {} and {}: {warning}", pair[0].0, pair[1].0);
+        }
+        for note in build_stamp_notes(pair[0].1, pair[1].1) {
+            eprintln!("{} and {}: {note}", pair[0].0, pair[1].0);
+        }
+        if let Some(list) = &options.compare.only_functions {
+            for missing in missing_listed_functions(pair[0].1, pair[1].1, list) {
+                eprintln!("warning: {} and {}: {missing}", pair[0].0, pair[1].0);
+            }
+        }
+    }
+
+    if let Some(name) = &options.diff_function {
+        diff_function(&programs, name, &options);
+        return;
+    }
+
+    let mut cache = options
+        .cache
+        .then(ComparisonCache::default_path)
+        .flatten()
+        .map(|path| ComparisonCache::load(&path));
+
+    // Each build is compared against the one before it
+    let comparisons: Vec<_> = programs
+        .windows(2)
+        .map(|pair| {
+            stage::set(format!("comparing {} and {}", pair[0].0, pair[1].0));
+            compare_programs(pair[0].1, pair[1].1, &options.compare, cache.as_mut())
+                .unwrap_or_else(|error| comparison_failed(&pair[0].0, &pair[1].0, error))
+        })
+        .collect();
+
+    stage::set("writing the results");
+    if let Some(cache) = &cache {
+        if let Err(error) = cache.save() {
+            log::warn!("couldn't save the comparison cache: {error}");
+        }
+    }
+
+    if options.baseline.is_some() || options.save_baseline.is_some() {
+        let current = Baseline::from_comparisons(&comparisons);
+        let mut regressed = false;
+
+        if let Some(path) = &options.baseline {
+            let baseline = Baseline::load(Path::new(path)).unwrap_or_else(|error| {
+                eprintln!("couldn't read the baseline {path}: {error}");
+                std::process::exit(1);
+            });
+            if baseline.comparison_count() != comparisons.len() {
+                eprintln!(
+                    "the baseline {path} has {} comparisons, but {} programs were given",
+                    baseline.comparison_count(),
+                    programs.len()
+                );
+                std::process::exit(1);
+            }
+
+            regressed = output::print_baseline_delta(&programs, &baseline, &current).unwrap();
+        }
+
+        if let Some(path) = &options.save_baseline {
+            if let Err(error) = current.save(Path::new(path)) {
+                eprintln!("couldn't write the baseline {path}: {error}");
+                std::process::exit(1);
+            }
+        }
+
+        if regressed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &options.csv {
+        let mut out = io::BufWriter::new(fs::File::create(path).unwrap());
+        csv::write_csv(&mut out, &programs, &comparisons).unwrap();
+        return;
+    }
+
+    if let Some(path) = &options.dot {
+        let mut out = io::BufWriter::new(fs::File::create(path).unwrap());
+        dot::write_dot(&mut out, &programs, &comparisons).unwrap();
+        return;
+    }
+
+    if let Some(html) = &options.html {
+        let mut out = io::BufWriter::new(fs::File::create(html).unwrap());
+        html_report::write_html_report(&mut out, &programs, &comparisons, &options.view).unwrap();
+        return;
+    }
+
+    if let Some(path) = &options.markdown {
+        let mut out = io::BufWriter::new(fs::File::create(path).unwrap());
+        markdown::write_markdown(&mut out, &programs, &comparisons, &options.view).unwrap();
         return;
     }
 
-    let (program1, program2) = (
-        Box::new(Program::load(&load_file(&args[1]))),
-        Box::new(Program::load(&load_file(&args[2]))),
-    );
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &options.sqlite {
+        sqlite::write_database(
+            path,
+            &programs,
+            &comparisons,
+            &options.view,
+            options.sqlite_lines,
+        )
+        .unwrap();
+        return;
+    }
 
-    let changes = compare_programs(&program1, &program2);
+    if options.section_stats {
+        output::print_section_stats(&programs, &comparisons).unwrap();
+        return;
+    }
+
+    if options.print {
+        output::print_changes(&programs, &comparisons, &options.view, options.name_width).unwrap();
+        return;
+    }
 
-    app::run(Box::leak(program1), Box::leak(program2), changes);
+    stage::set("showing the results");
+    app::run(programs, comparisons, options.view);
 }
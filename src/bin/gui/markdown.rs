@@ -0,0 +1,147 @@
+use std::io::{self, Write};
+use tfbindiff::compare::ProgramComparison;
+use tfbindiff::diff_model::{DiffModel, DiffViewOptions};
+use tfbindiff::program::Program;
+use tfbindiff::section_stats::section_stats;
+
+/// Wraps `text` in an inline code span, which keeps the `<`, `*` and `_` of demangled names from
+/// being read as Markdown. Backticks in the text need a longer fence.
+fn code_span(text: &str) -> String {
+    if text.contains('`') {
+        format!("`` {text} ``")
+    } else {
+        format!("`{text}`")
+    }
+}
+
+/// Writes every change as a Markdown document: a linked list of the changed functions, then a
+/// section per function with its addresses and similarity in a table and the unified diff in a
+/// fenced code block. `comparisons[i]` is the comparison between `programs[i]` and
+/// `programs[i + 1]`.
+pub fn write_markdown(
+    out: &mut impl Write,
+    programs: &[(String, &'static Program)],
+    comparisons: &[ProgramComparison],
+    view_options: &DiffViewOptions,
+) -> io::Result<()> {
+    writeln!(out, "# tfbindiff report")?;
+
+    for (comparison_idx, (pair, comparison)) in programs.windows(2).zip(comparisons).enumerate() {
+        let ((label1, program1), (label2, program2)) = (&pair[0], &pair[1]);
+        let models: Vec<_> = comparison
+            .changes
+            .iter()
+            .map(|change| DiffModel::build(program1, program2, change, view_options))
+            .collect();
+        let anchor = |idx: usize| format!("change-{comparison_idx}-{idx}");
+
+        writeln!(out)?;
+        writeln!(out, "## {label1} -> {label2}")?;
+        writeln!(out)?;
+        writeln!(out, "{} functions changed.", models.len())?;
+        if comparison.ignored_changes != 0 {
+            writeln!(
+                out,
+                "{} functions on the ignore list changed.",
+                comparison.ignored_changes
+            )?;
+        }
+        if comparison.minor_changes != 0 {
+            writeln!(
+                out,
+                "{} functions changed less than the minimum change.",
+                comparison.minor_changes
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "| Section | Functions | Changed | Added | Removed | Similarity |"
+        )?;
+        writeln!(out, "|---|---|---|---|---|---|")?;
+        for stats in section_stats(program1, program2, comparison) {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {:.1}% |",
+                code_span(&stats.section),
+                stats.functions,
+                stats.changed,
+                stats.added,
+                stats.removed,
+                stats.similarity * 100.0
+            )?;
+        }
+
+        writeln!(out)?;
+        for (idx, model) in models.iter().enumerate() {
+            writeln!(
+                out,
+                "- [{}](#{})",
+                code_span(&model.demangled_name),
+                anchor(idx)
+            )?;
+        }
+
+        for (idx, (model, change)) in models.iter().zip(&comparison.changes).enumerate() {
+            let (size1, size2) = change.sizes();
+
+            writeln!(out)?;
+            writeln!(out, "<a id=\"{}\"></a>", anchor(idx))?;
+            writeln!(out)?;
+            writeln!(out, "### {}", code_span(&model.demangled_name))?;
+            writeln!(out)?;
+            writeln!(out, "| | {label1} | {label2} |")?;
+            writeln!(out, "|---|---|---|")?;
+            writeln!(
+                out,
+                "| Symbol | {} | {} |",
+                code_span(&model.name),
+                code_span(&model.name2)
+            )?;
+            writeln!(
+                out,
+                "| Address | `{:08x}` | `{:08x}` |",
+                model.address1, model.address2
+            )?;
+            writeln!(out, "| Size | {size1:#x} | {size2:#x} |")?;
+            writeln!(out, "| Similarity | {:.1}% | |", model.similarity * 100.0)?;
+            writeln!(out)?;
+            for inlined in &model.inlined {
+                let label = if inlined.side == 0 { label1 } else { label2 };
+                let callee = tfbindiff::util::demangle_symbol(&inlined.name)
+                    .unwrap_or_else(|| inlined.name.clone());
+                writeln!(
+                    out,
+                    "{label} inlines {} at `{:08x}`.",
+                    code_span(&callee),
+                    inlined.address
+                )?;
+                writeln!(out)?;
+            }
+            for (label, folded_with) in [
+                (label1, &model.folded_with.0),
+                (label2, &model.folded_with.1),
+            ] {
+                if !folded_with.is_empty() {
+                    let names: Vec<_> = folded_with
+                        .iter()
+                        .map(|name| {
+                            code_span(
+                                &tfbindiff::util::demangle_symbol(name)
+                                    .unwrap_or_else(|| name.clone()),
+                            )
+                        })
+                        .collect();
+                    writeln!(out, "In {label}, folded with {}.", names.join(", "))?;
+                    writeln!(out)?;
+                }
+            }
+            writeln!(out, "```diff")?;
+            write!(out, "{}", model.to_unified_text())?;
+            writeln!(out, "```")?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,495 @@
+use crate::config::{self, Config};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::path::{Path, PathBuf};
+use tfbindiff::compare::CompareOptions;
+use tfbindiff::diff_model::DiffViewOptions;
+use tfbindiff::ignore_list::IgnoreList;
+use tfbindiff::symbol_map;
+
+#[derive(Default)]
+pub struct Options {
+    pub programs: Vec<String>,
+    /// Separate symbol files, keyed by the index of the program they belong to
+    pub symbol_files: FxHashMap<usize, String>,
+    /// Symbol maps (see `symbol_map::parse`) overriding the programs' own symbols, keyed by the
+    /// index of the program they belong to
+    pub symbol_maps: FxHashMap<usize, FxHashMap<u64, String>>,
+    /// Sections to discover functions in, all of them if empty
+    pub code_sections: Vec<String>,
+    /// Decode every program as 16, 32 or 64-bit code, instead of going by its pointer size
+    pub bitness: Option<u32>,
+    pub compare: CompareOptions,
+    pub view: DiffViewOptions,
+    /// Write an HTML report to this file instead of opening the viewer
+    pub html: Option<String>,
+    /// Write the changes as a Markdown document to this file instead of opening the viewer
+    pub markdown: Option<String>,
+    /// Print the changes to stdout instead of opening the viewer
+    pub print: bool,
+    /// Print how much changed in each section instead of opening the viewer
+    pub section_stats: bool,
+    /// Cut printed function names to this many characters, 0 to never cut them. Defaults to
+    /// fitting them to the terminal.
+    pub name_width: Option<usize>,
+    /// Write the changes to this SQLite database instead of opening the viewer
+    pub sqlite: Option<String>,
+    /// Also store every diff line in the SQLite database
+    pub sqlite_lines: bool,
+    /// Write every function and its status to this CSV file instead of opening the viewer
+    pub csv: Option<String>,
+    /// Write a GraphViz call graph colored by change status to this file instead of opening the
+    /// viewer
+    pub dot: Option<String>,
+    /// Only diff this function (`diff-func`), printing it to stdout
+    pub diff_function: Option<String>,
+    /// Diff two files of hex machine code (`diff-raw`) instead of programs
+    pub diff_raw: bool,
+    /// Address that each hex file's code starts at for `diff-raw`, keyed by the index of the
+    /// file. Defaults to 0.
+    pub raw_bases: FxHashMap<usize, u64>,
+    /// Print the functions of a single program (`list`) instead of comparing anything
+    pub list: bool,
+    /// With `list`, print the code that isn't in any function instead of the functions
+    pub coverage: bool,
+    /// Reuse comparison results from previous runs, see `ComparisonCache`
+    pub cache: bool,
+    /// Save the status of every function to this file, see `Baseline`
+    pub save_baseline: Option<String>,
+    /// Report the functions that regressed or got fixed since this saved baseline
+    pub baseline: Option<String>,
+    /// Config file given with `--config`
+    pub config: Option<String>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let (command, args) = match args.split_first() {
+            Some((command, rest))
+                if matches!(command.as_str(), "diff-func" | "diff-raw" | "list") =>
+            {
+                (Some(command.as_str()), rest)
+            }
+            _ => (None, args),
+        };
+
+        // The command line is read once to find the config file, and again on top of the
+        // config file's defaults so that it overrides them
+        let mut options = Self::default();
+        Self::parse_flags(&mut options, args)?;
+        if let Some(path) = Self::config_path(&options) {
+            let config = config::load(&path)?;
+            options = Self::default();
+            options
+                .apply_config(config)
+                .map_err(|error| format!("{}: {error}", path.display()))?;
+            Self::parse_flags(&mut options, args)?;
+        }
+
+        match command {
+            // diff-func <primary> <secondary> <symbol>
+            Some("diff-func") => {
+                if options.programs.len() != 3 {
+                    return Err("diff-func takes two programs and a function name".to_string());
+                }
+                options.diff_function = options.programs.pop();
+            }
+            // diff-raw <primary.hex> <secondary.hex>
+            Some("diff-raw") => {
+                if options.programs.len() != 2 {
+                    return Err("diff-raw takes two hex files".to_string());
+                }
+                options.diff_raw = true;
+            }
+            // list <program>
+            Some("list") => {
+                if options.programs.len() != 1 {
+                    return Err("list takes a single program".to_string());
+                }
+                options.list = true;
+            }
+            _ => {}
+        }
+
+        Ok(options)
+    }
+
+    /// The config file given with `--config`, otherwise `tfbindiff.toml` if there is one.
+    fn config_path(options: &Self) -> Option<PathBuf> {
+        match &options.config {
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                let path = Path::new(config::DEFAULT_PATH);
+                path.is_file().then(|| path.to_path_buf())
+            }
+        }
+    }
+
+    /// Sets the option that the flag `--<name>` turns on, or `--no-<name>` turns off. Returns
+    /// false if there's no such flag.
+    fn set_switch(&mut self, name: &str, on: bool) -> bool {
+        match name {
+            "ignore-regalloc" => self.compare.equality.ignore_regalloc = on,
+            "size-changed-only" => self.compare.size_changed_only = on,
+            "compare-displacements" => self.compare.equality.ignore_displacements = !on,
+            "compare-immediates" => self.compare.equality.ignore_immediates = !on,
+            "hide-thunks" => self.compare.hide_thunks = on,
+            "hide-stack-protector" => self.compare.hide_stack_protector = on,
+            "mask-relocations" => self.compare.mask_relocations = on,
+            "mask-build-id" => self.compare.mask_build_stamp = on,
+            "allow-reordering" => self.compare.allow_reordering = on,
+            "show-identical" => self.compare.keep_identical = on,
+            "only-ignored" => self.compare.only_ignored = on,
+            "coverage" => self.coverage = on,
+            "block-diff" => self.view.block_diff = on,
+            "relative-addresses" => self.view.relative_addresses = on,
+            "print" => self.print = on,
+            "section-stats" => self.section_stats = on,
+            "cache" => self.cache = on,
+            "sqlite-lines" => self.sqlite_lines = on,
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn set_min_change(&mut self, percent: f32) -> Result<(), String> {
+        if !(0.0..=100.0).contains(&percent) {
+            return Err("--min-change must be between 0 and 100".to_string());
+        }
+        self.compare.min_change = percent / 100.0;
+        Ok(())
+    }
+
+    fn set_bits(&mut self, bits: u32) -> Result<(), String> {
+        if ![16, 32, 64].contains(&bits) {
+            return Err("--bits must be 16, 32 or 64".to_string());
+        }
+        self.bitness = Some(bits);
+        Ok(())
+    }
+
+    fn add_ignore_line(&mut self, pattern: &str) -> Result<(), String> {
+        let regex = regex_lite::Regex::new(pattern)
+            .map_err(|error| format!("invalid regex for --ignore-line: {error}"))?;
+        self.view.ignore_lines.push(regex);
+        Ok(())
+    }
+
+    fn read_ignore_list(path: &str) -> Result<IgnoreList, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("couldn't read {path}: {error}"))?;
+        IgnoreList::parse(&text).map_err(|error| format!("{path}: {error}"))
+    }
+
+    /// Sets the defaults from a config file, before the command line is parsed.
+    fn apply_config(&mut self, config: Config) -> Result<(), String> {
+        let Config {
+            ignore_regalloc,
+            compare_displacements,
+            compare_immediates,
+            size_changed_only,
+            hide_thunks,
+            hide_stack_protector,
+            mask_relocations,
+            mask_build_id,
+            allow_reordering,
+            show_identical,
+            only_ignored,
+            block_diff,
+            relative_addresses,
+            print,
+            section_stats,
+            cache,
+            sqlite_lines,
+            head,
+            max_diff_size,
+            name_width,
+            min_change,
+            bits,
+            name_suffix,
+            ignore_line,
+            code_section,
+            ignore_list,
+            functions_file,
+        } = config;
+
+        let switches = [
+            ("ignore-regalloc", ignore_regalloc),
+            ("compare-displacements", compare_displacements),
+            ("compare-immediates", compare_immediates),
+            ("size-changed-only", size_changed_only),
+            ("hide-thunks", hide_thunks),
+            ("hide-stack-protector", hide_stack_protector),
+            ("mask-relocations", mask_relocations),
+            ("mask-build-id", mask_build_id),
+            ("allow-reordering", allow_reordering),
+            ("show-identical", show_identical),
+            ("only-ignored", only_ignored),
+            ("block-diff", block_diff),
+            ("relative-addresses", relative_addresses),
+            ("print", print),
+            ("section-stats", section_stats),
+            ("cache", cache),
+            ("sqlite-lines", sqlite_lines),
+        ];
+        for (name, on) in switches {
+            let known = self.set_switch(name, on);
+            debug_assert!(known, "no switch {name}");
+        }
+
+        self.compare.head = head;
+        self.compare.max_diff_size = max_diff_size;
+        self.name_width = name_width;
+        if let Some(percent) = min_change {
+            self.set_min_change(percent)?;
+        }
+        if let Some(bits) = bits {
+            self.set_bits(bits)?;
+        }
+        self.compare.name_suffixes = name_suffix;
+        for pattern in &ignore_line {
+            self.add_ignore_line(pattern)?;
+        }
+        self.code_sections = code_section;
+        if let Some(path) = ignore_list {
+            self.compare.ignore_list = Self::read_ignore_list(&path)?;
+        }
+        if let Some(path) = functions_file {
+            self.compare.only_functions = Some(Self::read_ignore_list(&path)?);
+        }
+
+        Ok(())
+    }
+
+    /// Applies `args` on top of the options set so far. Options given more than once keep the
+    /// last value, or add up for the ones that take several values like `--ignore-line`, which
+    /// replace the config file's values rather than adding to them.
+    fn parse_flags(options: &mut Self, args: &[String]) -> Result<(), String> {
+        // Lists that were given on the command line so far
+        let mut replaced: FxHashSet<&str> = FxHashSet::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let (name, on) = match name.strip_prefix("no-") {
+                    Some(name) => (name, false),
+                    None => (name, true),
+                };
+                if options.set_switch(name, on) {
+                    continue;
+                }
+            }
+
+            match arg.as_str() {
+                "--head" => options.compare.head = Some(Self::parse_value(arg, args.next())?),
+                "--max-diff-size" => {
+                    options.compare.max_diff_size = Some(Self::parse_value(arg, args.next())?)
+                }
+                "--name-suffix" => {
+                    let suffix = Self::parse_value(arg, args.next())?;
+                    // The first one replaces the defaults
+                    let suffixes = options.compare.name_suffixes.get_or_insert_with(Vec::new);
+                    if replaced.insert(arg) {
+                        suffixes.clear();
+                    }
+                    suffixes.push(suffix);
+                }
+                "--ignore-list" => {
+                    let path: String = Self::parse_value(arg, args.next())?;
+                    options.compare.ignore_list = Self::read_ignore_list(&path)?;
+                }
+                "--functions-file" => {
+                    let path: String = Self::parse_value(arg, args.next())?;
+                    options.compare.only_functions = Some(Self::read_ignore_list(&path)?);
+                }
+                "--min-change" => options.set_min_change(Self::parse_value(arg, args.next())?)?,
+                "--bits" => options.set_bits(Self::parse_value(arg, args.next())?)?,
+                "--code-section" => {
+                    let section = Self::parse_value(arg, args.next())?;
+                    if replaced.insert(arg) {
+                        options.code_sections.clear();
+                    }
+                    options.code_sections.push(section);
+                }
+                "--config" => options.config = Some(Self::parse_value(arg, args.next())?),
+                "--name-width" => options.name_width = Some(Self::parse_value(arg, args.next())?),
+                "--save-baseline" => {
+                    options.save_baseline = Some(Self::parse_value(arg, args.next())?);
+                    // Functions that regress were the same before, so those need to be stored
+                    options.compare.keep_identical = true;
+                }
+                "--baseline" => {
+                    options.baseline = Some(Self::parse_value(arg, args.next())?);
+                    options.compare.keep_identical = true;
+                }
+                "--csv" => {
+                    options.csv = Some(Self::parse_value(arg, args.next())?);
+                    // The CSV lists every function, not just the changed ones
+                    options.compare.keep_identical = true;
+                }
+                "--dot" => options.dot = Some(Self::parse_value(arg, args.next())?),
+                "--html" => options.html = Some(Self::parse_value(arg, args.next())?),
+                "--markdown" => options.markdown = Some(Self::parse_value(arg, args.next())?),
+                "--sqlite" if cfg!(feature = "sqlite") => {
+                    options.sqlite = Some(Self::parse_value(arg, args.next())?)
+                }
+                "--sqlite" => {
+                    return Err(format!(
+                        "{arg} requires tfbindiff to be built with the sqlite feature"
+                    ))
+                }
+                "--ignore-line" => {
+                    let pattern: String = Self::parse_value(arg, args.next())?;
+                    if replaced.insert(arg) {
+                        options.view.ignore_lines.clear();
+                    }
+                    options.add_ignore_line(&pattern)?;
+                }
+                flag if flag.starts_with("--symbols") => {
+                    let program_idx: usize = flag["--symbols".len()..]
+                        .parse()
+                        .ok()
+                        .filter(|&idx| idx > 0)
+                        .ok_or_else(|| format!("unknown option: {flag}"))?;
+                    let path: String = Self::parse_value(flag, args.next())?;
+                    let text = std::fs::read_to_string(&path)
+                        .map_err(|error| format!("couldn't read {path}: {error}"))?;
+                    let symbols =
+                        symbol_map::parse(&text).map_err(|error| format!("{path}: {error}"))?;
+                    options.symbol_maps.insert(program_idx - 1, symbols);
+                }
+                flag if flag.starts_with("--base") => {
+                    let file_idx: usize = flag["--base".len()..]
+                        .parse()
+                        .ok()
+                        .filter(|&idx| idx > 0)
+                        .ok_or_else(|| format!("unknown option: {flag}"))?;
+                    let address: String = Self::parse_value(flag, args.next())?;
+                    let base = u64::from_str_radix(address.trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("invalid value for {flag}: {address}"))?;
+                    options.raw_bases.insert(file_idx - 1, base);
+                }
+                flag if flag.starts_with("--syms") => {
+                    let program_idx: usize = flag["--syms".len()..]
+                        .parse()
+                        .ok()
+                        .filter(|&idx| idx > 0)
+                        .ok_or_else(|| format!("unknown option: {flag}"))?;
+                    options
+                        .symbol_files
+                        .insert(program_idx - 1, Self::parse_value(flag, args.next())?);
+                }
+                flag if flag.starts_with("--") => return Err(format!("unknown option: {flag}")),
+                _ => options.programs.push(arg.clone()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+        let value = value.ok_or_else(|| format!("{flag} requires a value"))?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid value for {flag}: {value}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_with_config(name: &str, config: &str, args: &[&str]) -> Result<Options, String> {
+        let path =
+            std::env::temp_dir().join(format!("tfbindiff-{name}-{}.toml", std::process::id()));
+        std::fs::write(&path, config).unwrap();
+
+        let mut all_args = vec!["--config".to_string(), path.display().to_string()];
+        all_args.extend(args.iter().map(|arg| arg.to_string()));
+        let options = Options::parse(&all_args);
+
+        std::fs::remove_file(&path).unwrap();
+        options
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let config = "print = true\nhead = 10\nshow-identical = true\ncompare-immediates = true\n";
+        let options = parse_with_config(
+            "override",
+            config,
+            &["--head", "20", "--no-show-identical", "a", "b"],
+        )
+        .unwrap();
+
+        assert!(options.print);
+        assert_eq!(options.compare.head, Some(20));
+        assert!(!options.compare.keep_identical);
+        assert!(!options.compare.equality.ignore_immediates);
+        assert_eq!(options.programs, ["a", "b"]);
+
+        // Turning off an option that's inverted internally
+        let options =
+            parse_with_config("inverted", config, &["--no-compare-immediates", "a", "b"]).unwrap();
+        assert!(options.compare.equality.ignore_immediates);
+    }
+
+    #[test]
+    fn command_line_lists_replace_config_lists() {
+        let config = "ignore-line = [\"nop\", \"int3\"]\ncode-section = [\".text\"]\n";
+
+        let options = parse_with_config("lists", config, &["a", "b"]).unwrap();
+        assert_eq!(options.view.ignore_lines.len(), 2);
+        assert_eq!(options.code_sections, [".text"]);
+
+        let options = parse_with_config(
+            "replaced-lists",
+            config,
+            &[
+                "--ignore-line",
+                "ret",
+                "--ignore-line",
+                "leave",
+                "--code-section",
+                ".init",
+                "a",
+                "b",
+            ],
+        )
+        .unwrap();
+        let patterns: Vec<_> = options
+            .view
+            .ignore_lines
+            .iter()
+            .map(|regex| regex.as_str())
+            .collect();
+        assert_eq!(patterns, ["ret", "leave"]);
+        assert_eq!(options.code_sections, [".init"]);
+    }
+
+    #[test]
+    fn config_errors_name_the_file() {
+        let error = parse_with_config("unknown", "no-such-option = true\n", &["a", "b"])
+            .err()
+            .unwrap();
+        assert!(error.contains("tfbindiff-unknown-"), "{error}");
+
+        let error = parse_with_config("bits", "bits = 12\n", &["a", "b"])
+            .err()
+            .unwrap();
+        assert!(error.contains("--bits must be 16, 32 or 64"), "{error}");
+    }
+
+    #[test]
+    fn config_flag_as_a_value_is_not_a_config_file() {
+        let args: Vec<String> = ["--ignore-line", "--config", "a", "b"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = Options::parse(&args).unwrap();
+
+        assert_eq!(options.config, None);
+        assert_eq!(options.view.ignore_lines[0].as_str(), "--config");
+        assert_eq!(options.programs, ["a", "b"]);
+    }
+}
@@ -0,0 +1,433 @@
+use similar::ChangeTag;
+use std::borrow::Cow;
+use std::io::{self, IsTerminal, Write};
+use terminal_size::Width;
+use tfbindiff::baseline::{Baseline, FunctionState, FunctionStatus};
+use tfbindiff::compare::{FunctionChange, ProgramComparison};
+use tfbindiff::diff_model::{collapsed_text, DiffCell, DiffModel, DiffViewOptions};
+use tfbindiff::program::Program;
+use tfbindiff::section_stats::section_stats;
+use tfbindiff::util::demangle_symbol;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const HIGHLIGHT: &str = "\x1b[1;4m";
+const NO_HIGHLIGHT: &str = "\x1b[22;24m";
+const RESET: &str = "\x1b[0m";
+
+/// Names aren't cut shorter than this, even in narrow terminals.
+const MIN_NAME_WIDTH: usize = 20;
+
+struct PrintOptions<'a> {
+    view: &'a DiffViewOptions,
+    color: bool,
+    /// Demangled names are cut to this many characters
+    name_width: Option<usize>,
+}
+
+/// Splits a line into words and individual punctuation, so `[rbp-4],edi` diffs as `[`, `rbp`,
+/// `-`, `4`, `]`, `,` and `edi`.
+fn tokenize(line: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    for (idx, c) in line.char_indices() {
+        if !c.is_alphanumeric() {
+            if start < idx {
+                tokens.push(&line[start..idx]);
+            }
+            tokens.push(&line[idx..idx + c.len_utf8()]);
+            start = idx + c.len_utf8();
+        }
+    }
+    if start < line.len() {
+        tokens.push(&line[start..]);
+    }
+
+    tokens
+}
+
+fn diff_tokens<'a>(old: &'a str, new: &'a str) -> Vec<(ChangeTag, &'a str)> {
+    let (old, new) = (tokenize(old), tokenize(new));
+    similar::capture_diff_slices(similar::Algorithm::Myers, &old, &new)
+        .iter()
+        .flat_map(|op| op.iter_changes(&old, &new))
+        .map(|change| (change.tag(), change.value()))
+        .collect()
+}
+
+/// Colors a line, highlighting the words that aren't `unchanged_tag` in the word diff.
+fn highlight_words(
+    color: &str,
+    marker: char,
+    words: &[(ChangeTag, &str)],
+    unchanged_tag: ChangeTag,
+) -> String {
+    let mut line = format!("{color}{marker}");
+    for &(tag, word) in words {
+        if tag == ChangeTag::Equal {
+            line.push_str(word);
+        } else if tag != unchanged_tag {
+            line.push_str(&format!("{HIGHLIGHT}{word}{NO_HIGHLIGHT}"));
+        }
+    }
+    line.push_str(RESET);
+
+    line
+}
+
+/// Formats a pair of lines where `old` was replaced by `new`.
+fn replaced_lines(old: &str, new: &str, color: bool) -> (String, String) {
+    if !color {
+        return (format!("-{old}"), format!("+{new}"));
+    }
+
+    let words = diff_tokens(old, new);
+    (
+        highlight_words(RED, '-', &words, ChangeTag::Insert),
+        highlight_words(GREEN, '+', &words, ChangeTag::Delete),
+    )
+}
+
+fn write_lines(
+    out: &mut impl Write,
+    lines: &[(DiffCell<String>, DiffCell<String>)],
+    color: bool,
+) -> io::Result<()> {
+    let deleted = |line: &str| {
+        if color {
+            format!("{RED}-{line}{RESET}")
+        } else {
+            format!("-{line}")
+        }
+    };
+    let inserted = |line: &str| {
+        if color {
+            format!("{GREEN}+{line}{RESET}")
+        } else {
+            format!("+{line}")
+        }
+    };
+
+    // Insertions are held back so a replaced region reads as all of its deletions, then all of
+    // its insertions
+    let mut pending_inserts: Vec<String> = vec![];
+    for (old, new) in lines {
+        match (old, new) {
+            (DiffCell::Delete(old), DiffCell::Insert(new)) => {
+                let (old, new) = replaced_lines(old, new, color);
+                writeln!(out, "{old}")?;
+                pending_inserts.push(new);
+            }
+            (DiffCell::Delete(old), _) => writeln!(out, "{}", deleted(old))?,
+            (_, DiffCell::Insert(new)) => pending_inserts.push(inserted(new)),
+            _ => {
+                for line in pending_inserts.drain(..) {
+                    writeln!(out, "{line}")?;
+                }
+
+                match (old, new) {
+                    (DiffCell::Collapsed(count), _) | (_, DiffCell::Collapsed(count)) => {
+                        writeln!(out, "{}", collapsed_text(*count))?
+                    }
+                    (DiffCell::Default(line), _) | (_, DiffCell::Default(line)) => {
+                        writeln!(out, " {line}")?
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    for line in pending_inserts {
+        writeln!(out, "{line}")?;
+    }
+
+    Ok(())
+}
+
+/// Shortens `name` to `width` characters, replacing the end with an ellipsis.
+fn truncate_name(name: &str, width: Option<usize>) -> Cow<'_, str> {
+    match width {
+        Some(width) if name.chars().count() > width => {
+            let kept: String = name.chars().take(width.saturating_sub(1)).collect();
+            Cow::Owned(format!("{kept}…"))
+        }
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// How many characters of a name fit in the headers. `--name-width` wins, with 0 meaning no
+/// limit, otherwise names are fit to the terminal if stdout is one.
+fn header_name_width(name_width: Option<usize>, labels: (&str, &str)) -> Option<usize> {
+    match name_width {
+        Some(0) => None,
+        Some(width) => Some(width),
+        None if io::stdout().is_terminal() => {
+            let (Width(columns), _) = terminal_size::terminal_size()?;
+            // "--- label 00001234 "
+            let prefix = 4 + labels.0.len().max(labels.1.len()) + 1 + 8 + 1;
+            Some(
+                (columns as usize)
+                    .saturating_sub(prefix)
+                    .max(MIN_NAME_WIDTH),
+            )
+        }
+        None => None,
+    }
+}
+
+fn write_change(
+    out: &mut impl Write,
+    (label1, program1): (&str, &'static Program),
+    (label2, program2): (&str, &'static Program),
+    change: &FunctionChange,
+    options: &PrintOptions,
+) -> io::Result<()> {
+    let model = DiffModel::build(program1, program2, change, options.view);
+    let name = truncate_name(&model.demangled_name, options.name_width);
+    writeln!(out, "--- {label1} {:08x} {name}", model.address1)?;
+    writeln!(out, "+++ {label2} {:08x} {name}", model.address2)?;
+    for inlined in &model.inlined {
+        let label = if inlined.side == 0 { label1 } else { label2 };
+        let callee = demangle_symbol(&inlined.name).unwrap_or_else(|| inlined.name.clone());
+        writeln!(out, "# {label} inlines {callee} at {:08x}", inlined.address)?;
+    }
+    for (label, folded_with) in [
+        (label1, &model.folded_with.0),
+        (label2, &model.folded_with.1),
+    ] {
+        if !folded_with.is_empty() {
+            let names: Vec<_> = folded_with
+                .iter()
+                .map(|name| demangle_symbol(name).unwrap_or_else(|| name.clone()))
+                .collect();
+            writeln!(out, "# {label}: folded with {}", names.join(", "))?;
+        }
+    }
+
+    write_lines(out, &model.lines, options.color)
+}
+
+/// Prints a single change as a unified diff, see `print_changes`.
+pub fn print_change(
+    program1: (&str, &'static Program),
+    program2: (&str, &'static Program),
+    change: &FunctionChange,
+    view_options: &DiffViewOptions,
+    name_width: Option<usize>,
+) -> io::Result<()> {
+    let options = PrintOptions {
+        view: view_options,
+        color: io::stdout().is_terminal(),
+        name_width: header_name_width(name_width, (program1.0, program2.0)),
+    };
+    write_change(
+        &mut io::stdout().lock(),
+        program1,
+        program2,
+        change,
+        &options,
+    )
+}
+
+/// Prints a diff of two blobs of code from `diff_raw` as a unified diff, see `print_changes`.
+pub fn print_raw_change(labels: (&str, &str), model: &DiffModel) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(out, "--- {} {:08x}", labels.0, model.address1)?;
+    writeln!(out, "+++ {} {:08x}", labels.1, model.address2)?;
+
+    write_lines(&mut out, &model.lines, io::stdout().is_terminal())
+}
+
+/// Prints every function of a program in address order, one per line as
+/// `address\tlength\tsection\tname\tdemangled name`. Functions without a symbol aren't loaded,
+/// run with `RUST_LOG=debug` to see those.
+pub fn print_functions(program: &Program) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+
+    for (address, name) in &program.function_index {
+        let function = &program.functions[name];
+        let demangled = tfbindiff::util::demangle_symbol(name).unwrap_or_default();
+        writeln!(
+            out,
+            "{address:08x}\t{:#x}\t{}\t{name}\t{demangled}",
+            function.length(),
+            function.section_name(program)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Prints the ranges of code that no FDE covers, one per line, and how much of the code that adds
+/// up to. Functions in these ranges aren't found, so they're never compared.
+pub fn print_coverage(label: &str, program: &Program) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    let coverage = &program.coverage;
+
+    for (section_idx, range) in &coverage.uncovered {
+        let section_name = program
+            .section_names
+            .get(section_idx)
+            .map_or("", String::as_str);
+        writeln!(
+            out,
+            "{:08x}\t{:08x}\t{:#x}\t{section_name}",
+            range.start,
+            range.end,
+            range.end - range.start
+        )?;
+    }
+
+    let uncovered = coverage.uncovered_size();
+    let percent = if coverage.code_size == 0 {
+        0.0
+    } else {
+        uncovered as f64 * 100.0 / coverage.code_size as f64
+    };
+    eprintln!(
+        "{label}: {uncovered:#x} of {:#x} bytes of code ({percent:.1}%) in {} ranges aren't covered by any FDE",
+        coverage.code_size,
+        coverage.uncovered.len()
+    );
+
+    Ok(())
+}
+
+/// Prints every change as a unified diff. When stdout is a terminal, replaced lines are colored
+/// and the words that changed within them are highlighted, otherwise whole `-`/`+` lines are
+/// printed without any escape codes. Demangled names longer than `name_width` are cut short, see
+/// `header_name_width`.
+pub fn print_changes(
+    programs: &[(String, &'static Program)],
+    comparisons: &[ProgramComparison],
+    view_options: &DiffViewOptions,
+    name_width: Option<usize>,
+) -> io::Result<()> {
+    let color = io::stdout().is_terminal();
+    let mut out = io::stdout().lock();
+
+    for (pair, comparison) in programs.windows(2).zip(comparisons) {
+        let options = PrintOptions {
+            view: view_options,
+            color,
+            name_width: header_name_width(name_width, (&pair[0].0, &pair[1].0)),
+        };
+        for change in &comparison.changes {
+            write_change(
+                &mut out,
+                (&pair[0].0, pair[0].1),
+                (&pair[1].0, pair[1].1),
+                change,
+                &options,
+            )?;
+            writeln!(out)?;
+        }
+
+        if comparison.ignored_changes != 0 {
+            eprintln!(
+                "{} -> {}: {} ignored functions changed",
+                pair[0].0, pair[1].0, comparison.ignored_changes
+            );
+        }
+        if comparison.minor_changes != 0 {
+            eprintln!(
+                "{} -> {}: {} functions changed less than --min-change",
+                pair[0].0, pair[1].0, comparison.minor_changes
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a table of how many functions changed in each section, most changed first.
+pub fn print_section_stats(
+    programs: &[(String, &'static Program)],
+    comparisons: &[ProgramComparison],
+) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+
+    for (idx, (pair, comparison)) in programs.windows(2).zip(comparisons).enumerate() {
+        let stats = section_stats(pair[0].1, pair[1].1, comparison);
+        let width = stats
+            .iter()
+            .map(|stats| stats.section.len())
+            .chain(["section".len()])
+            .max()
+            .unwrap_or_default();
+
+        if idx != 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "{} -> {}", pair[0].0, pair[1].0)?;
+        writeln!(
+            out,
+            "{:<width$}  {:>9}  {:>7}  {:>5}  {:>7}  {:>10}",
+            "section", "functions", "changed", "added", "removed", "similarity"
+        )?;
+        for stats in stats {
+            writeln!(
+                out,
+                "{:<width$}  {:>9}  {:>7}  {:>5}  {:>7}  {:>9.1}%",
+                stats.section,
+                stats.functions,
+                stats.changed,
+                stats.added,
+                stats.removed,
+                stats.similarity * 100.0
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn format_state(state: &FunctionState) -> String {
+    match (state.status, state.similarity) {
+        (FunctionStatus::Changed, Some(similarity)) => {
+            format!("changed ({:.1}% similar)", similarity * 100.0)
+        }
+        (status, _) => status.as_str().to_string(),
+    }
+}
+
+/// Prints the functions that regressed or got fixed since `baseline`, returning whether any
+/// regressed.
+pub fn print_baseline_delta(
+    programs: &[(String, &'static Program)],
+    baseline: &Baseline,
+    current: &Baseline,
+) -> io::Result<bool> {
+    let mut out = io::stdout().lock();
+    let mut any_regressed = false;
+
+    for (idx, pair) in programs.windows(2).enumerate() {
+        let delta = baseline.delta(current, idx);
+        writeln!(
+            out,
+            "{} -> {}: {} regressed, {} fixed since the baseline",
+            pair[0].0,
+            pair[1].0,
+            delta.regressed.len(),
+            delta.fixed.len()
+        )?;
+
+        for (label, changes) in [("regressed", &delta.regressed), ("fixed", &delta.fixed)] {
+            for change in changes {
+                let name = tfbindiff::util::demangle_symbol(&change.name)
+                    .unwrap_or_else(|| change.name.clone());
+                writeln!(
+                    out,
+                    "{label:<9} {name}: {} -> {}",
+                    format_state(&change.before),
+                    format_state(&change.after)
+                )?;
+            }
+        }
+
+        any_regressed |= !delta.regressed.is_empty();
+    }
+
+    Ok(any_regressed)
+}
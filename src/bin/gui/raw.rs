@@ -0,0 +1,68 @@
+use crate::options::Options;
+use crate::output;
+use std::fs;
+use std::path::Path;
+use tfbindiff::diff_model::diff_raw;
+
+/// Parses machine code written out as hex, like `55 48 89 e5` or `554889e5`. Bytes may also have
+/// `0x` prefixes or `\x` escapes and be separated by commas, as disassemblers copy code in all of
+/// these forms.
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|token| token.trim_start_matches("0x").replace("\\x", ""))
+        .collect();
+
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("{c:?} isn't a hex digit"));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".to_string());
+    }
+
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&digits[idx..idx + 2], 16).unwrap())
+        .collect())
+}
+
+/// Diffs the two hex files of `diff-raw` and prints the result. The code is decoded as 64-bit
+/// unless `--bits` says otherwise.
+pub fn diff_files(options: &Options) {
+    let blobs: Vec<_> = options
+        .programs
+        .iter()
+        .map(|path| {
+            let text = fs::read_to_string(path).unwrap_or_else(|error| {
+                eprintln!("couldn't read {path}: {error}");
+                std::process::exit(1);
+            });
+            parse_hex(&text).unwrap_or_else(|error| {
+                eprintln!("{path}: {error}");
+                std::process::exit(1);
+            })
+        })
+        .collect();
+
+    let base = |idx: usize| options.raw_bases.get(&idx).copied().unwrap_or(0);
+    let model = diff_raw(
+        &blobs[0],
+        base(0),
+        &blobs[1],
+        base(1),
+        options.bitness.unwrap_or(64),
+        &options.view,
+    );
+
+    let label = |path: &str| {
+        Path::new(path).file_name().map_or_else(
+            || path.to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+    };
+    output::print_raw_change(
+        (&label(&options.programs[0]), &label(&options.programs[1])),
+        &model,
+    )
+    .unwrap();
+}
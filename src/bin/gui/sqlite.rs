@@ -0,0 +1,139 @@
+use rusqlite::{params, Connection};
+use tfbindiff::compare::ProgramComparison;
+use tfbindiff::diff_model::{DiffCell, DiffModel, DiffViewOptions};
+use tfbindiff::program::Program;
+
+const SCHEMA: &str = "
+CREATE TABLE comparisons (
+    id INTEGER PRIMARY KEY,
+    program1 TEXT NOT NULL,
+    program2 TEXT NOT NULL
+);
+CREATE TABLE functions (
+    id INTEGER PRIMARY KEY,
+    comparison INTEGER NOT NULL REFERENCES comparisons(id),
+    name TEXT NOT NULL,
+    demangled_name TEXT NOT NULL,
+    address1 INTEGER NOT NULL,
+    address2 INTEGER NOT NULL,
+    similarity REAL NOT NULL,
+    instructions1 INTEGER NOT NULL,
+    instructions2 INTEGER NOT NULL,
+    size1 INTEGER NOT NULL,
+    size2 INTEGER NOT NULL,
+    section1 TEXT NOT NULL,
+    section2 TEXT NOT NULL,
+    register_renamed INTEGER NOT NULL,
+    truncated INTEGER NOT NULL,
+    semantic_hash1 TEXT,
+    semantic_hash2 TEXT
+);
+CREATE TABLE diff_lines (
+    function INTEGER NOT NULL REFERENCES functions(id),
+    line INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    old TEXT,
+    new TEXT
+);
+";
+
+/// Semantic hashes are stored as hex, SQLite integers are signed.
+fn hash_text(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+/// Tag (` `, `-`, `+`, `!` for replaced, `...`) and text of a row of the side-by-side diff.
+fn diff_row(
+    old: &DiffCell<String>,
+    new: &DiffCell<String>,
+) -> (&'static str, Option<String>, Option<String>) {
+    let text = |cell: &DiffCell<String>| match cell {
+        DiffCell::Default(line) | DiffCell::Insert(line) | DiffCell::Delete(line) => {
+            Some(line.clone())
+        }
+        DiffCell::Hidden | DiffCell::Collapsed(_) => None,
+    };
+
+    let tag = match (old, new) {
+        (DiffCell::Collapsed(_), _) | (_, DiffCell::Collapsed(_)) => "...",
+        (DiffCell::Delete(_), DiffCell::Insert(_)) => "!",
+        (DiffCell::Delete(_), _) => "-",
+        (_, DiffCell::Insert(_)) => "+",
+        _ => " ",
+    };
+
+    (tag, text(old), text(new))
+}
+
+/// Writes every change to a new SQLite database at `path`, replacing any existing file.
+/// `comparisons[i]` is the comparison between `programs[i]` and `programs[i + 1]`. The diff
+/// itself is only stored, one row per line, if `include_lines` is set.
+pub fn write_database(
+    path: &str,
+    programs: &[(String, &'static Program)],
+    comparisons: &[ProgramComparison],
+    view_options: &DiffViewOptions,
+    include_lines: bool,
+) -> rusqlite::Result<()> {
+    let _ = std::fs::remove_file(path);
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+
+    let transaction = connection.transaction()?;
+    for (comparison_idx, (pair, comparison)) in programs.windows(2).zip(comparisons).enumerate() {
+        let (program1, program2) = (pair[0].1, pair[1].1);
+        transaction.execute(
+            "INSERT INTO comparisons (id, program1, program2) VALUES (?1, ?2, ?3)",
+            params![comparison_idx, pair[0].0, pair[1].0],
+        )?;
+
+        for change in &comparison.changes {
+            let demangled_name = tfbindiff::util::demangle_symbol(change.name())
+                .unwrap_or_else(|| change.name().to_string());
+            let (instructions1, instructions2) = change.instruction_counts();
+            let (size1, size2) = change.sizes();
+            let (section1, section2) = change.sections();
+            let (semantic_hash1, semantic_hash2) = change.semantic_hashes(pair[0].1, pair[1].1);
+
+            transaction.execute(
+                "INSERT INTO functions (comparison, name, demangled_name, address1, address2,
+                    similarity, instructions1, instructions2, size1, size2, section1, section2,
+                    register_renamed, truncated, semantic_hash1, semantic_hash2)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                params![
+                    comparison_idx,
+                    change.name(),
+                    demangled_name,
+                    change.address1(),
+                    change.address2(),
+                    change.similarity(),
+                    instructions1,
+                    instructions2,
+                    size1,
+                    size2,
+                    section1,
+                    section2,
+                    change.register_renamed(),
+                    change.truncated(),
+                    semantic_hash1.map(hash_text),
+                    semantic_hash2.map(hash_text),
+                ],
+            )?;
+
+            if include_lines {
+                let function_id = transaction.last_insert_rowid();
+                let lines = DiffModel::build(program1, program2, change, view_options).lines;
+                for (line_idx, (old, new)) in lines.iter().enumerate() {
+                    let (tag, old, new) = diff_row(old, new);
+                    transaction.execute(
+                        "INSERT INTO diff_lines (function, line, tag, old, new)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![function_id, line_idx, tag, old, new],
+                    )?;
+                }
+            }
+        }
+    }
+
+    transaction.commit()
+}
@@ -0,0 +1,25 @@
+use std::cell::RefCell;
+
+thread_local! {
+    static STAGE: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Records what the tool is doing, e.g. `loading program 1 (a.out)`, for the panic hook.
+pub fn set(stage: impl Into<String>) {
+    STAGE.with(|current| *current.borrow_mut() = stage.into());
+}
+
+/// Prefixes panic messages with the current stage and the input files, so a crash on someone
+/// else's binary says which file it happened on.
+pub fn install_panic_hook(inputs: Vec<String>) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let stage = STAGE.with(|current| current.borrow().clone());
+        if !stage.is_empty() {
+            eprintln!("tfbindiff crashed while {stage}");
+        }
+        eprintln!("inputs: {}", inputs.join(" "));
+
+        default_hook(info);
+    }));
+}
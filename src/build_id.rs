@@ -0,0 +1,176 @@
+use object::{BinaryFormat, Object, ObjectSection};
+use rustc_hash::FxHasher;
+use std::hash::Hasher;
+
+/// Values that the linker stamps into every build, so they differ between builds of the same
+/// code. Reproducible builds are expected to be identical apart from these.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct BuildStamp {
+    /// The `.note.gnu.build-id` note, or the Mach-O UUID.
+    pub build_id: Option<Vec<u8>>,
+    /// When a PE was linked, from its COFF header.
+    pub timestamp: Option<u32>,
+    /// The GUID of the PDB a PE was linked with, which is regenerated by every link.
+    pub pdb_guid: Option<[u8; 16]>,
+}
+
+impl BuildStamp {
+    /// Reads the stamp of `object`, parsed from `data`. Stamps that can't be read are left out.
+    pub fn read(object: &object::File<'_>, data: &[u8]) -> Self {
+        let build_id = match object.build_id() {
+            Ok(Some(build_id)) => Some(build_id.to_vec()),
+            _ => object.mach_uuid().ok().flatten().map(|uuid| uuid.to_vec()),
+        };
+        let timestamp = if object.format() == BinaryFormat::Pe {
+            pe_timestamp(data)
+        } else {
+            None
+        };
+        let pdb_guid = object.pdb_info().ok().flatten().map(|info| info.guid());
+
+        Self {
+            build_id,
+            timestamp,
+            pdb_guid,
+        }
+    }
+
+    /// Zeroes every occurrence of the stamp in `data`.
+    pub fn mask(&self, data: &mut [u8]) {
+        let timestamp = self.timestamp.map(u32::to_le_bytes);
+        let values = [
+            self.build_id.as_deref(),
+            timestamp.as_ref().map(|timestamp| &timestamp[..]),
+            self.pdb_guid.as_ref().map(|guid| &guid[..]),
+        ];
+
+        for value in values.into_iter().flatten() {
+            mask_value(data, value);
+        }
+    }
+
+    /// Each part of the stamp that differs from `other`'s, as its name and how it changed, e.g.
+    /// `("build-id", "1a2b... -> 3c4d...")`.
+    pub fn differences(&self, other: &Self) -> Vec<(&'static str, String)> {
+        let mut differences = vec![];
+        if self.build_id != other.build_id {
+            differences.push((
+                "build-id",
+                format!(
+                    "{} -> {}",
+                    hex_or_none(self.build_id.as_deref()),
+                    hex_or_none(other.build_id.as_deref())
+                ),
+            ));
+        }
+        if self.timestamp != other.timestamp {
+            let format = |timestamp: Option<u32>| {
+                timestamp.map_or("none".to_string(), |timestamp| timestamp.to_string())
+            };
+            differences.push((
+                "timestamp",
+                format!("{} -> {}", format(self.timestamp), format(other.timestamp)),
+            ));
+        }
+        if self.pdb_guid != other.pdb_guid {
+            differences.push((
+                "PDB GUID",
+                format!(
+                    "{} -> {}",
+                    hex_or_none(self.pdb_guid.as_ref().map(|guid| &guid[..])),
+                    hex_or_none(other.pdb_guid.as_ref().map(|guid| &guid[..]))
+                ),
+            ));
+        }
+
+        differences
+    }
+}
+
+fn hex_or_none(bytes: Option<&[u8]>) -> String {
+    match bytes {
+        Some(bytes) => bytes.iter().map(|byte| format!("{byte:02x}")).collect(),
+        None => "none".to_string(),
+    }
+}
+
+fn mask_value(data: &mut [u8], value: &[u8]) {
+    if value.is_empty() {
+        return;
+    }
+
+    let mut idx = 0;
+    while idx + value.len() <= data.len() {
+        if data[idx..idx + value.len()] == *value {
+            data[idx..idx + value.len()].fill(0);
+            idx += value.len();
+        } else {
+            idx += 1;
+        }
+    }
+}
+
+/// `TimeDateStamp` of the COFF header, which follows the `PE\0\0` signature and two 16-bit fields.
+fn pe_timestamp(data: &[u8]) -> Option<u32> {
+    let read_u32 = |offset: usize| {
+        let bytes = data.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let pe_header = read_u32(0x3c)? as usize;
+    read_u32(pe_header + 8)
+}
+
+/// Hashes the contents of every section with `stamp` masked out, so builds that only differ by
+/// their stamps hash the same.
+pub fn content_hash(object: &object::File<'_>, stamp: &BuildStamp) -> u64 {
+    let mut hasher = FxHasher::default();
+    for section in object.sections() {
+        let Ok(data) = section.data() else {
+            continue;
+        };
+
+        let mut data = data.to_vec();
+        stamp.mask(&mut data);
+        hasher.write_usize(data.len());
+        hasher.write(&data);
+    }
+
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_every_part_of_the_stamp() {
+        let stamp = BuildStamp {
+            build_id: Some(vec![0xaa, 0xbb, 0xcc]),
+            timestamp: Some(0x11223344),
+            pdb_guid: None,
+        };
+
+        let mut data = vec![1, 0xaa, 0xbb, 0xcc, 2, 0x44, 0x33, 0x22, 0x11, 0xaa, 0xbb];
+        stamp.mask(&mut data);
+        assert_eq!(data, [1, 0, 0, 0, 2, 0, 0, 0, 0, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn describes_differences() {
+        let stamp1 = BuildStamp {
+            build_id: Some(vec![0x01, 0x02]),
+            ..Default::default()
+        };
+        let stamp2 = BuildStamp {
+            build_id: Some(vec![0x0a, 0x0b]),
+            ..Default::default()
+        };
+
+        assert!(stamp1.differences(&stamp1).is_empty());
+        assert_eq!(
+            stamp1.differences(&stamp2),
+            [("build-id", "0102 -> 0a0b".to_string())]
+        );
+    }
+}
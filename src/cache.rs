@@ -0,0 +1,172 @@
+use crate::compare::CompareOptions;
+use rustc_hash::{FxHashMap, FxHasher};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Bump whenever a change to the comparison could give a different verdict for the same bytes,
+/// so results from older versions are ignored.
+const CACHE_VERSION: u32 = 2;
+const HEADER: &str = "tfbindiff-cache";
+
+/// Outcome of comparing a pair of functions, without the instructions (those are cheap to decode
+/// again).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verdict {
+    Same,
+    SameUnderRenaming,
+    Differs,
+}
+
+impl Verdict {
+    fn to_char(self) -> char {
+        match self {
+            Verdict::Same => 's',
+            Verdict::SameUnderRenaming => 'r',
+            Verdict::Differs => 'd',
+        }
+    }
+
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            's' => Some(Verdict::Same),
+            'r' => Some(Verdict::SameUnderRenaming),
+            'd' => Some(Verdict::Differs),
+            _ => None,
+        }
+    }
+}
+
+/// Comparison verdicts from previous runs, keyed by a hash of both functions' bytes and the
+/// options that affect the verdict. Stored as one `key verdict` line per pair.
+pub struct ComparisonCache {
+    path: PathBuf,
+    entries: FxHashMap<u64, Verdict>,
+    dirty: bool,
+}
+
+impl ComparisonCache {
+    /// `$XDG_CACHE_HOME/tfbindiff/results`, falling back to `~/.cache`.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+        Some(cache_dir.join("tfbindiff").join("results"))
+    }
+
+    /// Loads the cache at `path`. A missing file, or one written by another version, gives an
+    /// empty cache.
+    pub fn load(path: &Path) -> Self {
+        let entries = fs::File::open(path)
+            .ok()
+            .and_then(|file| Self::parse(BufReader::new(file)))
+            .unwrap_or_default();
+        log::debug!(
+            "loaded {} cached results from {}",
+            entries.len(),
+            path.display()
+        );
+
+        Self {
+            path: path.to_path_buf(),
+            entries,
+            dirty: false,
+        }
+    }
+
+    fn parse(reader: impl BufRead) -> Option<FxHashMap<u64, Verdict>> {
+        let mut lines = reader.lines();
+        if lines.next()?.ok()? != format!("{HEADER} {CACHE_VERSION}") {
+            return None;
+        }
+
+        let mut entries = FxHashMap::default();
+        for line in lines {
+            let line = line.ok()?;
+            let (key, verdict) = line.split_once(' ')?;
+            let verdict = Verdict::from_char(verdict.chars().next()?)?;
+            entries.insert(u64::from_str_radix(key, 16).ok()?, verdict);
+        }
+
+        Some(entries)
+    }
+
+    /// Writes the cache back to where it was loaded from, if anything was added.
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut out = BufWriter::new(fs::File::create(&self.path)?);
+        writeln!(out, "{HEADER} {CACHE_VERSION}")?;
+        for (key, verdict) in &self.entries {
+            writeln!(out, "{key:016x} {}", verdict.to_char())?;
+        }
+
+        out.flush()
+    }
+
+    pub(crate) fn key(
+        data: (Option<&[u8]>, Option<&[u8]>),
+        can_disassemble: bool,
+        bitness: u32,
+        options: &CompareOptions,
+    ) -> u64 {
+        let mut hasher = FxHasher::default();
+        data.hash(&mut hasher);
+        can_disassemble.hash(&mut hasher);
+        bitness.hash(&mut hasher);
+        options.equality.hash(&mut hasher);
+        options.head.hash(&mut hasher);
+        options.max_diff_size.hash(&mut hasher);
+        options.allow_reordering.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    pub(crate) fn get(&self, key: u64) -> Option<Verdict> {
+        self.entries.get(&key).copied()
+    }
+
+    pub(crate) fn insert(&mut self, key: u64, verdict: Verdict) {
+        if self.entries.insert(key, verdict) != Some(verdict) {
+            self.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("tfbindiff-cache-{}", std::process::id()));
+        let mut cache = ComparisonCache::load(&path);
+        cache.insert(0x1234, Verdict::Differs);
+        cache.insert(0xffff_0000_0000_0001, Verdict::SameUnderRenaming);
+        cache.save().unwrap();
+
+        let loaded = ComparisonCache::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.get(0x1234), Some(Verdict::Differs));
+        assert_eq!(
+            loaded.get(0xffff_0000_0000_0001),
+            Some(Verdict::SameUnderRenaming)
+        );
+        assert_eq!(loaded.get(0x5678), None);
+    }
+
+    #[test]
+    fn other_version_is_ignored() {
+        let data = format!("{HEADER} {}\n0000000000001234 d\n", CACHE_VERSION + 1);
+        assert!(ComparisonCache::parse(data.as_bytes()).is_none());
+    }
+}
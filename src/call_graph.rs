@@ -0,0 +1,123 @@
+use crate::program::Program;
+use iced_x86::{FlowControl, OpKind};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::VecDeque;
+
+/// Direct calls between the functions of a program, keyed by function name.
+#[derive(Default)]
+pub struct CallGraph {
+    callees: FxHashMap<String, FxHashSet<String>>,
+}
+
+impl CallGraph {
+    /// Builds the call graph from every direct call (and tail call) whose target resolves to a
+    /// known function. Indirect calls can't be resolved statically and are ignored.
+    pub fn build(program: &Program) -> Self {
+        let mut graph = Self::default();
+        for (name, function) in &program.functions {
+            let Some(instructions) = program.get_data_for_function(function).and_then(|data| {
+                program.decode(
+                    function.address(),
+                    data,
+                    function.length(),
+                    Default::default(),
+                )
+            }) else {
+                continue;
+            };
+
+            for instr in instructions {
+                let instr = instr.get();
+                let is_direct_branch = matches!(
+                    instr.op0_kind(),
+                    OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+                );
+                let is_call_or_jump = matches!(
+                    instr.flow_control(),
+                    FlowControl::Call | FlowControl::UnconditionalBranch
+                );
+                if !is_direct_branch || !is_call_or_jump {
+                    continue;
+                }
+
+                // Jumps within the function are just control flow, not calls
+                if let Some((callee, callee_function)) =
+                    program.function_at(instr.near_branch_target())
+                {
+                    if callee_function.address() != function.address()
+                        || instr.flow_control() == FlowControl::Call
+                    {
+                        graph.add_call(name, callee);
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    pub fn add_call(&mut self, caller: &str, callee: &str) {
+        self.callees
+            .entry(caller.to_string())
+            .or_default()
+            .insert(callee.to_string());
+    }
+
+    pub fn callees(&self, caller: &str) -> impl Iterator<Item = &str> {
+        self.callees
+            .get(caller)
+            .into_iter()
+            .flat_map(|callees| callees.iter().map(String::as_str))
+    }
+
+    /// Every function reachable from `root` through one or more calls, in breadth-first order.
+    /// `root` itself is only included if it is (mutually) recursive.
+    pub fn reachable_from(&self, root: &str) -> Vec<&str> {
+        let mut visited: FxHashSet<&str> = FxHashSet::default();
+        let mut reachable = vec![];
+
+        let mut queue: VecDeque<&str> = self.callees(root).collect();
+        while let Some(function) = queue.pop_front() {
+            if !visited.insert(function) {
+                continue;
+            }
+
+            reachable.push(function);
+            queue.extend(self.callees(function));
+        }
+
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reachable_sorted<'a>(graph: &'a CallGraph, root: &str) -> Vec<&'a str> {
+        let mut reachable = graph.reachable_from(root);
+        reachable.sort();
+        reachable
+    }
+
+    #[test]
+    fn self_recursion_terminates() {
+        let mut graph = CallGraph::default();
+        graph.add_call("parse", "parse");
+        graph.add_call("parse", "next_token");
+
+        assert_eq!(reachable_sorted(&graph, "parse"), ["next_token", "parse"]);
+        assert!(graph.reachable_from("next_token").is_empty());
+    }
+
+    #[test]
+    fn mutual_recursion_terminates() {
+        let mut graph = CallGraph::default();
+        graph.add_call("main", "is_even");
+        graph.add_call("is_even", "is_odd");
+        graph.add_call("is_odd", "is_even");
+
+        assert_eq!(reachable_sorted(&graph, "main"), ["is_even", "is_odd"]);
+        assert_eq!(reachable_sorted(&graph, "is_odd"), ["is_even", "is_odd"]);
+    }
+}
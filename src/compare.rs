@@ -1,31 +1,511 @@
-use crate::instruction_wrapper::{InstructionIter, InstructionWrapper};
+use crate::cache::{ComparisonCache, Verdict};
+use crate::ignore_list::IgnoreList;
+use crate::instruction_wrapper::{EqualityPolicy, InstructionStream, InstructionWrapper};
 use crate::matcher::{FunctionMatcher, MatchResult};
 use crate::program::{Function, Program};
-use iced_x86::{Instruction, Mnemonic, OpKind, Register};
+use crate::regalloc::RegisterMapping;
+use iced_x86::{FlowControl, Instruction, Mnemonic, OpKind, Register};
 use itertools::Itertools;
+use rustc_hash::FxHashSet;
+use std::fmt;
+
+/// Knobs controlling how strictly functions are compared.
+#[derive(Clone, Default)]
+pub struct CompareOptions {
+    /// Which differences between instructions are reported.
+    pub equality: EqualityPolicy,
+    /// Only compare the first N instructions of each function.
+    pub head: Option<usize>,
+    /// Functions with more instructions than this aren't diffed, their change only records how
+    /// many instructions they have. See `FunctionChange::too_large`.
+    pub max_diff_size: Option<usize>,
+    /// Also report matched functions that compared equal.
+    pub keep_identical: bool,
+    /// Skip every matched pair whose byte size is equal, only diffing functions that grew or
+    /// shrank.
+    pub size_changed_only: bool,
+    /// Drop changed functions that are just PLT entries or thunks, see `is_thunk`.
+    pub hide_thunks: bool,
+    /// Drop changed functions whose only change is a stack protector that was added or removed,
+    /// see `is_stack_protector_change`.
+    pub hide_stack_protector: bool,
+    /// Experimental: treat functions as equal when their basic blocks only differ by the order of
+    /// independent instructions, see `reorder::equal_up_to_reordering`.
+    pub allow_reordering: bool,
+    /// Suffixes that compilers add to split-out or specialized functions, like `.part` in
+    /// `foo.part.0`. Leftover functions whose names only differ by these are matched if their
+    /// code is similar enough. `None` uses `DEFAULT_NAME_SUFFIXES`.
+    pub name_suffixes: Option<Vec<String>>,
+    /// Functions whose changes are dropped, they're only counted in
+    /// `ProgramComparison::ignored_changes`.
+    pub ignore_list: IgnoreList,
+    /// Invert `ignore_list`: only report changes to the functions on it.
+    pub only_ignored: bool,
+    /// Only compare the functions on this list, leaving every other function out of the results,
+    /// including the unmatched ones. See `missing_listed_functions` to catch typos.
+    pub only_functions: Option<IgnoreList>,
+    /// Treat functions whose bytes only differ where relocations patch in addresses as equal, as
+    /// long as the relocations refer to the same symbols. See `relocated_data_eq`.
+    pub mask_relocations: bool,
+    /// Treat functions whose bytes only differ where the programs' build-ids or timestamps are
+    /// embedded as equal, see `BuildStamp::mask`.
+    pub mask_build_stamp: bool,
+    /// Drop changes where less than this fraction of the instructions differ, from 0.0 to 1.0.
+    /// They're only counted in `ProgramComparison::minor_changes`.
+    pub min_change: f32,
+}
+
+pub const DEFAULT_NAME_SUFFIXES: &[&str] = &[
+    ".cold",
+    ".part",
+    ".isra",
+    ".constprop",
+    ".llvm",
+    ".lto_priv",
+    ".localalias",
+];
+
+#[derive(Debug)]
+pub enum CompareError {
+    /// A 32-bit program was compared against a 64-bit one, or the other way around.
+    PointerSizeMismatch {
+        pointer_size1: usize,
+        pointer_size2: usize,
+    },
+}
+
+impl fmt::Display for CompareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompareError::PointerSizeMismatch {
+                pointer_size1,
+                pointer_size2,
+            } => write!(
+                f,
+                "the first program is {}-bit but the second is {}-bit",
+                pointer_size1 * 8,
+                pointer_size2 * 8
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+/// Reasons the results of comparing two programs may be unreliable, even though they can be
+/// compared at all.
+pub fn comparison_warnings(program1: &Program, program2: &Program) -> Vec<String> {
+    let mut warnings = vec![];
+
+    // e.g. an ELF build against a PE build of the same code, where the calling conventions and
+    // symbol decoration differ too
+    if program1.format != program2.format {
+        warnings.push(format!(
+            "the first program is {:?} but the second is {:?}, differences in the object format and ABI will show up as changes",
+            program1.format, program2.format
+        ));
+    }
+
+    warnings
+}
+
+/// Describes each name on `list` that isn't a function of both programs. Patterns are left out,
+/// they can't be missing.
+pub fn missing_listed_functions(
+    program1: &Program,
+    program2: &Program,
+    list: &IgnoreList,
+) -> Vec<String> {
+    let mut missing: Vec<_> = list
+        .names()
+        .filter_map(|name| {
+            match (
+                program1.find_function(name).is_some(),
+                program2.find_function(name).is_some(),
+            ) {
+                (true, true) => None,
+                (false, true) => Some(format!("{name} isn't in the first program")),
+                (true, false) => Some(format!("{name} isn't in the second program")),
+                (false, false) => Some(format!("{name} isn't in either program")),
+            }
+        })
+        .collect();
+    missing.sort();
+
+    missing
+}
+
+/// Describes how the build stamps of the programs differ, and whether that's all that differs.
+pub fn build_stamp_notes(program1: &Program, program2: &Program) -> Vec<String> {
+    let differences = program1.build_stamp.differences(&program2.build_stamp);
+    let mut notes: Vec<_> = differences
+        .iter()
+        .map(|(part, change)| format!("{part} {change}"))
+        .collect();
+
+    if program1.content_hash == program2.content_hash {
+        if differences.is_empty() {
+            notes.push("binaries are identical".to_string());
+        } else {
+            let parts: Vec<_> = differences.iter().map(|(part, _)| *part).collect();
+            notes.push(format!(
+                "binaries are identical except for {}",
+                parts.join(" and ")
+            ));
+        }
+    }
+
+    notes
+}
+
+/// Compares the bytes of two functions with the programs' build stamps masked out.
+fn stamped_data_eq(
+    (program1, data1): (&Program, &[u8]),
+    (program2, data2): (&Program, &[u8]),
+) -> bool {
+    let (mut data1, mut data2) = (data1.to_vec(), data2.to_vec());
+    program1.build_stamp.mask(&mut data1);
+    program2.build_stamp.mask(&mut data2);
+
+    data1 == data2
+}
+
+fn check_comparable(program1: &Program, program2: &Program) -> Result<(), CompareError> {
+    if program1.pointer_size != program2.pointer_size {
+        return Err(CompareError::PointerSizeMismatch {
+            pointer_size1: program1.pointer_size,
+            pointer_size2: program2.pointer_size,
+        });
+    }
+
+    Ok(())
+}
 
 enum CompareResult {
     Same(),
+    /// Equal once registers are consistently renamed, only produced with
+    /// `EqualityPolicy::ignore_regalloc`.
+    SameUnderRenaming(CompareInfo),
     Differs(CompareInfo),
 }
 
 #[derive(Clone)]
 struct CompareInfo {
     instructions: (Vec<InstructionWrapper>, Vec<InstructionWrapper>),
+    // Set if either instruction stream was cut short by `CompareOptions::head`
+    truncated: bool,
+    // Instruction counts of functions over `CompareOptions::max_diff_size`, whose instructions
+    // aren't kept
+    too_large: Option<(usize, usize)>,
+    policy: EqualityPolicy,
+}
+
+/// Heuristically detects PLT entries and import/adjustor thunks: a handful of instructions that end
+/// in a jump somewhere else, e.g. `jmp [rip+x]` or `endbr64; bnd jmp [rip+x]`.
+pub fn is_thunk(instructions: &[InstructionWrapper]) -> bool {
+    let Some((last, rest)) = instructions.split_last() else {
+        return false;
+    };
+
+    rest.len() <= 2
+        && matches!(
+            last.get().flow_control(),
+            FlowControl::UnconditionalBranch | FlowControl::IndirectBranch
+        )
+        && rest
+            .iter()
+            .all(|instr| instr.get().flow_control() == FlowControl::Next)
+}
+
+/// Splits off a trailing direct `call X; ret`, returning the rest and `X`.
+fn split_call_ret(instructions: &[InstructionWrapper]) -> Option<(&[InstructionWrapper], u64)> {
+    let [rest @ .., call, ret] = instructions else {
+        return None;
+    };
+    let (call, ret) = (call.get(), ret.get());
+
+    (call.flow_control() == FlowControl::Call
+        && call.is_call_near()
+        && ret.flow_control() == FlowControl::Return)
+        .then(|| (rest, call.near_branch_target()))
 }
 
+/// Splits off a trailing direct `jmp X`, returning the rest and `X`.
+fn split_tail_call(instructions: &[InstructionWrapper]) -> Option<(&[InstructionWrapper], u64)> {
+    let [rest @ .., jmp] = instructions else {
+        return None;
+    };
+    let jmp = jmp.get();
+
+    (jmp.flow_control() == FlowControl::UnconditionalBranch && jmp.is_jmp_near())
+        .then(|| (rest, jmp.near_branch_target()))
+}
+
+/// Detects a `call X; ret` at the end of one function that became a `jmp X` tail call in the
+/// other, or the other way around, with everything before it unchanged. Targets are compared by
+/// symbol, as their addresses usually differ between builds.
+pub fn is_tail_call_change(
+    (program1, instructions1): (&Program, &[InstructionWrapper]),
+    (program2, instructions2): (&Program, &[InstructionWrapper]),
+) -> bool {
+    let endings = match (
+        split_call_ret(instructions1),
+        split_tail_call(instructions2),
+    ) {
+        (Some(call_ret), Some(tail_call)) => (call_ret, tail_call),
+        _ => match (
+            split_tail_call(instructions1),
+            split_call_ret(instructions2),
+        ) {
+            (Some(tail_call), Some(call_ret)) => (tail_call, call_ret),
+            _ => return false,
+        },
+    };
+    let ((rest1, target1), (rest2, target2)) = endings;
+
+    let target1 = program1.symbolize(target1).map(|(_, name)| name);
+    let target2 = program2.symbolize(target2).map(|(_, name)| name);
+    rest1 == rest2 && target1.is_some() && target1 == target2
+}
+
+/// True for loads and checks of the stack protector's canary, which lives at a fixed offset
+/// from the thread pointer: `fs:[0x28]` on x86-64 and `gs:[0x14]` on x86.
+fn is_canary_access(instr: &Instruction) -> bool {
+    (0..instr.op_count()).any(|op_idx| instr.op_kind(op_idx) == OpKind::Memory)
+        && instr.memory_base() == Register::None
+        && instr.memory_index() == Register::None
+        && matches!(
+            (instr.memory_segment(), instr.memory_displacement64()),
+            (Register::FS, 0x28) | (Register::GS, 0x14)
+        )
+}
+
+/// `sub rsp, N` and `add rsp, N`, which grow to make room for the canary.
+fn is_stack_adjustment(instr: &Instruction) -> bool {
+    matches!(instr.mnemonic(), Mnemonic::Sub | Mnemonic::Add)
+        && instr.op0_kind() == OpKind::Register
+        && matches!(instr.op0_register(), Register::ESP | Register::RSP)
+        && instr.op1_kind() != OpKind::Register
+}
+
+/// Removes the stack protector's canary setup and check from a function, along with any stack
+/// adjustments. Returns the remaining instructions, and whether there was a stack protector.
+fn strip_stack_protector(
+    program: &Program,
+    instructions: &[InstructionWrapper],
+) -> (Vec<InstructionWrapper>, bool) {
+    let mut keep = vec![true; instructions.len()];
+    let mut found = false;
+    let instr_at = |idx: usize| instructions.get(idx).map(InstructionWrapper::get);
+
+    for (idx, instr) in instructions.iter().map(InstructionWrapper::get).enumerate() {
+        if is_canary_access(instr) {
+            found = true;
+            keep[idx] = false;
+
+            let register = instr.op0_register().full_register();
+            if instr.mnemonic() == Mnemonic::Mov {
+                // mov rax, fs:[0x28]; mov [rsp+N], rax; xor eax, eax
+                let stored = instr_at(idx + 1).is_some_and(|store| {
+                    store.mnemonic() == Mnemonic::Mov
+                        && store.op0_kind() == OpKind::Memory
+                        && store.op1_kind() == OpKind::Register
+                        && store.op1_register().full_register() == register
+                });
+                if stored {
+                    keep[idx + 1] = false;
+                    if instr_at(idx + 2).is_some_and(|clear| {
+                        clear.mnemonic() == Mnemonic::Xor
+                            && clear.op0_kind() == OpKind::Register
+                            && clear.op1_kind() == OpKind::Register
+                            && clear.op0_register() == clear.op1_register()
+                            && clear.op0_register().full_register() == register
+                    }) {
+                        keep[idx + 2] = false;
+                    }
+                }
+            } else {
+                // mov rdx, [rsp+N]; sub rdx, fs:[0x28]; jne fail
+                if idx > 0
+                    && instr_at(idx - 1).is_some_and(|load| {
+                        load.mnemonic() == Mnemonic::Mov
+                            && load.op0_kind() == OpKind::Register
+                            && load.op1_kind() == OpKind::Memory
+                            && load.op0_register().full_register() == register
+                    })
+                {
+                    keep[idx - 1] = false;
+                }
+                if instr_at(idx + 1)
+                    .is_some_and(|branch| branch.flow_control() == FlowControl::ConditionalBranch)
+                {
+                    keep[idx + 1] = false;
+                }
+            }
+        } else if instr.flow_control() == FlowControl::Call && instr.is_call_near() {
+            let target = instr.near_branch_target();
+            let name = program
+                .plt_entries
+                .get(&target)
+                .map(String::as_str)
+                .or_else(|| program.symbolize(target).map(|(_, name)| name));
+            // __stack_chk_fail, or __stack_chk_fail_local in 32-bit PIC code
+            if name.is_some_and(|name| name.starts_with("__stack_chk_fail")) {
+                found = true;
+                keep[idx] = false;
+            }
+        } else if is_stack_adjustment(instr) {
+            keep[idx] = false;
+        }
+    }
+
+    let stripped = instructions
+        .iter()
+        .zip(keep)
+        .filter_map(|(instr, keep)| keep.then_some(*instr))
+        .collect();
+    (stripped, found)
+}
+
+/// Detects a stack protector (`-fstack-protector`) that was added to or removed from a function,
+/// with nothing else changed. Its canary setup and check otherwise show up as scattered changes
+/// at the start and end of the function.
+pub fn is_stack_protector_change(
+    (program1, instructions1): (&Program, &[InstructionWrapper]),
+    (program2, instructions2): (&Program, &[InstructionWrapper]),
+) -> bool {
+    let (stripped1, found1) = strip_stack_protector(program1, instructions1);
+    let (stripped2, found2) = strip_stack_protector(program2, instructions2);
+
+    found1 != found2 && stripped1 == stripped2
+}
+
+/// The depth that a `sub esp/rsp, <depth>` allocates, or `None` if it isn't a constant.
 fn get_stack_depth_from_instruction(instr: &Instruction) -> Option<i64> {
     match instr.op1_kind() {
         OpKind::Immediate8to32 => Some(instr.immediate8to32().into()),
         OpKind::Immediate32 => Some(instr.immediate32().into()),
-        OpKind::Register => None,
-        _ => todo!("stack depth: unhandled op1 type {:?}", instr.op1_kind()),
+        OpKind::Immediate8to64 => Some(instr.immediate8to64()),
+        OpKind::Immediate32to64 => Some(instr.immediate32to64()),
+        // e.g. sub rsp, rax / sub rsp, [rbx]
+        _ => None,
+    }
+}
+
+/// `sub esp/rsp, <depth>`
+fn is_stack_allocation(instr: &Instruction) -> bool {
+    instr.mnemonic() == Mnemonic::Sub
+        && instr.op0_kind() == OpKind::Register
+        && matches!(instr.op0_register(), Register::ESP | Register::RSP)
+}
+
+/// The stack depths that `instructions` allocate, in order. Instructions compare equal without
+/// their immediates, so checks that only look at instructions miss a change in stack depth.
+fn stack_allocations(instructions: &[InstructionWrapper]) -> Vec<Option<i64>> {
+    instructions
+        .iter()
+        .map(InstructionWrapper::get)
+        .filter(|instr| is_stack_allocation(instr))
+        .map(get_stack_depth_from_instruction)
+        .collect()
+}
+
+pub(crate) fn create_instruction_iter<'a>(
+    program: &'a Program,
+    func: &Function,
+    policy: EqualityPolicy,
+) -> Box<dyn InstructionStream + 'a> {
+    let func_content = program.get_data_for_function(func).unwrap_or_default();
+    // Other architectures are only compared byte by byte
+    program
+        .decode(func.address(), func_content, func.length(), policy)
+        .unwrap_or_else(|| Box::new(std::iter::empty()))
+}
+
+/// Decodes at most `head` instructions, returning whether there were more to decode.
+pub(crate) fn collect_instructions(
+    program: &Program,
+    func: &Function,
+    head: Option<usize>,
+    policy: EqualityPolicy,
+) -> (Vec<InstructionWrapper>, bool) {
+    let mut iter = create_instruction_iter(program, func, policy);
+    let mut instructions: Vec<_> = iter
+        .by_ref()
+        .take(head.map_or(usize::MAX, |head| head + 1))
+        .collect();
+
+    if iter.ended_mid_instruction() {
+        log::warn!(
+            "function at {:08x} ends in the middle of an instruction, its length ({:#x}) is probably wrong",
+            func.address(),
+            func.length()
+        );
+    }
+    if iter.invalid_bytes() > 0 {
+        log::debug!(
+            "function at {:08x} has {} bytes that couldn't be decoded",
+            func.address(),
+            iter.invalid_bytes()
+        );
+    }
+
+    match head {
+        Some(head) if instructions.len() > head => {
+            instructions.truncate(head);
+            (instructions, true)
+        }
+        _ => (instructions, false),
     }
 }
 
-fn create_instruction_iter<'a>(program: &'a Program, func: &Function) -> InstructionIter<'a> {
-    let func_content = program.get_data_for_function(func).unwrap();
-    InstructionIter::new(func.address(), func_content, program.pointer_size)
+/// Ratio of equal instructions between two functions, from 0.0 (nothing in common) to 1.0.
+pub(crate) fn function_similarity(
+    program1: &Program,
+    func1: &Function,
+    program2: &Program,
+    func2: &Function,
+) -> f32 {
+    let policy = EqualityPolicy::default();
+    let instructions1: Vec<_> = create_instruction_iter(program1, func1, policy).collect();
+    let instructions2: Vec<_> = create_instruction_iter(program2, func2, policy).collect();
+
+    let diff_ops =
+        similar::capture_diff_slices(similar::Algorithm::Myers, &instructions1, &instructions2);
+    similar::get_diff_ratio(&diff_ops, instructions1.len(), instructions2.len())
+}
+
+fn collect_compare_info(
+    program1: &Program,
+    program2: &Program,
+    func1: &Function,
+    func2: &Function,
+    options: &CompareOptions,
+) -> CompareInfo {
+    let (instructions1, truncated1) =
+        collect_instructions(program1, func1, options.head, options.equality);
+    let (instructions2, truncated2) =
+        collect_instructions(program2, func2, options.head, options.equality);
+
+    let counts = (instructions1.len(), instructions2.len());
+    if options
+        .max_diff_size
+        .is_some_and(|max| counts.0 > max || counts.1 > max)
+    {
+        return CompareInfo {
+            instructions: (vec![], vec![]),
+            truncated: truncated1 || truncated2,
+            too_large: Some(counts),
+            policy: options.equality,
+        };
+    }
+
+    CompareInfo {
+        instructions: (instructions1, instructions2),
+        truncated: truncated1 || truncated2,
+        too_large: None,
+        policy: options.equality,
+    }
 }
 
 fn compare_functions(
@@ -33,11 +513,106 @@ fn compare_functions(
     program2: &Program,
     func1: &Function,
     func2: &Function,
+    options: &CompareOptions,
+    cache: Option<&mut ComparisonCache>,
 ) -> CompareResult {
-    let mut has_difference = false;
+    let data = (
+        program1.get_data_for_function(func1),
+        program2.get_data_for_function(func2),
+    );
 
-    let instructions1 = create_instruction_iter(program1, func1);
-    let instructions2 = create_instruction_iter(program2, func2);
+    // Identical bytes always decode to identical instructions, no need to look any closer
+    if data.0 == data.1 {
+        return CompareResult::Same();
+    }
+    if let (true, Some(data1), Some(data2)) = (options.mask_relocations, data.0, data.1) {
+        if relocated_data_eq((program1, func1, data1), (program2, func2, data2)) {
+            return CompareResult::Same();
+        }
+    }
+    if let (true, Some(data1), Some(data2)) = (options.mask_build_stamp, data.0, data.1) {
+        if stamped_data_eq((program1, data1), (program2, data2)) {
+            return CompareResult::Same();
+        }
+    }
+
+    let Some(cache) = cache else {
+        return compare_function_instructions(program1, program2, func1, func2, options);
+    };
+
+    let can_disassemble = program1.can_disassemble() && program2.can_disassemble();
+    let key = ComparisonCache::key(data, can_disassemble, program1.bitness, options);
+    if let Some(verdict) = cache.get(key) {
+        return match verdict {
+            Verdict::Same => CompareResult::Same(),
+            Verdict::SameUnderRenaming => CompareResult::SameUnderRenaming(collect_compare_info(
+                program1, program2, func1, func2, options,
+            )),
+            Verdict::Differs => CompareResult::Differs(collect_compare_info(
+                program1, program2, func1, func2, options,
+            )),
+        };
+    }
+
+    let result = compare_function_instructions(program1, program2, func1, func2, options);
+    let verdict = match result {
+        CompareResult::Same() => Verdict::Same,
+        CompareResult::SameUnderRenaming(_) => Verdict::SameUnderRenaming,
+        CompareResult::Differs(_) => Verdict::Differs,
+    };
+    cache.insert(key, verdict);
+
+    result
+}
+
+/// Compares the bytes of two functions, except where a relocation patches in an address. Those
+/// bytes depend on where things ended up in each build, so instead the relocations at the same
+/// offset have to refer to the same symbol. Only works for programs that kept their relocations,
+/// like relocatable objects or binaries linked with `--emit-relocs`.
+pub fn relocated_data_eq(
+    (program1, func1, data1): (&Program, &Function, &[u8]),
+    (program2, func2, data2): (&Program, &Function, &[u8]),
+) -> bool {
+    if data1.len() != data2.len() {
+        return false;
+    }
+
+    let mut offset = 0;
+    while offset < data1.len() {
+        let relocation1 = program1.relocations.get(&(func1.address() + offset as u64));
+        let relocation2 = program2.relocations.get(&(func2.address() + offset as u64));
+
+        match (relocation1, relocation2) {
+            (Some(relocation1), Some(relocation2)) if relocation1 != relocation2 => return false,
+            (Some(relocation), Some(_)) if relocation.size > 0 => offset += relocation.size,
+            // Without a size, the bytes have to match after all
+            (Some(_), Some(_)) | (None, None) => {
+                if data1[offset] != data2[offset] {
+                    return false;
+                }
+                offset += 1;
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// The slow part of `compare_functions`, for functions whose bytes differ.
+fn compare_function_instructions(
+    program1: &Program,
+    program2: &Program,
+    func1: &Function,
+    func2: &Function,
+    options: &CompareOptions,
+) -> CompareResult {
+    // Without a disassembler, different bytes are all we can report
+    let mut has_difference = !program1.can_disassemble() || !program2.can_disassemble();
+
+    let head = options.head.unwrap_or(usize::MAX);
+    let instructions1 = create_instruction_iter(program1, func1, options.equality).take(head);
+    let instructions2 = create_instruction_iter(program2, func2, options.equality).take(head);
 
     for zipped in instructions1.zip_longest(instructions2) {
         match zipped {
@@ -48,22 +623,14 @@ fn compare_functions(
                 }
 
                 // Opcode matches, let's check for stack depth
-                // FIXME: Only handles 32-bit register
-                // sub esp, <depth>
-                if instr1.get().mnemonic() == Mnemonic::Sub
-                    && instr1.get().op0_kind() == OpKind::Register
-                    && instr1.get().op0_register() == Register::ESP
-                    && instr2.get().op0_kind() == OpKind::Register
-                    && instr2.get().op0_register() == Register::ESP
-                {
+                if is_stack_allocation(instr1.get()) && is_stack_allocation(instr2.get()) {
                     let stack_depth1 = get_stack_depth_from_instruction(instr1.get());
                     let stack_depth2 = get_stack_depth_from_instruction(instr2.get());
 
                     if stack_depth1 != stack_depth2 {
                         has_difference = true;
+                        break;
                     }
-
-                    break;
                 }
             }
             itertools::EitherOrBoth::Left(_) | itertools::EitherOrBoth::Right(_) => {
@@ -74,11 +641,28 @@ fn compare_functions(
     }
 
     if has_difference {
-        let instructions1 = create_instruction_iter(program1, func1).collect();
-        let instructions2 = create_instruction_iter(program2, func2).collect();
-        CompareResult::Differs(CompareInfo {
-            instructions: (instructions1, instructions2),
-        })
+        let info = collect_compare_info(program1, program2, func1, func2, options);
+        if info.too_large.is_some() {
+            return CompareResult::Differs(info);
+        }
+        let (instructions1, instructions2) = &info.instructions;
+
+        if options.allow_reordering
+            && stack_allocations(instructions1) == stack_allocations(instructions2)
+            && crate::reorder::equal_up_to_reordering(instructions1, instructions2)
+        {
+            return CompareResult::Same();
+        }
+
+        let renamed = options.equality.ignore_regalloc
+            && stack_allocations(instructions1) == stack_allocations(instructions2)
+            && RegisterMapping::build(instructions1, instructions2, &options.equality).is_some();
+
+        if renamed {
+            CompareResult::SameUnderRenaming(info)
+        } else {
+            CompareResult::Differs(info)
+        }
     } else {
         CompareResult::Same()
     }
@@ -88,17 +672,53 @@ fn compare_functions(
 pub struct FunctionChange {
     info: CompareInfo,
     name: String,
+    name2: String,
     address1: u64,
     address2: u64,
+    sizes: (usize, usize),
+    sections: (String, String),
+    register_renamed: bool,
+    thunk: bool,
+    tail_call: bool,
+    stack_protector: bool,
 }
 
 impl FunctionChange {
-    fn new(info: CompareInfo, name: String, address1: u64, address2: u64) -> Self {
+    fn new(
+        info: CompareInfo,
+        name: String,
+        name2: String,
+        (program1, func1): (&Program, &Function),
+        (program2, func2): (&Program, &Function),
+    ) -> Self {
+        let thunk = is_thunk(&info.instructions.0) && is_thunk(&info.instructions.1);
+        // A truncated function doesn't end where the instructions do
+        let tail_call = !info.truncated
+            && is_tail_call_change(
+                (program1, &info.instructions.0),
+                (program2, &info.instructions.1),
+            );
+        let stack_protector = !info.truncated
+            && is_stack_protector_change(
+                (program1, &info.instructions.0),
+                (program2, &info.instructions.1),
+            );
+
         Self {
             info,
             name,
-            address1,
-            address2,
+            name2,
+            address1: func1.address(),
+            address2: func2.address(),
+            sizes: (func1.length(), func2.length()),
+            sections: (
+                func1.section_name(program1).to_string(),
+                func2.section_name(program2).to_string(),
+            ),
+            register_renamed: false,
+            thunk,
+            tail_call,
+            stack_protector,
         }
     }
 
@@ -106,6 +726,12 @@ impl FunctionChange {
         &self.name
     }
 
+    /// Name of the function in the second program. Only differs from `name` if the functions
+    /// were matched by their demangled names.
+    pub fn name2(&self) -> &str {
+        &self.name2
+    }
+
     pub fn instructions(&self) -> (&[InstructionWrapper], &[InstructionWrapper]) {
         (&self.info.instructions.0, &self.info.instructions.1)
     }
@@ -117,30 +743,362 @@ impl FunctionChange {
     pub fn address2(&self) -> u64 {
         self.address2
     }
+
+    /// Number of decoded instructions in each function. Only counts the compared instructions if
+    /// the change was `truncated`.
+    pub fn instruction_counts(&self) -> (usize, usize) {
+        self.info.too_large.unwrap_or((
+            self.info.instructions.0.len(),
+            self.info.instructions.1.len(),
+        ))
+    }
+
+    /// Ratio of equal instructions between the compared instructions, from 0.0 to 1.0. Always 0.0
+    /// for changes that were `too_large` to diff.
+    pub fn similarity(&self) -> f32 {
+        if self.too_large() {
+            return 0.0;
+        }
+
+        let (instructions1, instructions2) = self.instructions();
+        let diff_ops =
+            similar::capture_diff_slices(similar::Algorithm::Myers, instructions1, instructions2);
+        similar::get_diff_ratio(&diff_ops, instructions1.len(), instructions2.len())
+    }
+
+    /// Size of each function in bytes.
+    pub fn sizes(&self) -> (usize, usize) {
+        self.sizes
+    }
+
+    /// Names of the sections containing each function.
+    pub fn sections(&self) -> (&str, &str) {
+        (&self.sections.0, &self.sections.1)
+    }
+
+    /// True if the function moved to a differently named section, e.g. from `.text` to
+    /// `.text.hot`.
+    pub fn section_changed(&self) -> bool {
+        self.sections.0 != self.sections.1
+    }
+
+    /// True if the functions are identical apart from a consistent register renaming.
+    pub fn register_renamed(&self) -> bool {
+        self.register_renamed
+    }
+
+    /// True if both functions look like PLT entries or thunks.
+    pub fn thunk(&self) -> bool {
+        self.thunk
+    }
+
+    /// True if the only change is a call and return turning into a tail call, or the other way
+    /// around, see `is_tail_call_change`.
+    pub fn tail_call(&self) -> bool {
+        self.tail_call
+    }
+
+    /// True if the only change is a stack protector that was added or removed, see
+    /// `is_stack_protector_change`.
+    pub fn stack_protector(&self) -> bool {
+        self.stack_protector
+    }
+
+    /// How the instructions were compared.
+    pub(crate) fn policy(&self) -> EqualityPolicy {
+        self.info.policy
+    }
+
+    /// True if only the first `CompareOptions::head` instructions were kept.
+    pub fn truncated(&self) -> bool {
+        self.info.truncated
+    }
+
+    /// True if either function had more instructions than `CompareOptions::max_diff_size`. Their
+    /// instructions weren't kept, so `instructions` is empty and only `instruction_counts` and
+    /// `sizes` say anything about them.
+    pub fn too_large(&self) -> bool {
+        self.info.too_large.is_some()
+    }
+
+    /// `Function::semantic_hash` of each function, for following them across builds.
+    pub fn semantic_hashes(
+        &self,
+        program1: &Program,
+        program2: &Program,
+    ) -> (Option<u64>, Option<u64>) {
+        (
+            program1.functions[&self.name].semantic_hash(program1),
+            program2.functions[&self.name2].semantic_hash(program2),
+        )
+    }
+
+    /// Re-decodes both functions in full, undoing any `CompareOptions::head` truncation.
+    pub fn with_full_instructions(&self, program1: &Program, program2: &Program) -> Self {
+        let func1 = &program1.functions[&self.name];
+        let func2 = &program2.functions[&self.name2];
+
+        let policy = self.info.policy;
+        let instructions = (
+            collect_instructions(program1, func1, None, policy).0,
+            collect_instructions(program2, func2, None, policy).0,
+        );
+
+        Self {
+            tail_call: is_tail_call_change(
+                (program1, &instructions.0),
+                (program2, &instructions.1),
+            ),
+            stack_protector: is_stack_protector_change(
+                (program1, &instructions.0),
+                (program2, &instructions.1),
+            ),
+            info: CompareInfo {
+                instructions,
+                truncated: false,
+                too_large: None,
+                policy,
+            },
+            ..self.clone()
+        }
+    }
+}
+
+/// A matched function that compared equal, only retained with `CompareOptions::keep_identical`.
+#[derive(Clone)]
+pub struct IdenticalFunction {
+    name: String,
+    address1: u64,
+    address2: u64,
+}
+
+impl IdenticalFunction {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address1(&self) -> u64 {
+        self.address1
+    }
+
+    pub fn address2(&self) -> u64 {
+        self.address2
+    }
+}
+
+/// A function that only exists in one of the programs.
+pub struct UnmatchedFunction {
+    name: String,
+    address: u64,
+}
+
+impl UnmatchedFunction {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
 }
 
-pub fn compare_programs(program1: &Program, program2: &Program) -> Vec<FunctionChange> {
-    assert!(
-        program1.pointer_size == program2.pointer_size,
-        "pointer sizes don't match"
+/// Functions of each program that weren't matched with any function of the other.
+pub type UnmatchedFunctions = (Vec<UnmatchedFunction>, Vec<UnmatchedFunction>);
+
+pub struct ProgramComparison {
+    pub changes: Vec<FunctionChange>,
+    pub identical: Vec<IdenticalFunction>,
+    pub only_in_primary: Vec<UnmatchedFunction>,
+    pub only_in_secondary: Vec<UnmatchedFunction>,
+    /// Number of functions on the ignore list that changed, and weren't reported.
+    pub ignored_changes: usize,
+    /// Number of changes below `CompareOptions::min_change`, which weren't reported.
+    pub minor_changes: usize,
+}
+
+/// A single result of `compare_programs_streaming`.
+pub enum ComparedFunction {
+    Changed(FunctionChange),
+    /// Only produced with `CompareOptions::keep_identical`.
+    Identical(IdenticalFunction),
+    /// A change to a function on `CompareOptions::ignore_list`, which shouldn't be reported.
+    Ignored(FunctionChange),
+    /// A change smaller than `CompareOptions::min_change`, which shouldn't be reported.
+    Minor(FunctionChange),
+}
+
+fn compare_pair(
+    (program1, name1, func1): (&Program, &str, &Function),
+    (program2, name2, func2): (&Program, &str, &Function),
+    options: &CompareOptions,
+    cache: Option<&mut ComparisonCache>,
+) -> ComparedFunction {
+    let (compare_info, register_renamed) =
+        match compare_functions(program1, program2, func1, func2, options, cache) {
+            CompareResult::Same() => {
+                return ComparedFunction::Identical(IdenticalFunction {
+                    name: name1.to_string(),
+                    address1: func1.address(),
+                    address2: func2.address(),
+                })
+            }
+            CompareResult::SameUnderRenaming(compare_info) => (compare_info, true),
+            CompareResult::Differs(compare_info) => (compare_info, false),
+        };
+
+    let mut change = FunctionChange::new(
+        compare_info,
+        name1.to_string(),
+        name2.to_string(),
+        (program1, func1),
+        (program2, func2),
     );
+    change.register_renamed = register_renamed;
+
+    ComparedFunction::Changed(change)
+}
+
+/// Compares a single function, without matching up the rest of the programs. `name` can be
+/// mangled or demangled, see `Program::find_function`. Returns `None` if either program has no
+/// such function.
+pub fn compare_function(
+    program1: &Program,
+    program2: &Program,
+    name: &str,
+    options: &CompareOptions,
+) -> Result<Option<ComparedFunction>, CompareError> {
+    check_comparable(program1, program2)?;
 
-    let mut matcher = FunctionMatcher::new(program1, program2);
+    let (Some((name1, func1)), Some((name2, func2))) =
+        (program1.find_function(name), program2.find_function(name))
+    else {
+        return Ok(None);
+    };
 
+    Ok(Some(compare_pair(
+        (program1, name1, func1),
+        (program2, name2, func2),
+        options,
+        None,
+    )))
+}
+
+/// Compares every matched pair of functions. Verdicts are looked up in and added to `cache`, if
+/// one is given.
+pub fn compare_programs(
+    program1: &Program,
+    program2: &Program,
+    options: &CompareOptions,
+    cache: Option<&mut ComparisonCache>,
+) -> Result<ProgramComparison, CompareError> {
     let mut changes: Vec<FunctionChange> = vec![];
+    let mut identical: Vec<IdenticalFunction> = vec![];
+    let mut ignored_changes = 0;
+    let mut minor_changes = 0;
+    let (only_in_primary, only_in_secondary) =
+        compare_programs_streaming(program1, program2, options, cache, |result| match result {
+            ComparedFunction::Changed(change) => changes.push(change),
+            ComparedFunction::Identical(function) => identical.push(function),
+            ComparedFunction::Ignored(_) => ignored_changes += 1,
+            ComparedFunction::Minor(_) => minor_changes += 1,
+        })?;
+
+    changes.sort_by_key(|change| change.address1);
+    identical.sort_by_key(|function| function.address1);
+
+    Ok(ProgramComparison {
+        changes,
+        identical,
+        only_in_primary,
+        only_in_secondary,
+        ignored_changes,
+        minor_changes,
+    })
+}
+
+/// Like `compare_programs`, but hands every result to `on_result` as soon as it's found instead of
+/// collecting them, so each change can be dropped once it has been processed. Results arrive in
+/// matching order rather than sorted by address. Returns the functions that weren't matched, sorted
+/// by address.
+pub fn compare_programs_streaming(
+    program1: &Program,
+    program2: &Program,
+    options: &CompareOptions,
+    mut cache: Option<&mut ComparisonCache>,
+    mut on_result: impl FnMut(ComparedFunction),
+) -> Result<UnmatchedFunctions, CompareError> {
+    check_comparable(program1, program2)?;
+
+    let name_suffixes = options.name_suffixes.clone().unwrap_or_else(|| {
+        DEFAULT_NAME_SUFFIXES
+            .iter()
+            .map(|suffix| suffix.to_string())
+            .collect()
+    });
+    let mut matcher = FunctionMatcher::new(program1, program2, name_suffixes);
+    // Folded functions are matched once per name, but if they're folded the same way in both
+    // programs, that's the same comparison every time
+    let mut compared = FxHashSet::default();
+
     loop {
         match matcher.next_match() {
-            MatchResult::Matched((func1, func2)) => {
-                if let CompareResult::Differs(compare_info) =
-                    compare_functions(program1, program2, func1, func2)
-                {
-                    let name = program1.symbol_map.get(&func1.address()).unwrap();
-                    changes.push(FunctionChange::new(
-                        compare_info,
-                        name.to_string(),
-                        func1.address(),
-                        func2.address(),
-                    ));
+            MatchResult::Matched(((name, func1), (name2, func2))) => {
+                if options.size_changed_only && func1.length() == func2.length() {
+                    continue;
+                }
+
+                if let Some(list) = &options.only_functions {
+                    if !list.matches(name) && !list.matches(name2) {
+                        continue;
+                    }
+                }
+
+                // A folded function is only ignored if all of its names are, otherwise whichever
+                // name comes first decides for the others
+                let ignored = options.ignore_list.matches(name)
+                    && program1
+                        .folded_with(name)
+                        .iter()
+                        .all(|folded_name| options.ignore_list.matches(folded_name));
+                if options.only_ignored && !ignored {
+                    continue;
+                }
+                let hide_ignored = ignored && !options.only_ignored;
+
+                if !compared.insert((func1.address(), func2.address())) {
+                    continue;
+                }
+
+                let result = match compare_pair(
+                    (program1, name, func1),
+                    (program2, name2, func2),
+                    options,
+                    cache.as_deref_mut(),
+                ) {
+                    ComparedFunction::Changed(change) if hide_ignored => {
+                        ComparedFunction::Ignored(change)
+                    }
+                    ComparedFunction::Changed(change)
+                        if options.min_change > 0.0
+                            && 1.0 - change.similarity() < options.min_change =>
+                    {
+                        ComparedFunction::Minor(change)
+                    }
+                    result => result,
+                };
+                match &result {
+                    ComparedFunction::Identical(_) if !options.keep_identical || hide_ignored => {
+                        continue
+                    }
+                    ComparedFunction::Changed(change) if options.hide_thunks && change.thunk => {
+                        continue
+                    }
+                    ComparedFunction::Changed(change)
+                        if options.hide_stack_protector && change.stack_protector =>
+                    {
+                        continue
+                    }
+                    _ => on_result(result),
                 }
             }
             MatchResult::Unmatched => (),
@@ -148,10 +1106,533 @@ pub fn compare_programs(program1: &Program, program2: &Program) -> Vec<FunctionC
         }
     }
 
-    changes.sort_by(|a, b| a.address1.cmp(&b.address1));
+    let to_unmatched = |functions: Vec<(&str, &Function)>| {
+        let mut unmatched: Vec<_> = functions
+            .into_iter()
+            .filter(|(name, _)| {
+                options
+                    .only_functions
+                    .as_ref()
+                    .is_none_or(|list| list.matches(name))
+            })
+            .map(|(name, function)| UnmatchedFunction {
+                name: name.to_string(),
+                address: function.address(),
+            })
+            .collect();
+        unmatched.sort_by_key(|function| function.address);
 
-    // TODO: return this for usage in the GUI
-    let (_program1_unmatched, _program2_unmatched) = matcher.get_unmatched();
+        unmatched
+    };
 
-    changes
+    let (program1_unmatched, program2_unmatched) = matcher.get_unmatched();
+    Ok((
+        to_unmatched(program1_unmatched),
+        to_unmatched(program2_unmatched),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_id::BuildStamp;
+    use crate::program::{CodeCoverage, Relocation};
+    use object::{Architecture, BinaryFormat, SectionIndex};
+    use rustc_hash::FxHashMap;
+
+    // push rbp; mov rbp, rsp; sub rsp, <depth>
+    fn prologue(depth: u8) -> Vec<u8> {
+        vec![0x55, 0x48, 0x89, 0xE5, 0x48, 0x83, 0xEC, depth]
+    }
+
+    const MOV_EAX_ECX: &[u8] = &[0x89, 0xC8];
+    const MOV_EAX_EDX: &[u8] = &[0x89, 0xD0];
+    const LEAVE_RET: &[u8] = &[0xC9, 0xC3];
+
+    // push ebp; mov ebp, esp; sub esp, <depth>
+    fn prologue32(depth: u8) -> Vec<u8> {
+        vec![0x55, 0x89, 0xE5, 0x83, 0xEC, depth]
+    }
+
+    fn program_with_function(code: Vec<u8>, pointer_size: usize) -> Program {
+        let section_idx = SectionIndex(1);
+        let address = 0x1000;
+        let function = Function::new(section_idx, address, address, code.len() as u64);
+
+        let mut functions = FxHashMap::default();
+        functions.insert("f".to_string(), function);
+        let function_index = Program::build_function_index(&functions);
+
+        Program {
+            format: BinaryFormat::Elf,
+            architecture: if pointer_size == 8 {
+                Architecture::X86_64
+            } else {
+                Architecture::I386
+            },
+            pointer_size,
+            bitness: pointer_size as u32 * 8,
+            functions,
+            symbol_map: [(address, "f".to_string())].into_iter().collect(),
+            sections: [(section_idx, code)].into_iter().collect(),
+            section_names: [(section_idx, ".text".to_string())].into_iter().collect(),
+            function_index,
+            relocations: FxHashMap::default(),
+            plt_entries: FxHashMap::default(),
+            build_stamp: BuildStamp::default(),
+            content_hash: 0,
+            coverage: CodeCoverage::default(),
+        }
+    }
+
+    fn compare_with_pointer_size(
+        code1: Vec<u8>,
+        code2: Vec<u8>,
+        pointer_size: usize,
+    ) -> CompareResult {
+        let program1 = program_with_function(code1, pointer_size);
+        let program2 = program_with_function(code2, pointer_size);
+
+        compare_functions(
+            &program1,
+            &program2,
+            &program1.functions["f"],
+            &program2.functions["f"],
+            &CompareOptions::default(),
+            None,
+        )
+    }
+
+    fn compare(code1: Vec<u8>, code2: Vec<u8>) -> CompareResult {
+        compare_with_pointer_size(code1, code2, 8)
+    }
+
+    #[test]
+    fn difference_after_prologue() {
+        let code1 = [prologue(0x10), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+        let code2 = [prologue(0x10), MOV_EAX_EDX.to_vec(), LEAVE_RET.to_vec()].concat();
+
+        assert!(matches!(compare(code1, code2), CompareResult::Differs(_)));
+
+        let code1 = [prologue32(0x10), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+        let code2 = [prologue32(0x10), MOV_EAX_EDX.to_vec(), LEAVE_RET.to_vec()].concat();
+        assert!(matches!(
+            compare_with_pointer_size(code1, code2, 4),
+            CompareResult::Differs(_)
+        ));
+    }
+
+    #[test]
+    fn different_stack_depth() {
+        let code1 = [prologue(0x10), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+        let code2 = [prologue(0x20), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+
+        assert!(matches!(
+            compare(code1.clone(), code2.clone()),
+            CompareResult::Differs(_)
+        ));
+
+        // Reordering can't make up for it either
+        let program1 = program_with_function(code1, 8);
+        let program2 = program_with_function(code2, 8);
+        let options = CompareOptions {
+            allow_reordering: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            compare_functions(
+                &program1,
+                &program2,
+                &program1.functions["f"],
+                &program2.functions["f"],
+                &options,
+                None,
+            ),
+            CompareResult::Differs(_)
+        ));
+    }
+
+    #[test]
+    fn stack_allocation_by_register() {
+        // push rbp; mov rbp, rsp; sub rsp, rax
+        let code = [
+            vec![0x55, 0x48, 0x89, 0xE5, 0x48, 0x29, 0xC4],
+            MOV_EAX_ECX.to_vec(),
+            LEAVE_RET.to_vec(),
+        ]
+        .concat();
+
+        assert!(matches!(
+            compare(code.clone(), code.clone()),
+            CompareResult::Same()
+        ));
+
+        // The depth isn't known, but it's still a different allocation than a constant one
+        let code2 = [prologue(0x10), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+        assert!(matches!(compare(code, code2), CompareResult::Differs(_)));
+    }
+
+    #[test]
+    fn register_renaming_follows_policy() {
+        let compare_with = |code1: Vec<u8>, code2: Vec<u8>, equality: EqualityPolicy| {
+            let program1 = program_with_function(code1, 8);
+            let program2 = program_with_function(code2, 8);
+            let options = CompareOptions {
+                equality: equality.ignore_regalloc(true),
+                ..Default::default()
+            };
+            compare_functions(
+                &program1,
+                &program2,
+                &program1.functions["f"],
+                &program2.functions["f"],
+                &options,
+                None,
+            )
+        };
+        // mov eax, 1 / mov ecx, 2
+        let mov_eax_1 = [prologue(0x10), vec![0xB8, 1, 0, 0, 0], LEAVE_RET.to_vec()].concat();
+        let mov_ecx_2 = [prologue(0x10), vec![0xB9, 2, 0, 0, 0], LEAVE_RET.to_vec()].concat();
+
+        assert!(matches!(
+            compare_with(
+                mov_eax_1.clone(),
+                mov_ecx_2.clone(),
+                EqualityPolicy::default()
+            ),
+            CompareResult::SameUnderRenaming(_)
+        ));
+        assert!(matches!(
+            compare_with(
+                mov_eax_1,
+                mov_ecx_2,
+                EqualityPolicy::default().ignore_immediates(false)
+            ),
+            CompareResult::Differs(_)
+        ));
+
+        // Only the stack depth changed
+        let code1 = [prologue(0x10), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+        let code2 = [prologue(0x20), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+        assert!(matches!(
+            compare_with(code1, code2, EqualityPolicy::default()),
+            CompareResult::Differs(_)
+        ));
+    }
+
+    #[test]
+    fn same_after_prologue() {
+        // Only the immediate differs, which isn't compared
+        let mut code1 = [prologue(0x10), vec![0xB8, 1, 0, 0, 0], LEAVE_RET.to_vec()].concat();
+        let code2 = [prologue(0x10), vec![0xB8, 2, 0, 0, 0], LEAVE_RET.to_vec()].concat();
+        assert!(matches!(
+            compare(code1.clone(), code2),
+            CompareResult::Same()
+        ));
+
+        code1.push(0x90);
+        assert!(matches!(
+            compare(code1, prologue(0x10)),
+            CompareResult::Differs(_)
+        ));
+    }
+
+    #[test]
+    fn min_change_drops_small_changes() {
+        let code1 = [prologue(0x10), MOV_EAX_ECX.to_vec(), LEAVE_RET.to_vec()].concat();
+        let code2 = [prologue(0x10), MOV_EAX_EDX.to_vec(), LEAVE_RET.to_vec()].concat();
+        let program1 = program_with_function(code1, 8);
+        let program2 = program_with_function(code2, 8);
+
+        // One of six instructions differs
+        let compare = |min_change| {
+            let options = CompareOptions {
+                min_change,
+                ..Default::default()
+            };
+            let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+            (comparison.changes.len(), comparison.minor_changes)
+        };
+
+        assert_eq!(compare(0.0), (1, 0));
+        assert_eq!(compare(0.1), (1, 0));
+        assert_eq!(compare(0.5), (0, 1));
+    }
+
+    #[test]
+    fn tail_call_change() {
+        // Both branch back to 0x1000 from 0x1002
+        const CALL_RET: &[u8] = &[0xE8, 0xF9, 0xFF, 0xFF, 0xFF, 0xC3];
+        const JMP: &[u8] = &[0xE9, 0xF9, 0xFF, 0xFF, 0xFF];
+
+        let is_tail_call = |code1: Vec<u8>, code2: Vec<u8>| {
+            let program1 = program_with_function(code1, 8);
+            let program2 = program_with_function(code2, 8);
+            let result = compare_pair(
+                (&program1, "f", &program1.functions["f"]),
+                (&program2, "f", &program2.functions["f"]),
+                &CompareOptions::default(),
+                None,
+            );
+            matches!(result, ComparedFunction::Changed(change) if change.tail_call())
+        };
+
+        let call_ret = [MOV_EAX_ECX, CALL_RET].concat();
+        let jmp = [MOV_EAX_ECX, JMP].concat();
+        assert!(is_tail_call(call_ret.clone(), jmp.clone()));
+        assert!(is_tail_call(jmp, call_ret.clone()));
+
+        // Something else changed too
+        assert!(!is_tail_call(call_ret, [MOV_EAX_EDX, JMP].concat()));
+    }
+
+    #[test]
+    fn relocated_bytes_compare_by_symbol() {
+        // call <rel32>; ret, with the displacement patched in by a relocation
+        let with_call = |displacement: u8, symbol: &str| {
+            let mut program = program_with_function(vec![0xE8, displacement, 0, 0, 0, 0xC3], 8);
+            let relocation = Relocation {
+                symbol: symbol.to_string(),
+                size: 4,
+                addend: -4,
+                relative: true,
+            };
+            program.relocations.insert(0x1001, relocation);
+            program
+        };
+        let eq = |program1: &Program, program2: &Program| {
+            let func1 = &program1.functions["f"];
+            let func2 = &program2.functions["f"];
+            relocated_data_eq(
+                (
+                    program1,
+                    func1,
+                    program1.get_data_for_function(func1).unwrap(),
+                ),
+                (
+                    program2,
+                    func2,
+                    program2.get_data_for_function(func2).unwrap(),
+                ),
+            )
+        };
+
+        assert!(eq(&with_call(0x00, "g"), &with_call(0x10, "g")));
+        assert!(!eq(&with_call(0x00, "g"), &with_call(0x00, "h")));
+        assert!(!eq(
+            &with_call(0x00, "g"),
+            &program_with_function(vec![0xE8, 0x10, 0, 0, 0, 0xC3], 8)
+        ));
+    }
+
+    #[test]
+    fn only_compares_listed_functions() {
+        let program1 = program_with_function([prologue(0x10), LEAVE_RET.to_vec()].concat(), 8);
+        let program2 = program_with_function([prologue(0x20), LEAVE_RET.to_vec()].concat(), 8);
+
+        let mut options = CompareOptions {
+            only_functions: Some(IgnoreList::parse("g\n").unwrap()),
+            ..Default::default()
+        };
+        let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+        assert!(comparison.changes.is_empty());
+
+        options.only_functions = Some(IgnoreList::parse("f\n").unwrap());
+        let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+        assert_eq!(comparison.changes.len(), 1);
+
+        let list = IgnoreList::parse("f\ng\n").unwrap();
+        assert_eq!(
+            missing_listed_functions(&program1, &program2, &list),
+            ["g isn't in either program"]
+        );
+    }
+
+    #[test]
+    fn too_large_functions_are_not_diffed() {
+        let program1 = program_with_function([prologue(0x10), LEAVE_RET.to_vec()].concat(), 8);
+        let program2 = program_with_function([prologue(0x20), LEAVE_RET.to_vec()].concat(), 8);
+
+        let options = CompareOptions {
+            max_diff_size: Some(3),
+            ..Default::default()
+        };
+        let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+        let change = &comparison.changes[0];
+        assert!(change.too_large());
+        assert!(change.instructions().0.is_empty());
+        assert_eq!(change.instruction_counts(), (5, 5));
+        assert_eq!(change.similarity(), 0.0);
+    }
+
+    #[test]
+    fn build_stamp_is_masked() {
+        // mov eax, <timestamp>; ret
+        let mut program1 = program_with_function(vec![0xB8, 0x44, 0x33, 0x22, 0x11, 0xC3], 8);
+        let mut program2 = program_with_function(vec![0xB8, 0x88, 0x77, 0x66, 0x55, 0xC3], 8);
+        program1.build_stamp.timestamp = Some(0x11223344);
+        program2.build_stamp.timestamp = Some(0x55667788);
+
+        let mut options = CompareOptions {
+            equality: EqualityPolicy::default().ignore_immediates(false),
+            ..Default::default()
+        };
+        let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+        assert_eq!(comparison.changes.len(), 1);
+
+        options.mask_build_stamp = true;
+        let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+        assert!(comparison.changes.is_empty());
+    }
+
+    #[test]
+    fn warns_about_format_mismatch() {
+        let elf = program_with_function(prologue(0x10), 8);
+        let mut pe = program_with_function(prologue(0x10), 8);
+        assert!(comparison_warnings(&elf, &pe).is_empty());
+
+        pe.format = BinaryFormat::Pe;
+        assert_eq!(comparison_warnings(&elf, &pe).len(), 1);
+    }
+
+    #[test]
+    fn stack_protector_change() {
+        // lea eax, [rdi+rdi*2+1]
+        const LEA: &[u8] = &[0x8D, 0x44, 0x3F, 0x01];
+        // sub rsp, 0x18 and add rsp, 0x18
+        const SUB_RSP: &[u8] = &[0x48, 0x83, 0xEC, 0x18];
+        const ADD_RSP: &[u8] = &[0x48, 0x83, 0xC4, 0x18];
+        // mov rax, fs:[0x28]; mov [rsp+8], rax; xor eax, eax
+        const CANARY_SETUP: &[u8] = &[
+            0x64, 0x48, 0x8B, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00, 0x48, 0x89, 0x44, 0x24, 0x08,
+            0x31, 0xC0,
+        ];
+        // mov rdx, [rsp+8]; sub rdx, fs:[0x28]; jne <call below>
+        const CANARY_CHECK: &[u8] = &[
+            0x48, 0x8B, 0x54, 0x24, 0x08, 0x64, 0x48, 0x2B, 0x14, 0x25, 0x28, 0x00, 0x00, 0x00,
+            0x75, 0x05,
+        ];
+        // call 0x2000, from 0x102D
+        const CALL_FAIL: &[u8] = &[0xE8, 0xCE, 0x0F, 0x00, 0x00];
+        const RET: &[u8] = &[0xC3];
+
+        let is_stack_protector = |code1: &[u8], code2: &[u8]| {
+            let mut program1 = program_with_function(code1.to_vec(), 8);
+            let mut program2 = program_with_function(code2.to_vec(), 8);
+            for program in [&mut program1, &mut program2] {
+                let name = "__stack_chk_fail".to_string();
+                program.symbol_map.insert(0x2000, name);
+            }
+
+            let result = compare_pair(
+                (&program1, "f", &program1.functions["f"]),
+                (&program2, "f", &program2.functions["f"]),
+                &CompareOptions::default(),
+                None,
+            );
+            matches!(result, ComparedFunction::Changed(change) if change.stack_protector())
+        };
+
+        let unprotected = [LEA, RET].concat();
+        let protect = |body: &[u8]| {
+            [
+                SUB_RSP,
+                CANARY_SETUP,
+                body,
+                CANARY_CHECK,
+                ADD_RSP,
+                RET,
+                CALL_FAIL,
+            ]
+            .concat()
+        };
+        assert!(is_stack_protector(&unprotected, &protect(LEA)));
+        assert!(is_stack_protector(&protect(LEA), &unprotected));
+
+        // Something else changed too, nop instead of lea
+        assert!(!is_stack_protector(&unprotected, &protect(&[0x90; 4])));
+    }
+
+    #[test]
+    fn folded_functions_are_matched_by_each_name() {
+        // f and g are separate in the first program, and folded into one in the second
+        let section_idx = SectionIndex(1);
+        let mut program1 =
+            program_with_function([MOV_EAX_ECX, LEAVE_RET, MOV_EAX_ECX, LEAVE_RET].concat(), 8);
+        program1.functions.insert(
+            "f".to_string(),
+            Function::new(section_idx, 0x1000, 0x1000, 4),
+        );
+        program1.functions.insert(
+            "g".to_string(),
+            Function::new(section_idx, 0x1000, 0x1004, 4),
+        );
+        program1.symbol_map.insert(0x1004, "g".to_string());
+        program1.function_index = Program::build_function_index(&program1.functions);
+
+        let mut program2 = program_with_function([MOV_EAX_EDX, LEAVE_RET].concat(), 8);
+        program2.functions.insert(
+            "g".to_string(),
+            Function::new(section_idx, 0x1000, 0x1000, 4),
+        );
+        program2.function_index = Program::build_function_index(&program2.functions);
+        assert_eq!(program2.folded_with("g"), ["f"]);
+
+        let comparison =
+            compare_programs(&program1, &program2, &CompareOptions::default(), None).unwrap();
+        let mut names: Vec<_> = comparison
+            .changes
+            .iter()
+            .map(|change| (change.name(), change.name2()))
+            .collect();
+        names.sort();
+        assert_eq!(names, [("f", "f"), ("g", "g")]);
+        assert!(comparison.only_in_primary.is_empty() && comparison.only_in_secondary.is_empty());
+
+        // Folded the same way on both sides, it's only compared once
+        let options = CompareOptions {
+            keep_identical: true,
+            ..Default::default()
+        };
+        let comparison = compare_programs(&program2, &program2, &options, None).unwrap();
+        assert_eq!(comparison.identical.len(), 1);
+    }
+
+    #[test]
+    fn folded_functions_are_filtered_by_any_name() {
+        // f and g are folded into one in both programs, and changed
+        let section_idx = SectionIndex(1);
+        let folded = |code: Vec<u8>| {
+            let mut program = program_with_function(code, 8);
+            program.functions.insert(
+                "g".to_string(),
+                Function::new(section_idx, 0x1000, 0x1000, 4),
+            );
+            program.function_index = Program::build_function_index(&program.functions);
+            program
+        };
+        let program1 = folded([MOV_EAX_ECX, LEAVE_RET].concat());
+        let program2 = folded([MOV_EAX_EDX, LEAVE_RET].concat());
+
+        // Whichever of the names is matched first, listing either of them finds the change
+        for listed in ["f", "g"] {
+            let options = CompareOptions {
+                only_functions: Some(IgnoreList::parse(listed).unwrap()),
+                ..Default::default()
+            };
+            let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+            assert_eq!(comparison.changes.len(), 1, "only {listed}");
+        }
+
+        // Ignoring one of the names doesn't ignore the function
+        for ignored in ["f", "g"] {
+            let options = CompareOptions {
+                ignore_list: IgnoreList::parse(ignored).unwrap(),
+                ..Default::default()
+            };
+            let comparison = compare_programs(&program1, &program2, &options, None).unwrap();
+            assert_eq!(comparison.changes.len(), 1, "ignoring {ignored}");
+            assert_eq!(comparison.ignored_changes, 0, "ignoring {ignored}");
+        }
+    }
 }
@@ -0,0 +1,570 @@
+//! Side-by-side diffs of changed functions, shared by every way of displaying them.
+
+use crate::compare::FunctionChange;
+use crate::inlining::{find_inlined_calls, InlinedCall};
+use crate::instruction_wrapper::{InstructionIter, InstructionWrapper};
+use crate::program::Program;
+use crate::util::{demangle_symbol, ProgramInstructionFormatter};
+use itertools::Itertools;
+
+const MAX_SAME_BEFORE_COLLAPSE: usize = 15;
+
+/// Options affecting how a change is displayed, but not whether it is reported.
+#[derive(Clone, Default)]
+pub struct DiffViewOptions {
+    /// Align basic blocks before diffing the instructions within them.
+    pub block_diff: bool,
+    /// Show instruction addresses as offsets from the start of their function.
+    pub relative_addresses: bool,
+    /// Changed lines whose disassembly matches any of these are displayed as unchanged.
+    pub ignore_lines: Vec<regex_lite::Regex>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffCell<T> {
+    Hidden,
+    /// A run of this many unchanged lines that aren't shown.
+    Collapsed(usize),
+
+    Default(T),
+    Insert(T),
+    Delete(T),
+}
+
+impl<T> DiffCell<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> DiffCell<U> {
+        match self {
+            DiffCell::Hidden => DiffCell::Hidden,
+            DiffCell::Collapsed(count) => DiffCell::Collapsed(count),
+            DiffCell::Default(item) => DiffCell::Default(f(item)),
+            DiffCell::Insert(item) => DiffCell::Insert(f(item)),
+            DiffCell::Delete(item) => DiffCell::Delete(f(item)),
+        }
+    }
+}
+
+/// How a collapsed run of `count` lines is displayed.
+pub fn collapsed_text(count: usize) -> String {
+    format!("... {count} unchanged lines ...")
+}
+
+/// One row of cells, old on the left and new on the right.
+pub type DiffRow<T> = (DiffCell<T>, DiffCell<T>);
+
+/// Lays out `diff_ops` as side-by-side cells. Inserted or deleted items for which `is_ignored`
+/// returns true are shown as if they were unchanged. Long runs of unchanged items are collapsed
+/// into a single `DiffCell::Collapsed` row, the rows each of them hides are returned in the same
+/// order.
+pub fn build<T>(
+    old: &[T],
+    new: &[T],
+    diff_ops: &[similar::DiffOp],
+    is_ignored: impl Fn(&T) -> bool,
+) -> (Vec<DiffRow<T>>, Vec<Vec<DiffRow<T>>>)
+where
+    T: Clone,
+{
+    let delete = |item: &T| {
+        if is_ignored(item) {
+            DiffCell::Default(item.clone())
+        } else {
+            DiffCell::Delete(item.clone())
+        }
+    };
+    let insert = |item: &T| {
+        if is_ignored(item) {
+            DiffCell::Default(item.clone())
+        } else {
+            DiffCell::Insert(item.clone())
+        }
+    };
+
+    let mut cells: Vec<DiffRow<T>> = vec![];
+    let mut collapsed: Vec<Vec<DiffRow<T>>> = vec![];
+
+    for op in diff_ops {
+        match *op {
+            similar::DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => {
+                let mut old = old[old_index..old_index + len].to_vec();
+                let mut new = new[new_index..new_index + len].to_vec();
+
+                if len > MAX_SAME_BEFORE_COLLAPSE * 2 {
+                    let hidden = MAX_SAME_BEFORE_COLLAPSE..(len - MAX_SAME_BEFORE_COLLAPSE);
+                    collapsed.push(
+                        old.drain(hidden.clone())
+                            .zip(new.drain(hidden))
+                            .map(|(old, new)| (DiffCell::Default(old), DiffCell::Default(new)))
+                            .collect(),
+                    );
+                }
+
+                for (i, pair) in old.iter().zip_longest(new.iter()).enumerate() {
+                    if (i == MAX_SAME_BEFORE_COLLAPSE) && (len > MAX_SAME_BEFORE_COLLAPSE * 2) {
+                        let count = len - MAX_SAME_BEFORE_COLLAPSE * 2;
+                        cells.push((DiffCell::Collapsed(count), DiffCell::Collapsed(count)));
+                    }
+
+                    cells.push(match pair {
+                        itertools::EitherOrBoth::Both(old, new) => (
+                            DiffCell::Default(old.clone()),
+                            DiffCell::Default(new.clone()),
+                        ),
+                        itertools::EitherOrBoth::Left(old) => {
+                            (DiffCell::Default(old.clone()), DiffCell::Hidden)
+                        }
+                        itertools::EitherOrBoth::Right(new) => {
+                            (DiffCell::Hidden, DiffCell::Default(new.clone()))
+                        }
+                    });
+                }
+            }
+            similar::DiffOp::Delete {
+                old_index,
+                old_len,
+                new_index: _,
+            } => {
+                for old_item in &old[old_index..old_index + old_len] {
+                    cells.push((delete(old_item), DiffCell::Hidden));
+                }
+            }
+            similar::DiffOp::Insert {
+                old_index: _,
+                new_index,
+                new_len,
+            } => {
+                for new_item in &new[new_index..new_index + new_len] {
+                    cells.push((DiffCell::Hidden, insert(new_item)));
+                }
+            }
+            similar::DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                for pair in old[old_index..old_index + old_len]
+                    .iter()
+                    .zip_longest(new[new_index..new_index + new_len].iter())
+                {
+                    cells.push(match pair {
+                        itertools::EitherOrBoth::Both(old, new) => (delete(old), insert(new)),
+                        itertools::EitherOrBoth::Left(old) => (delete(old), DiffCell::Hidden),
+                        itertools::EitherOrBoth::Right(new) => (DiffCell::Hidden, insert(new)),
+                    });
+                }
+            }
+        }
+    }
+
+    (cells, collapsed)
+}
+
+/// One row of a side-by-side diff.
+pub type DiffLine = DiffRow<String>;
+
+/// Address of the instruction on each side of a line.
+pub type LineAddresses = (Option<u64>, Option<u64>);
+
+/// Lines of a side-by-side diff, with the instructions on each.
+#[derive(Clone)]
+pub struct DiffLines {
+    pub lines: Vec<DiffLine>,
+    pub addresses: Vec<LineAddresses>,
+}
+
+/// A changed function, diffed and disassembled, ready to be rendered.
+pub struct DiffModel {
+    pub name: String,
+    pub name2: String,
+    /// Demangled `name`, or `name` itself if it isn't mangled.
+    pub demangled_name: String,
+    pub address1: u64,
+    pub address2: u64,
+    pub similarity: f32,
+    /// One row per line of the side-by-side diff, as `address\tinstruction`.
+    pub lines: Vec<DiffLine>,
+    /// The instructions on each line, for looking them up again.
+    pub addresses: Vec<LineAddresses>,
+    /// What each collapsed line hides, in the order they appear in `lines`.
+    pub collapsed: Vec<DiffLines>,
+    /// Function bodies that were inlined into one side, see `find_inlined_calls`.
+    pub inlined: Vec<InlinedCall>,
+    /// The other names of each function, if it was folded together with other functions, see
+    /// `Program::folded_with`.
+    pub folded_with: (Vec<String>, Vec<String>),
+}
+
+impl DiffModel {
+    pub fn build(
+        program1: &'static Program,
+        program2: &'static Program,
+        change: &FunctionChange,
+        options: &DiffViewOptions,
+    ) -> Self {
+        let (DiffLines { lines, addresses }, collapsed) =
+            build_lines(program1, program2, change, options);
+
+        Self {
+            name: change.name().to_string(),
+            name2: change.name2().to_string(),
+            demangled_name: demangle_symbol(change.name())
+                .unwrap_or_else(|| change.name().to_string()),
+            address1: change.address1(),
+            address2: change.address2(),
+            similarity: change.similarity(),
+            lines,
+            addresses,
+            collapsed,
+            inlined: find_inlined_calls(program1, program2, change),
+            folded_with: (
+                to_strings(program1.folded_with(change.name())),
+                to_strings(program2.folded_with(change.name2())),
+            ),
+        }
+    }
+
+    /// Renders the lines as a unified diff, see `to_unified_text`.
+    pub fn to_unified_text(&self) -> String {
+        to_unified_text(&self.lines)
+    }
+}
+
+/// Diffs two blobs of x86 code that aren't part of a program, e.g. copied out of a
+/// disassembler, decoded as `bits`-bit code at `base1` and `base2`. Without symbols, operands
+/// are shown by their addresses, and the names are left empty.
+pub fn diff_raw(
+    bytes1: &[u8],
+    base1: u64,
+    bytes2: &[u8],
+    base2: u64,
+    bits: u32,
+    options: &DiffViewOptions,
+) -> DiffModel {
+    let decode = |bytes: &[u8], base: u64| -> Vec<_> {
+        InstructionIter::new(base, bytes, bytes.len(), bits as usize / 8).collect()
+    };
+    let instructions1 = decode(bytes1, base1);
+    let instructions2 = decode(bytes2, base2);
+
+    let diff_ops =
+        similar::capture_diff_slices(similar::Algorithm::Myers, &instructions1, &instructions2);
+    let similarity = similar::get_diff_ratio(&diff_ops, instructions1.len(), instructions2.len());
+    let (DiffLines { lines, addresses }, collapsed) = lay_out(
+        (&instructions1, &instructions2),
+        (base1, base2),
+        (
+            ProgramInstructionFormatter::without_symbols(),
+            ProgramInstructionFormatter::without_symbols(),
+        ),
+        options,
+    );
+
+    DiffModel {
+        name: String::new(),
+        name2: String::new(),
+        demangled_name: String::new(),
+        address1: base1,
+        address2: base2,
+        similarity,
+        lines,
+        addresses,
+        collapsed,
+        inlined: vec![],
+        folded_with: (vec![], vec![]),
+    }
+}
+
+fn to_strings(names: Vec<&str>) -> Vec<String> {
+    names.into_iter().map(str::to_string).collect()
+}
+
+fn build_lines(
+    program1: &'static Program,
+    program2: &'static Program,
+    change: &FunctionChange,
+    options: &DiffViewOptions,
+) -> (DiffLines, Vec<DiffLines>) {
+    if change.too_large() {
+        let (count1, count2) = change.instruction_counts();
+        let text = format!("too large to diff in detail, {count1} vs {count2} instructions");
+        let lines = DiffLines {
+            lines: vec![(DiffCell::Default(text.clone()), DiffCell::Default(text))],
+            addresses: vec![(None, None)],
+        };
+        return (lines, vec![]);
+    }
+
+    lay_out(
+        change.instructions(),
+        (change.address1(), change.address2()),
+        (
+            ProgramInstructionFormatter::new(program1),
+            ProgramInstructionFormatter::new(program2),
+        ),
+        options,
+    )
+}
+
+/// Diffs and formats the instructions of two functions starting at `function_addresses`.
+fn lay_out(
+    (instructions1, instructions2): (&[InstructionWrapper], &[InstructionWrapper]),
+    (function_address1, function_address2): (u64, u64),
+    (formatter1, formatter2): (ProgramInstructionFormatter, ProgramInstructionFormatter),
+    options: &DiffViewOptions,
+) -> (DiffLines, Vec<DiffLines>) {
+    let diff_ops = if options.block_diff {
+        crate::basic_block::diff(instructions1, instructions2)
+    } else {
+        // NOTE: Lcs panics on oob, wtf?
+        similar::capture_diff_slices(similar::Algorithm::Myers, instructions1, instructions2)
+    };
+
+    // Format everything up front, the ignore filters match on the disassembly
+    let format_all = |mut formatter: ProgramInstructionFormatter,
+                      instructions: &[InstructionWrapper]| {
+        formatter.set_function(instructions);
+
+        instructions
+            .iter()
+            .map(|instr| (instr.get().ip(), formatter.format(instr)))
+            .collect::<Vec<_>>()
+    };
+    let lines1 = format_all(formatter1, instructions1);
+    let lines2 = format_all(formatter2, instructions2);
+
+    let (split_diff, collapsed) = build(&lines1, &lines2, &diff_ops, |(_, text)| {
+        options
+            .ignore_lines
+            .iter()
+            .any(|regex| regex.is_match(text))
+    });
+
+    let cell_address = |cell: &DiffCell<(u64, String)>| match cell {
+        DiffCell::Default((address, _))
+        | DiffCell::Insert((address, _))
+        | DiffCell::Delete((address, _)) => Some(*address),
+        DiffCell::Hidden | DiffCell::Collapsed(_) => None,
+    };
+    let fmt_line = |function_address: u64| {
+        move |(address, text): (u64, String)| {
+            if options.relative_addresses {
+                format!("+{:#06x}\t{text}", address - function_address)
+            } else {
+                format!("{address:08x}\t{text}")
+            }
+        }
+    };
+    let format_rows = |rows: Vec<DiffRow<(u64, String)>>| DiffLines {
+        addresses: rows
+            .iter()
+            .map(|(a, b)| (cell_address(a), cell_address(b)))
+            .collect(),
+        lines: rows
+            .into_iter()
+            .map(|(a, b)| {
+                (
+                    a.map(fmt_line(function_address1)),
+                    b.map(fmt_line(function_address2)),
+                )
+            })
+            .collect(),
+    };
+
+    (
+        format_rows(split_diff),
+        collapsed.into_iter().map(format_rows).collect(),
+    )
+}
+
+/// Renders side-by-side lines as a unified diff, with collapsed regions shown by `collapsed_text`.
+pub fn to_unified_text(lines: &[(DiffCell<String>, DiffCell<String>)]) -> String {
+    let mut text = String::new();
+    // Insertions are held back so a replaced region reads as all of its deletions, then all of
+    // its insertions
+    let mut pending_inserts: Vec<&str> = vec![];
+
+    let flush = |text: &mut String, pending_inserts: &mut Vec<&str>| {
+        for line in pending_inserts.drain(..) {
+            text.push_str(&format!("+{line}\n"));
+        }
+    };
+
+    for (old, new) in lines {
+        match (old, new) {
+            (DiffCell::Collapsed(count), _) | (_, DiffCell::Collapsed(count)) => {
+                flush(&mut text, &mut pending_inserts);
+                text.push_str(&collapsed_text(*count));
+                text.push('\n');
+            }
+            (DiffCell::Default(line), _) | (DiffCell::Hidden, DiffCell::Default(line)) => {
+                flush(&mut text, &mut pending_inserts);
+                text.push_str(&format!(" {line}\n"));
+            }
+            _ => {
+                if let DiffCell::Delete(line) = old {
+                    text.push_str(&format!("-{line}\n"));
+                }
+                if let DiffCell::Insert(line) = new {
+                    pending_inserts.push(line);
+                }
+            }
+        }
+    }
+    flush(&mut text, &mut pending_inserts);
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use similar::DiffOp;
+    use DiffCell::{Collapsed, Default, Delete, Hidden, Insert};
+
+    fn equal(old_index: usize, new_index: usize, len: usize) -> DiffOp {
+        DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        }
+    }
+
+    fn replace(old_index: usize, old_len: usize, new_index: usize, new_len: usize) -> DiffOp {
+        DiffOp::Replace {
+            old_index,
+            old_len,
+            new_index,
+            new_len,
+        }
+    }
+
+    #[test]
+    fn short_equal_run_is_not_collapsed() {
+        // Up to the run that would collapse into 0 hidden lines
+        for len in [
+            MAX_SAME_BEFORE_COLLAPSE * 2 - 1,
+            MAX_SAME_BEFORE_COLLAPSE * 2,
+        ] {
+            let items: Vec<usize> = (0..len).collect();
+            let (cells, collapsed) = build(&items, &items, &[equal(0, 0, items.len())], |_| false);
+
+            let expected: Vec<_> = items.iter().map(|&i| (Default(i), Default(i))).collect();
+            assert_eq!(cells, expected, "{len} lines");
+            assert!(collapsed.is_empty(), "{len} lines");
+        }
+    }
+
+    #[test]
+    fn long_equal_run_is_collapsed() {
+        let items: Vec<usize> = (0..MAX_SAME_BEFORE_COLLAPSE * 2 + 10).collect();
+        let (cells, collapsed) = build(&items, &items, &[equal(0, 0, items.len())], |_| false);
+
+        let row = |i: usize| (Default(i), Default(i));
+        let before = 0..MAX_SAME_BEFORE_COLLAPSE;
+        let hidden = MAX_SAME_BEFORE_COLLAPSE..MAX_SAME_BEFORE_COLLAPSE + 10;
+        let after = MAX_SAME_BEFORE_COLLAPSE + 10..items.len();
+
+        let mut expected: Vec<_> = before.map(row).collect();
+        expected.push((Collapsed(10), Collapsed(10)));
+        expected.extend(after.map(row));
+        assert_eq!(cells, expected);
+        assert_eq!(collapsed, [hidden.map(row).collect::<Vec<_>>()]);
+    }
+
+    #[test]
+    fn collapses_each_long_run() {
+        let old: Vec<usize> = (0..100).collect();
+        let mut new = old.clone();
+        new[50] = 1000;
+        let ops = [equal(0, 0, 50), replace(50, 1, 50, 1), equal(51, 51, 49)];
+        let (cells, collapsed) = build(&old, &new, &ops, |_| false);
+
+        // Each run keeps MAX_SAME_BEFORE_COLLAPSE rows on both ends
+        assert_eq!(cells.len(), MAX_SAME_BEFORE_COLLAPSE * 4 + 3);
+        assert_eq!(
+            cells[MAX_SAME_BEFORE_COLLAPSE * 2 + 1],
+            (Delete(50), Insert(1000))
+        );
+        assert_eq!(collapsed.len(), 2);
+        assert_eq!(collapsed[0].len(), 50 - MAX_SAME_BEFORE_COLLAPSE * 2);
+        assert_eq!(collapsed[1].len(), 49 - MAX_SAME_BEFORE_COLLAPSE * 2);
+        assert_eq!(
+            collapsed[1][0],
+            (
+                Default(51 + MAX_SAME_BEFORE_COLLAPSE),
+                Default(51 + MAX_SAME_BEFORE_COLLAPSE)
+            )
+        );
+    }
+
+    #[test]
+    fn insert_and_delete_leave_the_other_side_hidden() {
+        let ops = [
+            DiffOp::Delete {
+                old_index: 0,
+                old_len: 2,
+                new_index: 0,
+            },
+            DiffOp::Insert {
+                old_index: 2,
+                new_index: 0,
+                new_len: 1,
+            },
+        ];
+        let (cells, _) = build(&[1, 2], &[3], &ops, |_| false);
+
+        assert_eq!(
+            cells,
+            [
+                (Delete(1), Hidden),
+                (Delete(2), Hidden),
+                (Hidden, Insert(3))
+            ]
+        );
+    }
+
+    #[test]
+    fn uneven_replace_pads_the_shorter_side() {
+        let (cells, _) = build(&[1, 2, 3], &[4], &[replace(0, 3, 0, 1)], |_| false);
+        assert_eq!(
+            cells,
+            [
+                (Delete(1), Insert(4)),
+                (Delete(2), Hidden),
+                (Delete(3), Hidden)
+            ]
+        );
+
+        let (cells, _) = build(&[1], &[2, 3], &[replace(0, 1, 0, 2)], |_| false);
+        assert_eq!(cells, [(Delete(1), Insert(2)), (Hidden, Insert(3))]);
+    }
+
+    #[test]
+    fn ignored_items_are_shown_unchanged() {
+        let (cells, _) = build(&[1, 2], &[3, 4], &[replace(0, 2, 0, 2)], |item| *item == 2);
+        assert_eq!(cells, [(Delete(1), Insert(3)), (Default(2), Insert(4))]);
+    }
+
+    #[test]
+    fn diffs_raw_code() {
+        // push rbp; mov eax, ecx; ret / push rbp; mov eax, edx; ret
+        let model = diff_raw(
+            &[0x55, 0x89, 0xC8, 0xC3],
+            0x1000,
+            &[0x55, 0x89, 0xD0, 0xC3],
+            0x2000,
+            64,
+            &DiffViewOptions::default(),
+        );
+
+        assert_eq!(
+            model.to_unified_text(),
+            " 00001000\tpush rbp\n-00001001\tmov eax,ecx\n+00002001\tmov eax,edx\n 00001003\tret\n"
+        );
+        assert_eq!(model.addresses[1], (Some(0x1001), Some(0x2001)));
+    }
+}
@@ -2,7 +2,9 @@ use anyhow::Context;
 use byteorder::ByteOrder;
 use byteorder::ReadBytesExt;
 use num_enum::TryFromPrimitive;
+use object::{Object, ObjectSection};
 use rustc_hash::FxHashMap;
+use std::fmt;
 use std::io;
 use std::io::Cursor;
 use std::io::ErrorKind;
@@ -37,6 +39,8 @@ pub enum EhPointerFormat {
 #[derive(Debug, TryFromPrimitive, Clone, Copy)]
 #[repr(u8)]
 pub enum EhPointerApplication {
+    // Value is used as is.
+    DW_EH_PE_absptr = 0x00,
     // Value is relative to the current program counter.
     DW_EH_PE_pcrel = 0x10,
     // Value is relative to the beginning of the .text section.
@@ -49,21 +53,132 @@ pub enum EhPointerApplication {
     DW_EH_PE_aligned = 0x50,
 }
 
+/// Things in `.eh_frame` that can't be parsed yet, or reasons the whole section can't be.
+#[derive(Debug)]
+pub enum EhFrameError {
+    /// Reading the section failed.
+    Io(io::Error),
+    /// The section has more entries than fit in it, so their lengths must be wrong.
+    TooManyEntries {
+        section_size: u64,
+    },
+    UnhandledPointerFormat(EhPointerFormat),
+    UnhandledPointerApplication(EhPointerApplication),
+    /// Pointers of this many bytes.
+    UnhandledPointerSize(usize),
+    UnhandledAugmentation(char),
+    UnhandledCieVersion(u8),
+}
+
+impl fmt::Display for EhFrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EhFrameError::Io(error) => write!(f, "{error}"),
+            EhFrameError::TooManyEntries { section_size } => {
+                write!(f, "more entries than fit in {section_size} bytes")
+            }
+            EhFrameError::UnhandledPointerFormat(format) => {
+                write!(f, "unhandled pointer format {format:?}")
+            }
+            EhFrameError::UnhandledPointerApplication(application) => {
+                write!(f, "unhandled pointer application {application:?}")
+            }
+            EhFrameError::UnhandledPointerSize(size) => {
+                write!(f, "unhandled pointer size {size}")
+            }
+            EhFrameError::UnhandledAugmentation(augmentation) => {
+                write!(f, "unhandled augmentation {augmentation:?}")
+            }
+            EhFrameError::UnhandledCieVersion(version) => {
+                write!(f, "unhandled CIE version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EhFrameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EhFrameError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EhFrameError {
+    fn from(error: io::Error) -> Self {
+        EhFrameError::Io(error)
+    }
+}
+
+/// Addresses that encoded pointers can be relative to, see `EhPointerApplication`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct EhFrameBases {
+    /// Address of the `.eh_frame` section, for `DW_EH_PE_pcrel`.
+    pub eh_frame: u64,
+    /// Address of `.text`, for `DW_EH_PE_textrel`.
+    pub text: u64,
+    /// Address of the GOT, for `DW_EH_PE_datarel`.
+    pub data: u64,
+}
+
+impl EhFrameBases {
+    /// The bases of `object`, whose `.eh_frame` is at `eh_frame_address`.
+    pub fn new(object: &object::File<'_>, eh_frame_address: u64) -> Self {
+        // In .eh_frame, datarel is relative to the GOT pointer, which points at .got.plt if
+        // there is one
+        let data = object
+            .section_by_name(".got.plt")
+            .or_else(|| object.section_by_name(".got"))
+            .map_or(0, |section| section.address());
+        let text = object
+            .section_by_name(".text")
+            .map_or(0, |section| section.address());
+
+        Self {
+            eh_frame: eh_frame_address,
+            text,
+            data,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Cie {
+    pub version: u8,
+    /// Size of the target's addresses, which the CIE says itself from version 4 on.
+    pub address_size: usize,
     pub fde_pointer_format: Option<EhPointerFormat>,
     pub fde_pointer_application: Option<EhPointerApplication>,
+    /// Encoding of the FDEs' LSDA pointers, if they have one.
+    pub lsda_pointer_format: Option<EhPointerFormat>,
+    pub lsda_pointer_application: Option<EhPointerApplication>,
+    /// Address of the personality routine, or of the pointer to it if its encoding is
+    /// `DW_EH_PE_indirect`, which it usually is.
+    pub personality: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct Fde {
     pub begin: u64,
     pub length: u64,
+    /// Address of the function's language-specific data area, e.g. its exception tables.
+    pub lsda: Option<u64>,
 }
 
 pub enum EhFrameEntry {
     Cie(u64, Cie),
     Fde(Fde),
+    /// An entry at this offset that couldn't be parsed, and was skipped.
+    Malformed(u64),
+}
+
+/// The FDEs in `.eh_frame`, see `get_fdes`.
+#[derive(Debug, Default)]
+pub struct EhFrame {
+    pub fdes: Vec<Fde>,
+    /// Offsets of the entries that couldn't be parsed.
+    pub skipped: Vec<u64>,
 }
 
 fn read_encoded_no_application<Endian: ByteOrder, R: Read + Seek>(
@@ -73,13 +188,19 @@ fn read_encoded_no_application<Endian: ByteOrder, R: Read + Seek>(
 ) -> anyhow::Result<u64> {
     Ok(match format {
         EhPointerFormat::DW_EH_PE_absptr => match pointer_size {
+            2 => data.read_u16::<Endian>()?.into(),
             4 => data.read_u32::<Endian>()?.into(),
             8 => data.read_u64::<Endian>()?,
-            _ => todo!("unhandled pointer size: {}", pointer_size),
+            _ => return Err(EhFrameError::UnhandledPointerSize(pointer_size).into()),
         },
+        EhPointerFormat::DW_EH_PE_uleb128 => leb128::read::unsigned(data)?,
+        EhPointerFormat::DW_EH_PE_sleb128 => leb128::read::signed(data)? as u64,
+        EhPointerFormat::DW_EH_PE_udata2 => data.read_u16::<Endian>()?.into(),
+        EhPointerFormat::DW_EH_PE_udata4 => data.read_u32::<Endian>()?.into(),
+        EhPointerFormat::DW_EH_PE_udata8 => data.read_u64::<Endian>()?,
         EhPointerFormat::DW_EH_PE_sdata4 => data.read_i32::<Endian>()? as u64,
 
-        _ => todo!("unhandled format {:?}", format),
+        _ => return Err(EhFrameError::UnhandledPointerFormat(format).into()),
     })
 }
 
@@ -88,31 +209,41 @@ fn read_encoded<Endian: ByteOrder, R: Read + Seek>(
     format: EhPointerFormat,
     application: EhPointerApplication,
     pointer_size: usize,
-    base_address: u64,
+    bases: &EhFrameBases,
+    function_address: u64,
 ) -> anyhow::Result<u64> {
     let pcrel_offs = data.stream_position()?;
     let unapplied_value = read_encoded_no_application::<Endian, _>(data, format, pointer_size)?;
     let applied_value: u64 = match application {
-        EhPointerApplication::DW_EH_PE_pcrel => base_address
+        EhPointerApplication::DW_EH_PE_absptr => unapplied_value,
+        EhPointerApplication::DW_EH_PE_pcrel => bases
+            .eh_frame
             .wrapping_add(pcrel_offs)
             .wrapping_add(unapplied_value),
-        _ => todo!("unhandled application {:?}", application),
+        EhPointerApplication::DW_EH_PE_textrel => bases.text.wrapping_add(unapplied_value),
+        EhPointerApplication::DW_EH_PE_datarel => bases.data.wrapping_add(unapplied_value),
+        EhPointerApplication::DW_EH_PE_funcrel => function_address.wrapping_add(unapplied_value),
+        _ => return Err(EhFrameError::UnhandledPointerApplication(application).into()),
     };
 
     // truncate addresses larger than address_size bytes
-    let max_address = 2u128.pow((pointer_size * 8) as u32) - 1;
-    Ok(applied_value & (max_address as u64))
+    let max_address = u64::MAX >> (64 - pointer_size * 8);
+    Ok(applied_value & max_address)
 }
 
 impl Cie {
     fn parse<Endian: ByteOrder, R: Read + Seek>(
         data: &mut R,
         pointer_size: usize,
+        bases: &EhFrameBases,
     ) -> anyhow::Result<Self> {
         // Version
-        // Version assigned to the call frame information structure. This value shall be 1.
+        // Version assigned to the call frame information structure. This value shall be 1 in
+        // .eh_frame, but some compilers emit 3, and .debug_frame uses 4.
         let version = data.read_u8()?;
-        assert_eq!(version, 1, "version mismatch: {version} != 1");
+        if !matches!(version, 1 | 3 | 4) {
+            return Err(EhFrameError::UnhandledCieVersion(version).into());
+        }
 
         // Augmentation String
         // This value is a NUL terminated string that identifies the augmentation to the CIE or to the
@@ -128,15 +259,27 @@ impl Cie {
             augmentation_string.push(augmentation.into());
         }
 
+        // Address Size, Segment Size
+        // Only present from version 4 on. The size of a target address and of a segment
+        // selector in this CIE and any FDEs that use it, in bytes.
+        let mut address_size = pointer_size;
+        if version >= 4 {
+            address_size = data.read_u8()?.into();
+            let _segment_size = data.read_u8()?;
+            if !matches!(address_size, 4 | 8) {
+                return Err(EhFrameError::UnhandledPointerSize(address_size).into());
+            }
+        }
+
         // EH Data
         // On 32 bit architectures, this is a 4 byte value that... On 64 bit architectures, this is a
         // 8 byte value that... This field is only present if the Augmentation String contains the
         // string "eh".
         let mut _eh: Option<u64> = None;
         if augmentation_string.contains("eh") {
-            _eh = Some(match pointer_size {
+            _eh = Some(match address_size {
                 4 => data.read_u32::<Endian>()?.into(),
-                _ => todo!("Unhandled pointer size: {}", pointer_size),
+                _ => return Err(EhFrameError::UnhandledPointerSize(address_size).into()),
             });
         }
 
@@ -156,13 +299,12 @@ impl Cie {
         // An unsigned byte constant that indicates which column in the rule table represents the
         // return address of the function. Note that this column might not correspond to an actual
         // machine register.
-        // NOTE: This field was a pain to figure out, as it doesn't seem to be properly documented.
-        // It may be incorrect
-        let _return_address_register = data.read_u8()?;
-        // let _return_address_register = leb128::read::unsigned(data);
-        // Check LEB continuation bit, if this isn't zero, see if uncommenting the above line fixes
-        // parsing issues
-        assert_eq!(_return_address_register & (1 << 7), 0);
+        // From version 3 on, this is an unsigned LEB128 instead of a byte.
+        let _return_address_register: u64 = if version == 1 {
+            data.read_u8()?.into()
+        } else {
+            leb128::read::unsigned(data)?
+        };
 
         // Augmentation Length
         // An unsigned LEB128 encoded value indicating the length in bytes of the Augmentation Data. This
@@ -178,16 +320,34 @@ impl Cie {
         // described below. This field is only present if the Augmentation String contains the character
         // 'z'.
         let mut augmentation_data: Option<Vec<u8>> = None;
+        // Pointers in the augmentation data are read from a copy of it, pcrel ones need to know
+        // where it really is
+        let augmentation_data_bases = EhFrameBases {
+            eh_frame: bases.eh_frame.wrapping_add(data.stream_position()?),
+            ..*bases
+        };
         if let Some(augmentation_data_length) = augmentation_data_length {
-            let mut buf = vec![0u8; augmentation_data_length.try_into().unwrap()];
-
-            data.read_exact(&mut buf)?;
+            // Read through take() rather than into a buffer of that size, the length could be
+            // anything
+            let mut buf = vec![];
+            data.by_ref()
+                .take(augmentation_data_length)
+                .read_to_end(&mut buf)?;
+            if buf.len() as u64 != augmentation_data_length {
+                anyhow::bail!(
+                    "augmentation data is {augmentation_data_length} bytes, but only {} are left",
+                    buf.len()
+                );
+            }
 
             augmentation_data = Some(buf)
         }
 
         let mut fde_pointer_format: Option<EhPointerFormat> = None;
         let mut fde_pointer_application: Option<EhPointerApplication> = None;
+        let mut lsda_pointer_format: Option<EhPointerFormat> = None;
+        let mut lsda_pointer_application: Option<EhPointerApplication> = None;
+        let mut personality: Option<u64> = None;
         if let Some(augmentation_data) = augmentation_data {
             let mut augmentation_data = Cursor::new(&augmentation_data);
 
@@ -203,12 +363,9 @@ impl Cie {
                     // If the Augmentation string has the value "eh", then the EH Data field shall
                     // be present.
                     'e' => {
-                        let next_char = augmentation_string_iter.next();
-                        assert_eq!(
-                            Some('h'),
-                            next_char,
-                            "saw '{next_char:?}' in augmentation, expected 'h' ('eh')"
-                        );
+                        if augmentation_string_iter.next() != Some('h') {
+                            return Err(EhFrameError::UnhandledAugmentation('e').into());
+                        }
                     }
 
                     // A 'L' may be present at any position after the first character of the
@@ -221,7 +378,14 @@ impl Cie {
                     // language-specific data area (LSDA). The size of the LSDA pointer is
                     // specified by the pointer encoding used.
                     'L' => {
-                        let _pointer_format = augmentation_data.read_u8()?;
+                        let b = augmentation_data.read_u8()?;
+                        // DW_EH_PE_omit, the FDEs have no LSDA
+                        if b != 0xff {
+                            lsda_pointer_format = Some(EhPointerFormat::try_from(b & 0x0F)?);
+                            // Leaves out DW_EH_PE_indirect, the pointer is still to the LSDA
+                            lsda_pointer_application =
+                                Some(EhPointerApplication::try_from(b & 0x70)?);
+                        }
                     }
 
                     // A 'P' may be present at any position after the first character of the string. This character may
@@ -236,12 +400,17 @@ impl Cie {
                     'P' => {
                         let b = augmentation_data.read_u8()?;
                         let pointer_format = EhPointerFormat::try_from(b & 0x0F)?;
+                        // Leaves out DW_EH_PE_indirect, the pointer isn't followed
+                        let pointer_application = EhPointerApplication::try_from(b & 0x70)?;
 
-                        let _personality_routine = read_encoded_no_application::<Endian, _>(
+                        personality = Some(read_encoded::<Endian, _>(
                             &mut augmentation_data,
                             pointer_format,
-                            pointer_size,
-                        );
+                            pointer_application,
+                            address_size,
+                            &augmentation_data_bases,
+                            0,
+                        )?);
                     }
 
                     // A 'R' may be present at any position after the first character of the
@@ -255,14 +424,23 @@ impl Cie {
                         fde_pointer_application = Some(EhPointerApplication::try_from(b & 0xF0)?);
                     }
 
-                    _ => todo!("unhandled augmentation: {}", augmentation),
+                    _ => return Err(EhFrameError::UnhandledAugmentation(augmentation).into()),
                 }
             }
         }
 
+        log::trace!(
+            "CIE version {version}, augmentation {augmentation_string:?}, FDE pointer format {fde_pointer_format:?}, application {fde_pointer_application:?}, personality {personality:X?}"
+        );
+
         Ok(Self {
+            version,
+            address_size,
             fde_pointer_format,
             fde_pointer_application,
+            lsda_pointer_format,
+            lsda_pointer_application,
+            personality,
         })
     }
 }
@@ -272,13 +450,15 @@ impl Fde {
         data: &mut R,
         cie_pointer: u32,
         cies: &FxHashMap<u64, Cie>,
-        pointer_size: usize,
-        base_address: u64,
+        bases: &EhFrameBases,
     ) -> anyhow::Result<Self> {
         let offs = data.stream_position()?;
 
         // - 4 because the stream is currently *after* the CIE id, we want directly before
-        let absolute_cie_pointer = offs - u64::from(cie_pointer) - 4;
+        let absolute_cie_pointer = offs
+            .checked_sub(u64::from(cie_pointer))
+            .and_then(|offs| offs.checked_sub(4))
+            .with_context(|| format!("CIE pointer {cie_pointer:08X} is before the section"))?;
         let cie = cies
             .get(&absolute_cie_pointer)
             .with_context(|| format!("no such CIE @ {:08X}", absolute_cie_pointer))?;
@@ -300,8 +480,10 @@ impl Fde {
                     absolute_cie_pointer,
                 )
             })?,
-            pointer_size,
-            base_address,
+            cie.address_size,
+            bases,
+            // The function starts here, so there's nothing for it to be relative to yet
+            0,
         )?;
 
         // PC Range
@@ -316,12 +498,37 @@ impl Fde {
                     absolute_cie_pointer,
                 )
             })?,
-            pointer_size,
+            cie.address_size,
         )?;
 
+        // Augmentation Data Length, Augmentation Data
+        // Only present if the CIE has a 'z' augmentation, which it has if it has an 'L'. The LSDA
+        // pointer is the only thing in it.
+        let mut lsda = None;
+        if let (Some(format), Some(application)) =
+            (cie.lsda_pointer_format, cie.lsda_pointer_application)
+        {
+            let _augmentation_data_length = leb128::read::unsigned(data)?;
+
+            // A pointer of 0 means no LSDA, no matter how it's applied
+            let lsda_offs = data.stream_position()?;
+            if read_encoded_no_application::<Endian, _>(data, format, cie.address_size)? != 0 {
+                data.seek(io::SeekFrom::Start(lsda_offs))?;
+                lsda = Some(read_encoded::<Endian, _>(
+                    data,
+                    format,
+                    application,
+                    cie.address_size,
+                    bases,
+                    pc_begin,
+                )?);
+            }
+        }
+
         Ok(Self {
             begin: pc_begin,
             length: pc_range,
+            lsda,
         })
     }
 }
@@ -330,8 +537,8 @@ fn parse_eh_frame_entry<Endian: ByteOrder, R: Read + Seek>(
     data: &mut R,
     pointer_size: usize,
     cies: &FxHashMap<u64, Cie>,
-    base_address: u64,
-) -> anyhow::Result<Option<EhFrameEntry>> {
+    bases: &EhFrameBases,
+) -> io::Result<Option<EhFrameEntry>> {
     let entry_offset = data.stream_position()?;
 
     // Length
@@ -342,6 +549,7 @@ fn parse_eh_frame_entry<Endian: ByteOrder, R: Read + Seek>(
         // Some compilers don't put a terminator CIE in the section, and so we get an EOF.
         Err(e) => {
             if e.kind() == ErrorKind::UnexpectedEof {
+                log::trace!("no terminator, stopping at EOF @ {:08X}", entry_offset);
                 return Ok(None);
             } else {
                 Err(e)
@@ -361,6 +569,7 @@ fn parse_eh_frame_entry<Endian: ByteOrder, R: Read + Seek>(
     // If Length contains the value 0, then this CIE shall be considered a terminator and
     // processing shall end.
     if length == 0 {
+        log::trace!("terminator @ {:08X}", entry_offset);
         return Ok(None);
     }
 
@@ -368,57 +577,645 @@ fn parse_eh_frame_entry<Endian: ByteOrder, R: Read + Seek>(
     // CIE length
     let start_pos = data.stream_position()?;
 
+    let entry =
+        parse_entry_body::<Endian, _>(data, entry_offset, length, pointer_size, cies, bases)
+            .unwrap_or_else(|error| {
+                log::warn!("skipping malformed entry @ {entry_offset:08X}: {error:#}");
+                EhFrameEntry::Malformed(entry_offset)
+            });
+
+    // Skip over unread padding, or whatever's left of a malformed entry
+    data.seek(io::SeekFrom::Start(start_pos.saturating_add(length)))?;
+
+    Ok(Some(entry))
+}
+
+/// Parses what comes after the length of the entry at `entry_offset`.
+fn parse_entry_body<Endian: ByteOrder, R: Read + Seek>(
+    data: &mut R,
+    entry_offset: u64,
+    length: u64,
+    pointer_size: usize,
+    cies: &FxHashMap<u64, Cie>,
+    bases: &EhFrameBases,
+) -> anyhow::Result<EhFrameEntry> {
+    let start_pos = data.stream_position()?;
+
     // CIE ID
     // A 4 byte unsigned value that is used to distinguish CIE records from FDE records.
     let cie_id = data.read_u32::<Endian>()?;
 
     let entry = match cie_id {
         // For CIEs, This value shall always be 0, which indicates this record is a CIE.
-        0 => EhFrameEntry::Cie(entry_offset, Cie::parse::<Endian, _>(data, pointer_size)?),
+        0 => {
+            log::trace!("CIE @ {:08X}, length {:08X}", entry_offset, length);
+            EhFrameEntry::Cie(
+                entry_offset,
+                Cie::parse::<Endian, _>(data, pointer_size, bases)?,
+            )
+        }
         // For FDEs, A 4 byte unsigned value that when subtracted from the offset of the CIE
         // Pointer in the current FDE yields the offset of the start of the associated CIE. This value
         // shall never be 0.
-        _ => EhFrameEntry::Fde(Fde::parse::<Endian, _>(
-            data,
-            cie_id,
-            cies,
-            pointer_size,
-            base_address,
-        )?),
+        _ => {
+            let fde = Fde::parse::<Endian, _>(data, cie_id, cies, bases)?;
+            log::trace!(
+                "FDE @ {:08X}: begin {:08X}, length {:08X}",
+                entry_offset,
+                fde.begin,
+                fde.length
+            );
+            EhFrameEntry::Fde(fde)
+        }
     };
 
     let n_bytes_read = data.stream_position()? - start_pos;
-    assert!(
-        n_bytes_read <= length,
-        "number of bytes read overflowed CIE length: {n_bytes_read} > {length}"
-    );
-
-    // Skip over unread padding
-    data.seek(io::SeekFrom::Current(
-        (length - n_bytes_read).try_into().unwrap(),
-    ))?;
+    if n_bytes_read > length {
+        anyhow::bail!("number of bytes read overflowed CIE length: {n_bytes_read} > {length}");
+    }
 
-    Ok(Some(entry))
+    Ok(entry)
 }
 
+/// Parses the FDEs in the `.eh_frame` section `data`. Entries that can't be parsed are skipped,
+/// so one bad entry doesn't lose every function after it.
 pub fn get_fdes<Endian: ByteOrder, R: Read + Seek>(
     data: &mut R,
     pointer_size: usize,
-    base_address: u64,
-) -> anyhow::Result<Vec<Fde>> {
-    let mut fdes: Vec<Fde> = vec![];
+    bases: &EhFrameBases,
+) -> Result<EhFrame, EhFrameError> {
+    let mut eh_frame = EhFrame::default();
     let mut cies: FxHashMap<u64, Cie> = FxHashMap::default();
 
-    while let Some(entry) =
-        parse_eh_frame_entry::<Endian, _>(data, pointer_size, &cies, base_address)?
-    {
+    // Every entry takes at least 8 bytes (length and CIE id), so there can't be more than this.
+    // Stops crafted lengths from keeping the parser going forever.
+    let start = data.stream_position()?;
+    let end = data.seek(io::SeekFrom::End(0))?;
+    data.seek(io::SeekFrom::Start(start))?;
+    let max_entries = end.saturating_sub(start) / 8;
+
+    let mut entry_count = 0;
+    while let Some(entry) = parse_eh_frame_entry::<Endian, _>(data, pointer_size, &cies, bases)? {
+        entry_count += 1;
+        if entry_count > max_entries {
+            return Err(EhFrameError::TooManyEntries {
+                section_size: end - start,
+            });
+        }
+
         match entry {
             EhFrameEntry::Cie(offset, cie) => {
                 cies.insert(offset, cie);
             }
-            EhFrameEntry::Fde(fde) => fdes.push(fde),
+            EhFrameEntry::Fde(fde) => eh_frame.fdes.push(fde),
+            EhFrameEntry::Malformed(offset) => eh_frame.skipped.push(offset),
         }
     }
 
-    Ok(fdes)
+    Ok(eh_frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    /// Builds a little-endian `.eh_frame` section for a 32-bit target, with FDE addresses
+    /// encoded as `DW_EH_PE_pcrel | DW_EH_PE_sdata4` unless the CIE says otherwise.
+    struct EhFrameBuilder {
+        bases: EhFrameBases,
+        data: Vec<u8>,
+    }
+
+    impl EhFrameBuilder {
+        fn new(base_address: u64) -> Self {
+            Self {
+                bases: EhFrameBases {
+                    eh_frame: base_address,
+                    ..Default::default()
+                },
+                data: vec![],
+            }
+        }
+
+        fn entry(&mut self, body: &[u8]) {
+            self.data
+                .write_u32::<LittleEndian>(body.len() as u32)
+                .unwrap();
+            self.data.extend_from_slice(body);
+        }
+
+        /// Appends a CIE with a "zR" augmentation, returning its offset.
+        fn cie(&mut self) -> u64 {
+            self.cie_with_encoding(0x1b)
+        }
+
+        /// Appends a CIE whose FDEs encode their addresses with `encoding`, which has to be a
+        /// 4-byte format.
+        fn cie_with_encoding(&mut self, encoding: u8) -> u64 {
+            let offset = self.data.len() as u64;
+            self.entry(&[
+                0, 0, 0, 0, // CIE id
+                1, // version
+                b'z', b'R', 0,        // augmentation string
+                1,        // code alignment factor
+                0x7c,     // data alignment factor (-4)
+                8,        // return address register
+                1,        // augmentation data length
+                encoding, // FDE pointer encoding
+                0, 0, // padding (DW_CFA_nop)
+            ]);
+
+            offset
+        }
+
+        /// Appends an FDE for a CIE made by `cie`.
+        fn fde(&mut self, cie_offset: u64, begin: u64, length: u32) {
+            let pc_begin_offset = self.data.len() as u64 + 8;
+            let value = begin.wrapping_sub(self.bases.eh_frame + pc_begin_offset) as u32;
+
+            self.fde_with_value(cie_offset, value, length);
+        }
+
+        /// Appends an FDE with `value` as its encoded address.
+        fn fde_with_value(&mut self, cie_offset: u64, value: u32, length: u32) {
+            let cie_pointer_offset = self.data.len() as u64 + 4;
+
+            let mut body = vec![];
+            body.write_u32::<LittleEndian>((cie_pointer_offset - cie_offset) as u32)
+                .unwrap();
+            body.write_u32::<LittleEndian>(value).unwrap();
+            body.write_u32::<LittleEndian>(length).unwrap();
+            body.extend_from_slice(&[0, 0, 0, 0]); // augmentation data length + padding
+
+            self.entry(&body);
+        }
+
+        fn terminator(&mut self) {
+            self.data.write_u32::<LittleEndian>(0).unwrap();
+        }
+
+        fn parse(&self) -> Vec<(u64, u64)> {
+            get_fdes::<LittleEndian, _>(&mut Cursor::new(&self.data), 4, &self.bases)
+                .unwrap()
+                .fdes
+                .iter()
+                .map(|fde| (fde.begin, fde.length))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn parses_fdes() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        let cie = builder.cie();
+        builder.fde(cie, 0x1000, 0x20);
+        builder.fde(cie, 0x1020, 0x134);
+        builder.terminator();
+
+        assert_eq!(builder.parse(), vec![(0x1000, 0x20), (0x1020, 0x134)]);
+    }
+
+    #[test]
+    fn parses_fdes_after_eh_frame() {
+        // pcrel addresses pointing forward, past the end of .eh_frame
+        let mut builder = EhFrameBuilder::new(0x1000);
+        let cie = builder.cie();
+        builder.fde(cie, 0x8000, 0x10);
+        builder.terminator();
+
+        assert_eq!(builder.parse(), vec![(0x8000, 0x10)]);
+    }
+
+    #[test]
+    fn parses_multiple_cies() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        let cie1 = builder.cie();
+        builder.fde(cie1, 0x1000, 0x20);
+        let cie2 = builder.cie();
+        builder.fde(cie2, 0x1020, 0x8);
+        builder.fde(cie1, 0x1030, 0x4);
+        builder.terminator();
+
+        assert_eq!(
+            builder.parse(),
+            vec![(0x1000, 0x20), (0x1020, 0x8), (0x1030, 0x4)]
+        );
+    }
+
+    #[test]
+    fn missing_terminator_ends_at_eof() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        let cie = builder.cie();
+        builder.fde(cie, 0x1000, 0x20);
+        builder.fde(cie, 0x1020, 0x10);
+
+        assert_eq!(builder.parse(), vec![(0x1000, 0x20), (0x1020, 0x10)]);
+    }
+
+    #[test]
+    fn terminator_stops_parsing() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        let cie = builder.cie();
+        builder.fde(cie, 0x1000, 0x20);
+        builder.terminator();
+        builder.fde(cie, 0x1020, 0x10);
+
+        assert_eq!(builder.parse(), vec![(0x1000, 0x20)]);
+    }
+
+    #[test]
+    fn empty_section() {
+        let builder = EhFrameBuilder::new(0x2000);
+
+        assert_eq!(builder.parse(), vec![]);
+    }
+
+    #[test]
+    fn applies_datarel() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        builder.bases.data = 0x5000;
+        // datarel | sdata4
+        let cie = builder.cie_with_encoding(0x3b);
+        builder.fde_with_value(cie, 0x100, 0x20);
+        builder.fde_with_value(cie, -0x10i32 as u32, 0x8);
+        builder.terminator();
+
+        assert_eq!(builder.parse(), vec![(0x5100, 0x20), (0x4ff0, 0x8)]);
+    }
+
+    #[test]
+    fn applies_textrel() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        builder.bases.text = 0x40_0000;
+        // textrel | udata4
+        let cie = builder.cie_with_encoding(0x23);
+        builder.fde_with_value(cie, 0x1230, 0x20);
+        builder.terminator();
+
+        assert_eq!(builder.parse(), vec![(0x40_1230, 0x20)]);
+    }
+
+    #[test]
+    fn applies_funcrel() {
+        let data = 0x10u32.to_le_bytes();
+        let value = read_encoded::<LittleEndian, _>(
+            &mut Cursor::new(&data),
+            EhPointerFormat::DW_EH_PE_udata4,
+            EhPointerApplication::DW_EH_PE_funcrel,
+            4,
+            &EhFrameBases::default(),
+            0x1000,
+        )
+        .unwrap();
+
+        assert_eq!(value, 0x1010);
+    }
+
+    #[test]
+    fn reads_lsda_pointers() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        let cie = builder.data.len() as u64;
+        builder.entry(&[
+            0, 0, 0, 0, // CIE id
+            1, // version
+            b'z', b'L', b'R', 0,    // augmentation string
+            1,    // code alignment factor
+            0x7c, // data alignment factor (-4)
+            8,    // return address register
+            2,    // augmentation data length
+            0x1b, // LSDA pointer encoding: pcrel | sdata4
+            0x1b, // FDE pointer encoding: pcrel | sdata4
+            0, 0, // padding (DW_CFA_nop)
+        ]);
+
+        let mut fde = |lsda: u64| {
+            let cie_pointer_offset = builder.data.len() as u64 + 4;
+            let pc_begin_offset = cie_pointer_offset + 4;
+            let lsda_offset = cie_pointer_offset + 13;
+            let pcrel =
+                |address: u64, offset| address.wrapping_sub(builder.bases.eh_frame + offset) as u32;
+            let lsda = if lsda == 0 {
+                0
+            } else {
+                pcrel(lsda, lsda_offset)
+            };
+
+            let mut body = vec![];
+            body.write_u32::<LittleEndian>((cie_pointer_offset - cie) as u32)
+                .unwrap();
+            body.write_u32::<LittleEndian>(pcrel(0x1000, pc_begin_offset))
+                .unwrap();
+            body.write_u32::<LittleEndian>(0x20).unwrap();
+            body.push(4); // augmentation data length
+            body.write_u32::<LittleEndian>(lsda).unwrap();
+            body.extend_from_slice(&[0, 0, 0]); // padding
+            builder.entry(&body);
+        };
+        fde(0x3000);
+        fde(0);
+        builder.terminator();
+
+        let eh_frame =
+            get_fdes::<LittleEndian, _>(&mut Cursor::new(&builder.data), 4, &builder.bases)
+                .unwrap();
+        let lsdas: Vec<_> = eh_frame.fdes.iter().map(|fde| fde.lsda).collect();
+        assert_eq!(lsdas, vec![Some(0x3000), None]);
+    }
+
+    #[test]
+    fn reads_absolute_lsda_pointers() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        let cie = builder.data.len() as u64;
+        builder.entry(&[
+            0, 0, 0, 0, // CIE id
+            1, // version
+            b'z', b'L', b'R', 0,    // augmentation string
+            1,    // code alignment factor
+            0x7c, // data alignment factor (-4)
+            8,    // return address register
+            2,    // augmentation data length
+            0x00, // LSDA pointer encoding: absptr
+            0x1b, // FDE pointer encoding: pcrel | sdata4
+            0, 0, // padding (DW_CFA_nop)
+        ]);
+
+        let cie_pointer_offset = builder.data.len() as u64 + 4;
+        let pc_begin = 0x1000u64.wrapping_sub(builder.bases.eh_frame + cie_pointer_offset + 4);
+        let mut body = vec![];
+        body.write_u32::<LittleEndian>((cie_pointer_offset - cie) as u32)
+            .unwrap();
+        body.write_u32::<LittleEndian>(pc_begin as u32).unwrap();
+        body.write_u32::<LittleEndian>(0x20).unwrap();
+        body.push(4); // augmentation data length
+        body.write_u32::<LittleEndian>(0x3000).unwrap();
+        body.extend_from_slice(&[0, 0, 0]); // padding
+        builder.entry(&body);
+        builder.terminator();
+
+        let eh_frame =
+            get_fdes::<LittleEndian, _>(&mut Cursor::new(&builder.data), 4, &builder.bases)
+                .unwrap();
+        assert!(eh_frame.skipped.is_empty());
+        let fdes: Vec<_> = eh_frame
+            .fdes
+            .iter()
+            .map(|fde| (fde.begin, fde.length, fde.lsda))
+            .collect();
+        assert_eq!(fdes, vec![(0x1000, 0x20, Some(0x3000))]);
+    }
+
+    #[test]
+    fn reads_personality() {
+        let bases = EhFrameBases {
+            eh_frame: 0x2000,
+            ..Default::default()
+        };
+        // The personality pointer is at 0x200a, and points at 0x4000
+        let personality = (0x4000u32 - 0x200a).to_le_bytes();
+        let data = [
+            &[1, b'z', b'P', b'R', 0, 1, 0x7c, 8, 6][..],
+            // indirect | pcrel | sdata4
+            &[0x9b],
+            &personality,
+            &[0x1b],
+        ]
+        .concat();
+
+        let cie = Cie::parse::<LittleEndian, _>(&mut Cursor::new(&data), 4, &bases).unwrap();
+        assert_eq!(cie.personality, Some(0x4000));
+    }
+
+    #[test]
+    fn reads_absolute_personality() {
+        // absptr, and udata4 with no application
+        for encoding in [0x00, 0x03] {
+            let data = [
+                &[1, b'z', b'P', b'R', 0, 1, 0x7c, 8, 6][..],
+                &[encoding],
+                &0x4000u32.to_le_bytes(),
+                &[0x1b],
+            ]
+            .concat();
+
+            let cie =
+                Cie::parse::<LittleEndian, _>(&mut Cursor::new(&data), 4, &EhFrameBases::default())
+                    .unwrap();
+            assert_eq!(cie.personality, Some(0x4000));
+        }
+    }
+
+    #[test]
+    fn parses_cie_versions() {
+        let parse = |data: &[u8]| {
+            Cie::parse::<LittleEndian, _>(&mut Cursor::new(data), 4, &EhFrameBases::default())
+        };
+
+        // Version 3 has a LEB128 return address register
+        let cie = parse(&[3, b'z', b'R', 0, 1, 0x7c, 0x80, 0x01, 1, 0x1b]).unwrap();
+        assert_eq!((cie.version, cie.address_size), (3, 4));
+        assert!(matches!(
+            cie.fde_pointer_format,
+            Some(EhPointerFormat::DW_EH_PE_sdata4)
+        ));
+
+        // Version 4 has its own address and segment size
+        let cie = parse(&[4, b'z', b'R', 0, 8, 0, 1, 0x78, 16, 1, 0x1b]).unwrap();
+        assert_eq!((cie.version, cie.address_size), (4, 8));
+        assert!(matches!(
+            cie.fde_pointer_format,
+            Some(EhPointerFormat::DW_EH_PE_sdata4)
+        ));
+
+        for address_size in [0, 16] {
+            let error =
+                parse(&[4, b'z', b'R', 0, address_size, 0, 1, 0x78, 16, 1, 0x1b]).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref(),
+                Some(EhFrameError::UnhandledPointerSize(size)) if *size == usize::from(address_size)
+            ));
+        }
+
+        let error = parse(&[2, b'z', b'R', 0, 1, 0x7c, 8, 1, 0x1b]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(EhFrameError::UnhandledCieVersion(2))
+        ));
+    }
+
+    #[test]
+    fn malformed_cies_are_errors() {
+        let parse = |data: &[u8]| {
+            Cie::parse::<LittleEndian, _>(&mut Cursor::new(data), 4, &EhFrameBases::default())
+        };
+
+        // Augmentation data far longer than the section
+        assert!(
+            parse(&[1, b'z', b'R', 0, 1, 0x7c, 8, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x1b]).is_err()
+        );
+
+        // An 'e' that isn't part of "eh"
+        let error = parse(&[1, b'z', b'e', b'R', 0, 1, 0x7c, 8, 1, 0x1b]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(EhFrameError::UnhandledAugmentation('e'))
+        ));
+    }
+
+    #[test]
+    fn parses_absolute_fdes_on_64_bit() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        builder.entry(&[
+            0, 0, 0, 0, // CIE id
+            1, // version
+            b'z', b'R', 0,    // augmentation string
+            1,    // code alignment factor
+            0x78, // data alignment factor (-8)
+            16,   // return address register
+            1,    // augmentation data length
+            0x00, // FDE pointer encoding: absptr
+            0, 0, 0, // padding (DW_CFA_nop)
+        ]);
+
+        let cie_pointer_offset = builder.data.len() as u64 + 4;
+        let mut body = vec![];
+        body.write_u32::<LittleEndian>(cie_pointer_offset as u32)
+            .unwrap();
+        body.write_u64::<LittleEndian>(0x1_4000_1000).unwrap();
+        body.write_u64::<LittleEndian>(0x20).unwrap();
+        body.extend_from_slice(&[0, 0, 0, 0]); // augmentation data length + padding
+        builder.entry(&body);
+        builder.terminator();
+
+        let eh_frame =
+            get_fdes::<LittleEndian, _>(&mut Cursor::new(&builder.data), 8, &builder.bases)
+                .unwrap();
+        assert!(eh_frame.skipped.is_empty());
+        let fdes: Vec<_> = eh_frame
+            .fdes
+            .iter()
+            .map(|fde| (fde.begin, fde.length))
+            .collect();
+        assert_eq!(fdes, vec![(0x1_4000_1000, 0x20)]);
+    }
+
+    #[test]
+    fn reads_unsigned_pointers() {
+        let data = [0xff, 0xfe, 0xfd, 0xfc, 0xfb, 0xfa, 0xf9, 0xf8];
+        let read = |format| {
+            read_encoded_no_application::<LittleEndian, _>(&mut Cursor::new(&data), format, 4)
+                .unwrap()
+        };
+
+        // Unlike sdata, these aren't sign extended
+        assert_eq!(read(EhPointerFormat::DW_EH_PE_udata2), 0xfeff);
+        assert_eq!(read(EhPointerFormat::DW_EH_PE_udata4), 0xfcfd_feff);
+        assert_eq!(
+            read(EhPointerFormat::DW_EH_PE_udata8),
+            0xf8f9_fafb_fcfd_feff
+        );
+    }
+
+    #[test]
+    fn reads_leb128_pointers() {
+        let read = |data: &[u8], format| {
+            read_encoded_no_application::<LittleEndian, _>(&mut Cursor::new(data), format, 4)
+                .unwrap()
+        };
+
+        assert_eq!(
+            read(&[0xe5, 0x8e, 0x26], EhPointerFormat::DW_EH_PE_uleb128),
+            624485
+        );
+        assert_eq!(
+            read(&[0xc0, 0xbb, 0x78], EhPointerFormat::DW_EH_PE_sleb128),
+            -123456i64 as u64
+        );
+    }
+
+    #[test]
+    fn unhandled_pointers_are_errors() {
+        let error = read_encoded::<LittleEndian, _>(
+            &mut Cursor::new(&[0; 8]),
+            EhPointerFormat::DW_EH_PE_udata4,
+            EhPointerApplication::DW_EH_PE_aligned,
+            4,
+            &EhFrameBases::default(),
+            0,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(EhFrameError::UnhandledPointerApplication(
+                EhPointerApplication::DW_EH_PE_aligned
+            ))
+        ));
+
+        let error = read_encoded_no_application::<LittleEndian, _>(
+            &mut Cursor::new(&[0; 8]),
+            EhPointerFormat::DW_EH_PE_absptr,
+            3,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(EhFrameError::UnhandledPointerSize(3))
+        ));
+    }
+
+    #[test]
+    fn too_many_entries_is_a_typed_error() {
+        // Entries of 5 bytes, which are too short to be anything
+        let data = [1, 0, 0, 0, 0].repeat(4);
+        let error =
+            get_fdes::<LittleEndian, _>(&mut Cursor::new(&data), 4, &EhFrameBases::default())
+                .unwrap_err();
+        assert!(matches!(
+            error,
+            EhFrameError::TooManyEntries { section_size: 20 }
+        ));
+
+        // Callers can match on it without downcasting
+        let error = crate::error::TfbindiffError::from(crate::program::LoadError::EhFrame(error));
+        assert!(matches!(
+            error,
+            crate::error::TfbindiffError::EhFrame(EhFrameError::TooManyEntries { .. })
+        ));
+    }
+
+    #[test]
+    fn skips_malformed_fdes() {
+        let mut builder = EhFrameBuilder::new(0x2000);
+        let cie = builder.cie();
+        // aligned | udata4
+        let aligned_cie = builder.cie_with_encoding(0x53);
+        builder.fde(cie, 0x1000, 0x20);
+        let unhandled_fde = builder.data.len() as u64;
+        builder.fde_with_value(aligned_cie, 0, 0x10);
+        let missing_cie_fde = builder.data.len() as u64;
+        // Points at offset 4, which is in the middle of the CIE
+        builder.fde(4, 0x1020, 0x10);
+        // Points before the start of the section
+        let out_of_bounds_fde = builder.data.len() as u64;
+        let mut body = vec![];
+        body.write_u32::<LittleEndian>(0xffff_fff0).unwrap();
+        body.extend_from_slice(&[0; 12]);
+        builder.entry(&body);
+        builder.fde(cie, 0x1030, 0x8);
+        builder.terminator();
+
+        let eh_frame =
+            get_fdes::<LittleEndian, _>(&mut Cursor::new(&builder.data), 4, &builder.bases)
+                .unwrap();
+        let fdes: Vec<_> = eh_frame
+            .fdes
+            .iter()
+            .map(|fde| (fde.begin, fde.length))
+            .collect();
+        assert_eq!(fdes, vec![(0x1000, 0x20), (0x1030, 0x8)]);
+        assert_eq!(
+            eh_frame.skipped,
+            vec![unhandled_fde, missing_cie_fde, out_of_bounds_fde]
+        );
+    }
 }
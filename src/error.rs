@@ -0,0 +1,57 @@
+use crate::compare::CompareError;
+use crate::eh_frame::EhFrameError;
+use crate::program::LoadError;
+use std::io;
+
+/// Any error returned by the library, for callers that just want to `?` it into their own error
+/// handling. The more specific `LoadError` and `CompareError` convert into it.
+#[derive(Debug, thiserror::Error)]
+pub enum TfbindiffError {
+    /// Reading a program from disk failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The file isn't an object file that `object` can parse.
+    #[error("failed to parse object file: {0}")]
+    Parse(object::Error),
+    /// The program's `.eh_frame` couldn't be parsed at all. Entries that are broken on their own
+    /// are skipped instead.
+    #[error("failed to parse .eh_frame: {0}")]
+    EhFrame(#[from] EhFrameError),
+    /// A 32-bit program was compared against a 64-bit one, or the other way around.
+    #[error(
+        "the first program is {}-bit but the second is {}-bit",
+        pointer_size1 * 8,
+        pointer_size2 * 8
+    )]
+    PointerSizeMismatch {
+        pointer_size1: usize,
+        pointer_size2: usize,
+    },
+    /// Any other reason a program couldn't be loaded, e.g. because it has no unwind info.
+    #[error(transparent)]
+    Load(LoadError),
+}
+
+impl From<LoadError> for TfbindiffError {
+    fn from(error: LoadError) -> Self {
+        match error {
+            LoadError::Parse(error) => TfbindiffError::Parse(error),
+            LoadError::EhFrame(error) => TfbindiffError::EhFrame(error),
+            error => TfbindiffError::Load(error),
+        }
+    }
+}
+
+impl From<CompareError> for TfbindiffError {
+    fn from(error: CompareError) -> Self {
+        match error {
+            CompareError::PointerSizeMismatch {
+                pointer_size1,
+                pointer_size2,
+            } => TfbindiffError::PointerSizeMismatch {
+                pointer_size1,
+                pointer_size2,
+            },
+        }
+    }
+}
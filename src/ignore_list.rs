@@ -0,0 +1,88 @@
+use crate::util::demangle_symbol;
+use rustc_hash::FxHashSet;
+
+/// Functions that are known to differ for uninteresting reasons, e.g. because they embed a build
+/// timestamp. Loaded from a file with one entry per line: a symbol name (mangled or demangled),
+/// or a regex prefixed with `re:`. Empty lines and lines starting with `#` are skipped.
+///
+/// `CompareOptions::only_functions` lists the functions to compare in the same format.
+#[derive(Clone, Default)]
+pub struct IgnoreList {
+    names: FxHashSet<String>,
+    patterns: Vec<regex_lite::Regex>,
+}
+
+impl IgnoreList {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut ignore_list = Self::default();
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.strip_prefix("re:") {
+                Some(pattern) => {
+                    let regex = regex_lite::Regex::new(pattern.trim()).map_err(|error| {
+                        format!("line {}: invalid regex: {error}", line_idx + 1)
+                    })?;
+                    ignore_list.patterns.push(regex);
+                }
+                None => {
+                    ignore_list.names.insert(line.to_string());
+                }
+            }
+        }
+
+        Ok(ignore_list)
+    }
+
+    /// The plain names on the list, without the patterns.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Checks both the symbol and its demangled name.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let demangled = demangle_symbol(name);
+        std::iter::once(name)
+            .chain(demangled.as_deref())
+            .any(|candidate| {
+                self.names.contains(candidate)
+                    || self.patterns.iter().any(|regex| regex.is_match(candidate))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_names_and_patterns() {
+        let ignore_list =
+            IgnoreList::parse("# build info\nget_build_time\n\nre:^_ZN5Stamp\napi(int, int)\n")
+                .unwrap();
+
+        assert!(ignore_list.matches("get_build_time"));
+        assert!(ignore_list.matches("_ZN5Stamp3nowEv"));
+        // By demangled name
+        assert!(ignore_list.matches("_Z3apiii"));
+
+        assert!(!ignore_list.matches("get_build_time_2"));
+        assert!(!ignore_list.matches("main"));
+    }
+
+    #[test]
+    fn reports_invalid_regex() {
+        assert!(IgnoreList::parse("foo\nre:(\n").is_err());
+    }
+}
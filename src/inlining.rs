@@ -0,0 +1,256 @@
+use crate::compare::{collect_instructions, FunctionChange};
+use crate::instruction_wrapper::InstructionWrapper;
+use crate::program::Program;
+use iced_x86::{Code, FlowControl, Mnemonic, OpKind, Register};
+use rustc_hash::FxHashSet;
+use std::ops::Range;
+
+/// Runs of instructions shorter than this aren't checked, they'd match too many functions.
+pub const MIN_INLINED_INSTRUCTIONS: usize = 4;
+/// Fraction of a function's body that has to show up in a run for it to count as inlined.
+const MIN_BODY_MATCH: f32 = 0.6;
+
+/// A run of instructions that only one side of a change has, which looks like the body of a
+/// function that the other side calls instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InlinedCall {
+    /// The side the body was inlined into, 0 for the first program and 1 for the second.
+    pub side: usize,
+    /// Address of the first instruction of the run.
+    pub address: u64,
+    /// Symbol of the function that was inlined.
+    pub name: String,
+}
+
+/// Functions that `instructions` calls directly, by symbol.
+fn direct_callees<'a>(program: &'a Program, instructions: &[InstructionWrapper]) -> Vec<&'a str> {
+    let mut seen = FxHashSet::default();
+    instructions
+        .iter()
+        .map(InstructionWrapper::get)
+        .filter(|instr| {
+            instr.flow_control() == FlowControl::Call
+                && matches!(
+                    instr.op0_kind(),
+                    OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+                )
+        })
+        .filter_map(|instr| program.function_at(instr.near_branch_target()))
+        .filter(|(_, function)| seen.insert(function.address()))
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Setting up and tearing down the frame, and returning, which the inlined copy of a function
+/// doesn't do.
+fn is_frame_instruction(instr: &InstructionWrapper) -> bool {
+    let instr = instr.get();
+    let is_frame_pointer = |register| matches!(register, Register::RBP | Register::EBP);
+
+    match instr.mnemonic() {
+        Mnemonic::Ret | Mnemonic::Leave | Mnemonic::Endbr64 | Mnemonic::Endbr32 | Mnemonic::Nop => {
+            true
+        }
+        Mnemonic::Push | Mnemonic::Pop => {
+            instr.op0_kind() == OpKind::Register && is_frame_pointer(instr.op0_register())
+        }
+        _ => {
+            matches!(
+                instr.code(),
+                Code::Mov_r64_rm64 | Code::Mov_r32_rm32 | Code::Mov_rm64_r64 | Code::Mov_rm32_r32
+            ) && is_frame_pointer(instr.op0_register())
+                && matches!(instr.op1_register(), Register::RSP | Register::ESP)
+        }
+    }
+}
+
+/// Whether `run` contains most of `body`. Instructions are compared by mnemonic, since the
+/// inlined copy works on whatever registers the caller had free.
+fn contains_body(run: &[InstructionWrapper], body: &[InstructionWrapper]) -> bool {
+    let mnemonics = |instructions: &[InstructionWrapper]| -> Vec<Mnemonic> {
+        instructions
+            .iter()
+            .map(|instr| instr.get().mnemonic())
+            .collect()
+    };
+    let (run, body) = (mnemonics(run), mnemonics(body));
+
+    let matched: usize = similar::capture_diff_slices(similar::Algorithm::Myers, &run, &body)
+        .iter()
+        .filter_map(|op| match *op {
+            similar::DiffOp::Equal { len, .. } => Some(len),
+            _ => None,
+        })
+        .sum();
+    matched as f32 >= body.len() as f32 * MIN_BODY_MATCH
+}
+
+/// Finds the first of `callees` whose body `run` contains.
+fn find_inlined_callee<'a>(
+    program: &Program,
+    change: &FunctionChange,
+    callees: &[&'a str],
+    run: &[InstructionWrapper],
+) -> Option<&'a str> {
+    callees.iter().copied().find(|name| {
+        let function = &program.functions[*name];
+        let (instructions, _) = collect_instructions(program, function, None, change.policy());
+        let body: Vec<_> = instructions
+            .into_iter()
+            .filter(|instr| !is_frame_instruction(instr))
+            .collect();
+
+        body.len() >= MIN_INLINED_INSTRUCTIONS
+            && body.len() <= run.len() * 2
+            && contains_body(run, &body)
+    })
+}
+
+/// Merges the diff into regions of changed instructions, as ranges into the old and new
+/// instructions. An inlined body rarely comes out as a single insertion, as some of its
+/// instructions happen to match what the call was surrounded with, so short equal runs don't end
+/// a region.
+fn changed_regions(diff_ops: &[similar::DiffOp]) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut regions: Vec<(Range<usize>, Range<usize>)> = vec![];
+    let mut current: Option<(Range<usize>, Range<usize>)> = None;
+
+    for op in diff_ops {
+        let (old, new) = (op.old_range(), op.new_range());
+        let is_equal = matches!(op, similar::DiffOp::Equal { .. });
+        if is_equal && old.len() >= MIN_INLINED_INSTRUCTIONS {
+            regions.extend(current.take());
+            continue;
+        }
+
+        current = match current {
+            Some((current_old, current_new)) => {
+                Some((current_old.start..old.end, current_new.start..new.end))
+            }
+            None if is_equal => None,
+            None => Some((old, new)),
+        };
+    }
+    regions.extend(current);
+
+    regions
+}
+
+/// Finds the runs of instructions that were inlined into one side of `change`, where the other
+/// side calls a function with the same body. A function that got inlined otherwise shows up as
+/// a large insertion that's hard to make sense of.
+pub fn find_inlined_calls(
+    program1: &Program,
+    program2: &Program,
+    change: &FunctionChange,
+) -> Vec<InlinedCall> {
+    if change.too_large() || !program1.can_disassemble() || !program2.can_disassemble() {
+        return vec![];
+    }
+
+    let (instructions1, instructions2) = change.instructions();
+    let callees1 = direct_callees(program1, instructions1);
+    let callees2 = direct_callees(program2, instructions2);
+
+    let mut inlined = vec![];
+    let diff_ops =
+        similar::capture_diff_slices(similar::Algorithm::Myers, instructions1, instructions2);
+    for (old, new) in changed_regions(&diff_ops) {
+        // A body inlined into the first function replaces a call in the second, and the other
+        // way around
+        let sides = [
+            (0, &instructions1[old], program2, &callees2),
+            (1, &instructions2[new], program1, &callees1),
+        ];
+        for (side, run, program, callees) in sides {
+            if run.len() < MIN_INLINED_INSTRUCTIONS {
+                continue;
+            }
+
+            if let Some(name) = find_inlined_callee(program, change, callees, run) {
+                inlined.push(InlinedCall {
+                    side,
+                    address: run[0].get().ip(),
+                    name: name.to_string(),
+                });
+            }
+        }
+    }
+
+    inlined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_id::BuildStamp;
+    use crate::compare::{compare_function, CompareOptions, ComparedFunction};
+    use crate::program::{CodeCoverage, Function};
+    use object::{Architecture, BinaryFormat, SectionIndex};
+    use rustc_hash::FxHashMap;
+
+    // mov eax, edi; add eax, eax; imul eax, eax; sub eax, 1
+    const BODY: &[u8] = &[0x89, 0xF8, 0x01, 0xC0, 0x0F, 0xAF, 0xC0, 0x83, 0xE8, 0x01];
+    const RET: &[u8] = &[0xC3];
+
+    /// A program with the given functions, each as (name, address, code).
+    fn program(functions: &[(&str, u64, Vec<u8>)]) -> Program {
+        let section_idx = SectionIndex(1);
+        let base = 0x1000;
+        let mut code = vec![0xCC; 0x100];
+        let mut function_map = FxHashMap::default();
+        let mut symbol_map = FxHashMap::default();
+        for (name, address, bytes) in functions {
+            let offset = (address - base) as usize;
+            code[offset..offset + bytes.len()].copy_from_slice(bytes);
+            function_map.insert(
+                name.to_string(),
+                Function::new(section_idx, base, *address, bytes.len() as u64),
+            );
+            symbol_map.insert(*address, name.to_string());
+        }
+        let function_index = Program::build_function_index(&function_map);
+
+        Program {
+            format: BinaryFormat::Elf,
+            architecture: Architecture::X86_64,
+            pointer_size: 8,
+            bitness: 64,
+            functions: function_map,
+            symbol_map,
+            sections: [(section_idx, code)].into_iter().collect(),
+            section_names: [(section_idx, ".text".to_string())].into_iter().collect(),
+            function_index,
+            relocations: FxHashMap::default(),
+            plt_entries: FxHashMap::default(),
+            build_stamp: BuildStamp::default(),
+            content_hash: 0,
+            coverage: CodeCoverage::default(),
+        }
+    }
+
+    #[test]
+    fn finds_inlined_callee() {
+        // f calls g at 0x1080 in the first program, and has g's body inlined in the second
+        let call_g = vec![0xE8, 0x7B, 0x00, 0x00, 0x00];
+        let program1 = program(&[
+            ("f", 0x1000, [call_g, RET.to_vec()].concat()),
+            ("g", 0x1080, [BODY, RET].concat()),
+        ]);
+        let program2 = program(&[("f", 0x1000, [BODY, RET].concat())]);
+
+        let Some(ComparedFunction::Changed(change)) =
+            compare_function(&program1, &program2, "f", &CompareOptions::default()).unwrap()
+        else {
+            panic!("f should have changed");
+        };
+
+        assert_eq!(
+            find_inlined_calls(&program1, &program2, &change),
+            [InlinedCall {
+                side: 1,
+                address: 0x1000,
+                name: "g".to_string(),
+            }]
+        );
+    }
+}
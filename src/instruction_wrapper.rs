@@ -1,34 +1,129 @@
-use iced_x86::{Decoder, DecoderOptions, Instruction, OpKind};
+use iced_x86::{Code, Decoder, DecoderError, DecoderOptions, Instruction, OpKind, Register};
 use std::hash::Hash;
 
+/// Which differences between two instructions are reported. Everything not covered here, like
+/// the opcode and register operands, always has to match.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct EqualityPolicy {
+    /// Don't compare immediate operands, e.g. constants and sizes.
+    pub ignore_immediates: bool,
+    /// Don't compare the displacements of memory operands, like stack slots and struct offsets.
+    /// References to data at a fixed address are never compared, as they move whenever the
+    /// binary does.
+    pub ignore_displacements: bool,
+    /// Treat functions that only differ by a consistent register renaming as equal. This is
+    /// applied to whole functions, see `RegisterMapping`, not to single instructions.
+    pub ignore_regalloc: bool,
+}
+
+impl Default for EqualityPolicy {
+    fn default() -> Self {
+        Self {
+            ignore_immediates: true,
+            ignore_displacements: true,
+            ignore_regalloc: false,
+        }
+    }
+}
+
+impl EqualityPolicy {
+    pub fn ignore_immediates(mut self, ignore_immediates: bool) -> Self {
+        self.ignore_immediates = ignore_immediates;
+        self
+    }
+
+    pub fn ignore_displacements(mut self, ignore_displacements: bool) -> Self {
+        self.ignore_displacements = ignore_displacements;
+        self
+    }
+
+    pub fn ignore_regalloc(mut self, ignore_regalloc: bool) -> Self {
+        self.ignore_regalloc = ignore_regalloc;
+        self
+    }
+}
+
 #[derive(Clone, Copy)]
-pub struct InstructionWrapper(Instruction);
+pub struct InstructionWrapper {
+    instruction: Instruction,
+    policy: EqualityPolicy,
+}
+
 impl InstructionWrapper {
     pub fn get(&self) -> &Instruction {
-        &self.0
+        &self.instruction
+    }
+
+    /// True if the memory operand refers to data at a fixed address (RIP-relative or absolute),
+    /// which moves whenever anything before it in the binary changes size.
+    fn is_data_reference(&self) -> bool {
+        let base = self.instruction.memory_base();
+        matches!(base, Register::RIP | Register::EIP)
+            || (base == Register::None && self.instruction.memory_index() == Register::None)
+    }
+
+    fn displacement_eq(&self, other: &Self, policy: &EqualityPolicy) -> bool {
+        // Data references are compared by address, which would report every relocated global
+        policy.ignore_displacements
+            || self.is_data_reference()
+            || self.instruction.memory_displacement64() == other.instruction.memory_displacement64()
+    }
+
+    /// Compares everything but the register operands under `policy`: the opcode, immediates,
+    /// displacements and the value of invalid bytes. `RegisterMapping` checks the registers
+    /// itself.
+    pub fn values_eq_with(&self, other: &Self, policy: &EqualityPolicy) -> bool {
+        let (instr1, instr2) = (&self.instruction, &other.instruction);
+        if instr1.code() == Code::DeclareByte && instr2.code() == Code::DeclareByte {
+            return instr1.get_declare_byte_value(0) == instr2.get_declare_byte_value(0);
+        }
+
+        if instr1.code() != instr2.code()
+            || instr1.op_code().op_kinds() != instr2.op_code().op_kinds()
+        {
+            return false;
+        }
+
+        (0..instr1.op_count()).all(|op_idx| match instr1.op_kind(op_idx) {
+            OpKind::Memory => {
+                instr1.memory_segment() == instr2.memory_segment()
+                    && self.displacement_eq(other, policy)
+            }
+            OpKind::Immediate8
+            | OpKind::Immediate8_2nd
+            | OpKind::Immediate16
+            | OpKind::Immediate32
+            | OpKind::Immediate64
+            | OpKind::Immediate8to16
+            | OpKind::Immediate8to32
+            | OpKind::Immediate8to64
+            | OpKind::Immediate32to64 => {
+                policy.ignore_immediates || instr1.immediate(op_idx) == instr2.immediate(op_idx)
+            }
+            _ => true,
+        })
+    }
+
+    /// Compares two instructions under `policy`, instead of the one they were decoded with.
+    pub fn eq_with(&self, other: &Self, policy: &EqualityPolicy) -> bool {
+        let (instr1, instr2) = (&self.instruction, &other.instruction);
+        self.values_eq_with(other, policy)
+            && (0..instr1.op_count()).all(|op_idx| match instr1.op_kind(op_idx) {
+                OpKind::Register => instr1.op_register(op_idx) == instr2.op_register(op_idx),
+                OpKind::Memory => {
+                    instr1.memory_base() == instr2.memory_base()
+                        && instr1.memory_index() == instr2.memory_index()
+                        && instr1.memory_index_scale() == instr2.memory_index_scale()
+                }
+                _ => true,
+            })
     }
 }
 
 impl Eq for InstructionWrapper {}
 impl PartialEq for InstructionWrapper {
     fn eq(&self, other: &Self) -> bool {
-        if (self.0.code() == other.0.code())
-            && (self.0.op_code().op_kinds() == other.0.op_code().op_kinds())
-        {
-            for op_idx in 0..self.0.op_count() {
-                if self.0.op_kind(op_idx) == OpKind::Register {
-                    let reg1 = self.0.op_register(op_idx);
-                    let reg2 = other.0.op_register(op_idx);
-
-                    if reg1 != reg2 {
-                        return false;
-                    }
-                };
-            }
-            return true;
-        }
-
-        false
+        self.eq_with(other, &self.policy)
     }
 }
 
@@ -51,12 +146,42 @@ impl PartialOrd for InstructionWrapper {
     }
 }
 
+/// Decodes a function's code into instructions for comparing. There's one per architecture that
+/// can be disassembled, `Program::decode` picks the right one.
+pub trait InstructionStream: Iterator<Item = InstructionWrapper> {
+    /// True if the last instruction didn't fit in the length, or the code ran out before the
+    /// length did, which means the length is wrong (or the code isn't code at all).
+    fn ended_mid_instruction(&self) -> bool;
+
+    /// Number of bytes that couldn't be decoded so far. Each of them is returned as a `db`.
+    fn invalid_bytes(&self) -> usize;
+}
+
+/// Functions that can't be disassembled have no instructions, they're only compared by bytes.
+impl InstructionStream for std::iter::Empty<InstructionWrapper> {
+    fn ended_mid_instruction(&self) -> bool {
+        false
+    }
+
+    fn invalid_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// The x86 decoder.
 pub struct InstructionIter<'a> {
     decoder: Decoder<'a>,
+    code: &'a [u8],
+    end: u64,
+    ended_mid_instruction: bool,
+    invalid_bytes: usize,
+    policy: EqualityPolicy,
 }
 
 impl<'a> InstructionIter<'a> {
-    pub fn new(address: u64, code: &'a [u8], address_size: usize) -> Self {
+    /// Decodes the `length` bytes of code at `address`. `code` may extend past the end, e.g. when
+    /// it's the rest of the section, but decoding always stops at `address + length`.
+    pub fn new(address: u64, code: &'a [u8], length: usize, address_size: usize) -> Self {
         Self {
             decoder: Decoder::with_ip(
                 (address_size * 8).try_into().unwrap(),
@@ -64,18 +189,186 @@ impl<'a> InstructionIter<'a> {
                 address,
                 DecoderOptions::NONE,
             ),
+            code,
+            end: address.saturating_add(length as u64),
+            ended_mid_instruction: false,
+            invalid_bytes: 0,
+            policy: EqualityPolicy::default(),
         }
     }
+
+    /// Sets the policy the decoded instructions are compared with by `==`.
+    pub fn policy(mut self, policy: EqualityPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+impl InstructionStream for InstructionIter<'_> {
+    fn ended_mid_instruction(&self) -> bool {
+        self.ended_mid_instruction
+    }
+
+    fn invalid_bytes(&self) -> usize {
+        self.invalid_bytes
+    }
 }
 
 impl<'a> Iterator for InstructionIter<'a> {
     type Item = InstructionWrapper;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.decoder.can_decode() {
-            Some(InstructionWrapper(self.decoder.decode()))
-        } else {
-            None
+        if self.decoder.ip() >= self.end {
+            return None;
+        }
+        if !self.decoder.can_decode() {
+            self.ended_mid_instruction = true;
+            return None;
+        }
+
+        let (ip, position) = (self.decoder.ip(), self.decoder.position());
+        let mut instruction = self.decoder.decode();
+        if instruction.next_ip() > self.end
+            || self.decoder.last_error() == DecoderError::NoMoreBytes
+        {
+            self.ended_mid_instruction = true;
+        }
+
+        // Data in the middle of code (e.g. jump tables) would otherwise all decode as the same
+        // invalid instruction. Show it byte by byte instead, and resync after the first one.
+        if instruction.is_invalid() {
+            let byte = self.code[position];
+            self.decoder.set_position(position + 1).unwrap();
+            self.decoder.set_ip(ip + 1);
+
+            instruction = Instruction::with_declare_byte_1(byte);
+            instruction.set_ip(ip);
+            instruction.set_len(1);
+            instruction.set_next_ip(ip + 1);
+            self.invalid_bytes += 1;
         }
+
+        Some(InstructionWrapper {
+            instruction,
+            policy: self.policy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(address: u64, code: &[u8], compare_displacements: bool) -> InstructionWrapper {
+        InstructionIter::new(address, code, code.len(), 8)
+            .policy(EqualityPolicy::default().ignore_displacements(!compare_displacements))
+            .next()
+            .unwrap()
+    }
+
+    // mov eax, [rbp-8]
+    const STACK_SLOT_8: &[u8] = &[0x8B, 0x45, 0xF8];
+    // mov eax, [rbp-0x20]
+    const STACK_SLOT_20: &[u8] = &[0x8B, 0x45, 0xE0];
+    // mov eax, [rbx-8]
+    const RBX_SLOT_8: &[u8] = &[0x8B, 0x43, 0xF8];
+    // mov eax, [rip+0x100]
+    const GLOBAL_100: &[u8] = &[0x8B, 0x05, 0x00, 0x01, 0x00, 0x00];
+    // mov eax, [rip+0x200]
+    const GLOBAL_200: &[u8] = &[0x8B, 0x05, 0x00, 0x02, 0x00, 0x00];
+
+    #[test]
+    fn stack_slot_change_ignored_by_default() {
+        assert!(decode(0x1000, STACK_SLOT_8, false) == decode(0x1000, STACK_SLOT_20, false));
+    }
+
+    #[test]
+    fn stack_slot_change_detected() {
+        assert!(decode(0x1000, STACK_SLOT_8, true) != decode(0x1000, STACK_SLOT_20, true));
+        assert!(decode(0x1000, STACK_SLOT_8, true) == decode(0x2000, STACK_SLOT_8, true));
+    }
+
+    #[test]
+    fn base_register_change_detected() {
+        assert!(decode(0x1000, STACK_SLOT_8, false) != decode(0x1000, RBX_SLOT_8, false));
+    }
+
+    #[test]
+    fn relocated_global_is_equal() {
+        // Same instruction moved by 0x100 bytes, and the global it reads moved by another 0x100
+        assert!(decode(0x1000, GLOBAL_100, true) == decode(0x1100, GLOBAL_200, true));
+        assert!(decode(0x1000, GLOBAL_100, true) == decode(0x1000, GLOBAL_200, true));
+    }
+
+    #[test]
+    fn invalid_bytes_are_declared() {
+        // nop; push es (invalid in 64-bit mode); ret
+        let mut iter = InstructionIter::new(0x1000, &[0x90, 0x06, 0xC3], 3, 8);
+        let instructions: Vec<_> = iter.by_ref().collect();
+
+        let codes: Vec<_> = instructions
+            .iter()
+            .map(|instr| instr.get().code())
+            .collect();
+        assert_eq!(codes, [Code::Nopd, Code::DeclareByte, Code::Retnq]);
+        assert_eq!(instructions[1].get().get_declare_byte_value(0), 0x06);
+        assert_eq!(instructions[2].get().ip(), 0x1002);
+        assert_eq!(iter.invalid_bytes(), 1);
+        assert!(!iter.ended_mid_instruction());
+    }
+
+    #[test]
+    fn different_invalid_bytes_differ() {
+        let byte = |code: &[u8]| InstructionIter::new(0x1000, code, 1, 8).next().unwrap();
+        assert!(byte(&[0x06]) != byte(&[0x07]));
+        assert!(byte(&[0x06]) == byte(&[0x06]));
+    }
+
+    #[test]
+    fn stops_at_length() {
+        // nop; nop; ret, but only the first two bytes belong to the function
+        let mut iter = InstructionIter::new(0x1000, &[0x90, 0x90, 0xC3], 2, 8);
+        assert_eq!(iter.by_ref().count(), 2);
+        assert!(!iter.ended_mid_instruction());
+    }
+
+    #[test]
+    fn reports_ending_mid_instruction() {
+        // mov eax, [rbp-8] with a length that cuts it in half
+        let mut iter = InstructionIter::new(0x1000, STACK_SLOT_8, 2, 8);
+        assert_eq!(iter.by_ref().count(), 1);
+        assert!(iter.ended_mid_instruction());
+
+        // The same, but the code runs out too, so what's left can only be shown as bytes
+        let mut iter = InstructionIter::new(0x1000, &STACK_SLOT_8[..2], 2, 8);
+        assert_eq!(iter.by_ref().count(), 2);
+        assert!(iter.ended_mid_instruction());
+        assert_eq!(iter.invalid_bytes(), 2);
+    }
+
+    #[test]
+    fn absolute_global_is_equal() {
+        // mov eax, [0x804a010] and mov eax, [0x804a020]
+        let decode32 = |code: &[u8]| {
+            InstructionIter::new(0x1000, code, code.len(), 4)
+                .policy(EqualityPolicy::default().ignore_displacements(false))
+                .next()
+                .unwrap()
+        };
+        assert!(
+            decode32(&[0xA1, 0x10, 0xA0, 0x04, 0x08]) == decode32(&[0xA1, 0x20, 0xA0, 0x04, 0x08])
+        );
+    }
+
+    #[test]
+    fn immediate_change_follows_policy() {
+        // mov eax, 1 and mov eax, 2
+        let one = decode(0x1000, &[0xB8, 0x01, 0x00, 0x00, 0x00], false);
+        let two = decode(0x1000, &[0xB8, 0x02, 0x00, 0x00, 0x00], false);
+        assert!(one == two);
+
+        let strict = EqualityPolicy::default().ignore_immediates(false);
+        assert!(!one.eq_with(&two, &strict));
+        assert!(one.eq_with(&one, &strict));
     }
 }
@@ -1,7 +1,22 @@
 mod matcher;
 
+pub mod baseline;
+pub mod basic_block;
+pub mod build_id;
+pub mod cache;
+pub mod call_graph;
 pub mod compare;
+pub mod diff_model;
 pub mod eh_frame;
+pub mod error;
+pub mod ignore_list;
+pub mod inlining;
 pub mod instruction_wrapper;
+pub mod pdata;
 pub mod program;
+pub mod regalloc;
+pub mod reorder;
+pub mod section_stats;
+pub mod semantic_hash;
+pub mod symbol_map;
 pub mod util;
@@ -1,22 +1,112 @@
+use crate::compare::function_similarity;
 use crate::program::{Function, Program};
+use crate::util::demangle_symbol;
 use rustc_hash::FxHashMap;
 
+/// A function and the name it was matched by. Functions that were folded together (e.g. by the
+/// linker's identical code folding) have several names, and are matched once by each of them.
+pub type NamedFunction<'a> = (&'a str, &'a Function);
+
 pub enum MatchResult<'a> {
-    Matched((&'a Function, &'a Function)),
+    Matched((NamedFunction<'a>, NamedFunction<'a>)),
     Unmatched,
     Finished,
 }
 
+type NamedFunctions<'a> = Vec<NamedFunction<'a>>;
+
 pub struct FunctionMatcher<'a> {
-    program1_functions: Vec<(&'a str, &'a Function)>,
+    program1: &'a Program,
+    program2: &'a Program,
+
+    program1_functions: NamedFunctions<'a>,
     program2_functions: FxHashMap<&'a str, &'a Function>,
 
-    program1_unmatched: Vec<(&'a str, &'a Function)>,
+    program1_unmatched: NamedFunctions<'a>,
+
+    // Matches by demangled name or by name without compiler suffixes, found once all exact
+    // matches are exhausted
+    fuzzy_matches: Option<Vec<(NamedFunction<'a>, NamedFunction<'a>)>>,
+
+    name_suffixes: Vec<String>,
+}
+
+/// Functions whose names only differ by a compiler suffix must be at least this similar to be
+/// matched, `foo.cold` isn't necessarily anything like `foo.part.0`.
+const MIN_SUFFIX_MATCH_SIMILARITY: f32 = 0.5;
+
+/// Strips compiler-added suffixes like `.part.0`, `.isra.0.cold` or `.llvm.123456`. Each of
+/// `suffixes` may be followed by any number of `.N` components.
+fn strip_name_suffixes<'n>(mut name: &'n str, suffixes: &[String]) -> &'n str {
+    let is_numbering = |rest: &str| {
+        rest.split('.')
+            .skip(1)
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+            && (rest.is_empty() || rest.starts_with('.'))
+    };
+
+    'strip: loop {
+        for suffix in suffixes {
+            if let Some(idx) = name.rfind(suffix.as_str()) {
+                if idx > 0 && is_numbering(&name[idx + suffix.len()..]) {
+                    name = &name[..idx];
+                    continue 'strip;
+                }
+            }
+        }
+
+        return name;
+    }
+}
+
+/// Strips the parts of a demangled name that tend to be unstable between builds: template
+/// arguments, parameter lists and anonymous namespaces. Returns None for names that aren't
+/// mangled.
+fn normalize_demangled_name(name: &str) -> Option<String> {
+    let demangled = demangle_symbol(name)?;
+
+    // Bracket counting doesn't work for operator<, operator() and friends
+    if demangled.contains("operator") {
+        return Some(demangled);
+    }
+
+    let mut normalized = String::new();
+    let mut depth = 0usize;
+    for c in demangled.replace("(anonymous namespace)::", "").chars() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => normalized.push(c),
+            _ => {}
+        }
+    }
+
+    // Template functions have their return type in the demangled name, drop it along with any
+    // trailing cv-qualifiers
+    let normalized = normalized.trim().trim_end_matches(" const");
+    Some(
+        normalized
+            .rsplit(' ')
+            .next()
+            .unwrap_or(normalized)
+            .to_string(),
+    )
+}
+
+/// Where a function is in its program, from 0.0 (the first function) to 1.0 (the last one).
+fn relative_position(program: &Program, function: &Function) -> f64 {
+    let index = &program.function_index;
+    let position = index.partition_point(|(address, _)| *address < function.address());
+
+    position as f64 / index.len().max(1) as f64
 }
 
 impl<'a> FunctionMatcher<'a> {
-    pub fn new(program1: &'a Program, program2: &'a Program) -> Self {
+    pub fn new(program1: &'a Program, program2: &'a Program, name_suffixes: Vec<String>) -> Self {
         Self {
+            program1,
+            program2,
+
             program1_functions: program1
                 .functions
                 .iter()
@@ -29,24 +119,166 @@ impl<'a> FunctionMatcher<'a> {
                 .collect(),
 
             program1_unmatched: vec![],
+
+            fuzzy_matches: None,
+
+            name_suffixes,
+        }
+    }
+
+    /// Pairs leftover functions whose names are equal once compiler suffixes are stripped, and
+    /// whose code is similar enough to be the same function.
+    fn find_suffix_matches(&mut self) -> Vec<(NamedFunction<'a>, NamedFunction<'a>)> {
+        let mut candidates: FxHashMap<&'a str, Vec<&'a str>> = FxHashMap::default();
+        for &name in self.program2_functions.keys() {
+            let stripped = strip_name_suffixes(name, &self.name_suffixes);
+            candidates.entry(stripped).or_default().push(name);
         }
+
+        let mut matches = vec![];
+        self.program1_unmatched.retain(|&(func1_name, func1)| {
+            let stripped = strip_name_suffixes(func1_name, &self.name_suffixes);
+            let Some(candidates) = candidates.get_mut(stripped) else {
+                return true;
+            };
+
+            let best = candidates
+                .iter()
+                .enumerate()
+                .map(|(idx, func2_name)| {
+                    let func2 = self.program2_functions[func2_name];
+                    let similarity =
+                        function_similarity(self.program1, func1, self.program2, func2);
+
+                    (idx, similarity)
+                })
+                .filter(|(_, similarity)| *similarity >= MIN_SUFFIX_MATCH_SIMILARITY)
+                .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+            match best {
+                Some((idx, _)) => {
+                    let func2_name = candidates.swap_remove(idx);
+                    let func2 = self.program2_functions.remove(func2_name).unwrap();
+                    matches.push(((func1_name, func1), (func2_name, func2)));
+                    false
+                }
+                None => true,
+            }
+        });
+
+        matches
+    }
+
+    /// Pairs the functions left over after exact matching whose normalized demangled names are
+    /// equal. If several functions share a normalized name, the most similar one is chosen, and
+    /// of equally similar ones the one closest to the same relative position in its program (so
+    /// the Nth of many identical small functions is paired with the Nth).
+    fn find_fuzzy_matches(&mut self) -> Vec<(NamedFunction<'a>, NamedFunction<'a>)> {
+        let mut candidates: FxHashMap<String, Vec<&'a str>> = FxHashMap::default();
+        for name in self.program2_functions.keys() {
+            if let Some(normalized) = normalize_demangled_name(name) {
+                candidates.entry(normalized).or_default().push(name);
+            }
+        }
+
+        let mut matches = vec![];
+        self.program1_unmatched.retain(|&(func1_name, func1)| {
+            let Some(candidates) = normalize_demangled_name(func1_name)
+                .and_then(|normalized| candidates.get_mut(&normalized))
+            else {
+                return true;
+            };
+
+            let position1 = relative_position(self.program1, func1);
+            let best = candidates
+                .iter()
+                .enumerate()
+                .map(|(idx, func2_name)| {
+                    let func2 = self.program2_functions[func2_name];
+                    let similarity = if candidates.len() == 1 {
+                        1.0
+                    } else {
+                        function_similarity(self.program1, func1, self.program2, func2)
+                    };
+                    let distance = (relative_position(self.program2, func2) - position1).abs();
+
+                    (idx, similarity, distance)
+                })
+                .max_by(|(_, similarity1, distance1), (_, similarity2, distance2)| {
+                    similarity1
+                        .total_cmp(similarity2)
+                        .then(distance2.total_cmp(distance1))
+                });
+
+            match best {
+                Some((idx, _, _)) => {
+                    let func2_name = candidates.swap_remove(idx);
+                    let func2 = self.program2_functions.remove(func2_name).unwrap();
+                    matches.push(((func1_name, func1), (func2_name, func2)));
+                    false
+                }
+                None => true,
+            }
+        });
+
+        matches
     }
 
     pub fn next_match(&mut self) -> MatchResult<'a> {
         if let Some((func1_name, func1)) = self.program1_functions.pop() {
-            if let Some(func2) = self.program2_functions.remove(&func1_name) {
-                return MatchResult::Matched((func1, func2));
+            if let Some((func2_name, func2)) = self.program2_functions.remove_entry(&func1_name) {
+                return MatchResult::Matched(((func1_name, func1), (func2_name, func2)));
             }
 
             self.program1_unmatched.push((func1_name, func1));
             return MatchResult::Unmatched;
         }
 
+        if self.fuzzy_matches.is_none() {
+            let mut fuzzy_matches = self.find_fuzzy_matches();
+            fuzzy_matches.extend(self.find_suffix_matches());
+            self.fuzzy_matches = Some(fuzzy_matches);
+        }
+
+        if let Some(fuzzy_match) = self.fuzzy_matches.as_mut().and_then(Vec::pop) {
+            return MatchResult::Matched(fuzzy_match);
+        }
+
         MatchResult::Finished
     }
 
-    pub fn get_unmatched(self) -> (Vec<(&'a str, &'a Function)>, Vec<(&'a str, &'a Function)>) {
+    pub fn get_unmatched(self) -> (NamedFunctions<'a>, NamedFunctions<'a>) {
         let program2_unmatched = self.program2_functions.into_iter().collect();
         (self.program1_unmatched, program2_unmatched)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare::DEFAULT_NAME_SUFFIXES;
+
+    fn strip(name: &str) -> &str {
+        let suffixes: Vec<_> = DEFAULT_NAME_SUFFIXES
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        strip_name_suffixes(name, &suffixes)
+    }
+
+    #[test]
+    fn strips_known_suffixes() {
+        assert_eq!(strip("foo.part.0"), "foo");
+        assert_eq!(strip("foo.cold"), "foo");
+        assert_eq!(strip("foo.isra.0.cold"), "foo");
+        assert_eq!(strip("_ZN3Foo3barEv.llvm.123456"), "_ZN3Foo3barEv");
+    }
+
+    #[test]
+    fn keeps_other_names() {
+        assert_eq!(strip("foo"), "foo");
+        assert_eq!(strip("foo.partial"), "foo.partial");
+        assert_eq!(strip("foo.part.x"), "foo.part.x");
+        assert_eq!(strip(".cold"), ".cold");
+    }
+}
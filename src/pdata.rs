@@ -0,0 +1,78 @@
+//! The exception directory (`.pdata`) of x64 PE files, which Windows uses instead of
+//! `.eh_frame`.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+/// A `RUNTIME_FUNCTION` entry, with its addresses made absolute.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeFunction {
+    pub begin: u64,
+    /// Address just past the end of the function.
+    pub end: u64,
+    /// Address of the `UNWIND_INFO` in `.xdata`.
+    pub unwind_info: u64,
+}
+
+/// Parses the `RUNTIME_FUNCTION` entries in `data`, whose addresses are relative to
+/// `image_base`. Functions that are split up, e.g. into hot and cold parts, have an entry for
+/// each part.
+pub fn get_runtime_functions(data: &[u8], image_base: u64) -> Vec<RuntimeFunction> {
+    data.chunks_exact(12)
+        .filter_map(|mut entry| {
+            let begin = entry.read_u32::<LittleEndian>().ok()?;
+            let end = entry.read_u32::<LittleEndian>().ok()?;
+            let unwind_info = entry.read_u32::<LittleEndian>().ok()?;
+
+            // The section is padded with zeroes
+            if end <= begin {
+                return None;
+            }
+
+            Some(RuntimeFunction {
+                begin: image_base + u64::from(begin),
+                end: image_base + u64::from(end),
+                unwind_info: image_base + u64::from(unwind_info),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(begin: u32, end: u32, unwind_info: u32) -> Vec<u8> {
+        [begin, end, unwind_info]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn parses_runtime_functions() {
+        let data = [
+            entry(0x1000, 0x1020, 0x3000),
+            entry(0x1020, 0x1100, 0x3008),
+            // Padding, and a truncated entry
+            entry(0, 0, 0),
+            vec![0x00, 0x20],
+        ]
+        .concat();
+
+        assert_eq!(
+            get_runtime_functions(&data, 0x1_4000_0000),
+            [
+                RuntimeFunction {
+                    begin: 0x1_4000_1000,
+                    end: 0x1_4000_1020,
+                    unwind_info: 0x1_4000_3000,
+                },
+                RuntimeFunction {
+                    begin: 0x1_4000_1020,
+                    end: 0x1_4000_1100,
+                    unwind_info: 0x1_4000_3008,
+                },
+            ]
+        );
+    }
+}
@@ -1,8 +1,84 @@
-use crate::eh_frame::get_fdes;
-use byteorder::LittleEndian;
-use object::{Object, ObjectSection, SectionIndex};
+use crate::build_id::{content_hash, BuildStamp};
+use crate::eh_frame::{get_fdes, EhFrameBases, EhFrameError};
+use crate::error::TfbindiffError;
+use crate::instruction_wrapper::{EqualityPolicy, InstructionIter, InstructionStream};
+use crate::pdata::get_runtime_functions;
+use crate::util::ProgramInstructionFormatter;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use iced_x86::{Decoder, DecoderOptions, FlowControl, Instruction, Mnemonic, OpKind, Register};
+use object::read::archive::ArchiveFile;
+use object::{
+    Architecture, BinaryFormat, Endianness, Object, ObjectSection, ObjectSymbol, ObjectSymbolTable,
+    RelocationKind, RelocationTarget, SectionIndex, SectionKind, SymbolKind, SymbolTable,
+};
 use rustc_hash::FxHashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::fs;
 use std::io::Cursor;
+use std::ops::{ControlFlow, Range};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file isn't an object file that `object` can parse.
+    Parse(object::Error),
+    /// Functions are found through the unwind info (`.eh_frame`, or `.pdata` on Windows), or the
+    /// symbol table if there's none, so there's nothing to compare without either.
+    MissingEhFrame,
+    /// The section is compressed, but decompressing it failed or its compression isn't supported.
+    Decompress {
+        section: String,
+        error: object::Error,
+    },
+    EhFrame(EhFrameError),
+    /// The archive has no member with this name.
+    MissingArchiveMember(String),
+    /// `LoadOptions::on_function` asked to stop loading.
+    Cancelled,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Parse(error) => write!(f, "failed to parse object file: {error}"),
+            LoadError::MissingEhFrame => {
+                write!(f, "no unwind info, and no function symbols with a size")
+            }
+            LoadError::Decompress { section, error } => {
+                write!(f, "failed to decompress {section}: {error}")
+            }
+            LoadError::EhFrame(error) => write!(f, "failed to parse .eh_frame: {error}"),
+            LoadError::MissingArchiveMember(member) => write!(f, "no archive member {member}"),
+            LoadError::Cancelled => write!(f, "loading was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Parse(error) | LoadError::Decompress { error, .. } => Some(error),
+            LoadError::EhFrame(error) => Some(error),
+            LoadError::MissingEhFrame
+            | LoadError::MissingArchiveMember(_)
+            | LoadError::Cancelled => None,
+        }
+    }
+}
+
+/// Finds the data of a member of a static (`.a`) archive, to be passed to `Program::load`.
+pub fn archive_member<'a>(data: &'a [u8], member: &str) -> Result<&'a [u8], LoadError> {
+    let archive = ArchiveFile::parse(data).map_err(LoadError::Parse)?;
+    for entry in archive.members() {
+        let entry = entry.map_err(LoadError::Parse)?;
+        if entry.name() == member.as_bytes() {
+            return entry.data(data).map_err(LoadError::Parse);
+        }
+    }
+
+    Err(LoadError::MissingArchiveMember(member.to_string()))
+}
 
 pub struct Function {
     section_idx: SectionIndex,
@@ -25,25 +101,461 @@ impl Function {
     pub fn address(&self) -> u64 {
         self.address
     }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Hash of the function's mnemonics and callees, which stays the same through small changes,
+    /// see `semantic_hash::semantic_hash`.
+    pub fn semantic_hash(&self, program: &Program) -> Option<u64> {
+        crate::semantic_hash::semantic_hash(program, self)
+    }
+
+    /// Name of the section containing this function, e.g. `.text`.
+    pub fn section_name<'a>(&self, program: &'a Program) -> &'a str {
+        program
+            .section_names
+            .get(&self.section_idx)
+            .map_or("", String::as_str)
+    }
+}
+
+/// A location that the linker or loader patches with the address of a symbol.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Relocation {
+    pub symbol: String,
+    /// Number of bytes patched, 0 if the relocation kind doesn't say.
+    pub size: usize,
+    /// Added to the symbol's address, including the part stored in the patched bytes for
+    /// relocations that keep it there.
+    pub addend: i64,
+    /// Whether the patched value is relative to the location, like a `call`'s displacement.
+    pub relative: bool,
+}
+
+impl Relocation {
+    /// Offset from the symbol of the address an instruction refers to, if this relocation patches
+    /// the instruction at `location`. Relative operands are measured from the next instruction,
+    /// which the addend makes up for, so `call symbol` has an offset of 0.
+    pub fn symbol_offset(&self, location: u64, next_ip: u64) -> i64 {
+        if self.relative {
+            self.addend
+                .wrapping_add(next_ip.wrapping_sub(location) as i64)
+        } else {
+            self.addend
+        }
+    }
+}
+
+/// A function that was just loaded, see `LoadOptions::on_function`.
+pub struct LoadProgress<'a> {
+    pub name: &'a str,
+    pub function: &'a Function,
+    /// Number of functions discovered so far, including ones that were skipped (e.g. because
+    /// they have no symbol), out of `total`.
+    pub discovered: usize,
+    pub total: usize,
+}
+
+/// See `LoadOptions::on_function`.
+pub type LoadCallback<'a> = &'a dyn Fn(LoadProgress<'_>) -> ControlFlow<()>;
+
+#[derive(Default)]
+pub struct LoadOptions<'a> {
+    /// A separate debug/symbol file (e.g. from `objcopy --only-keep-debug`) whose symbol table is
+    /// merged into the program's symbols.
+    pub symbols: Option<&'a [u8]>,
+    /// Only discover functions in sections with these names. All sections are searched if empty.
+    pub code_sections: &'a [String],
+    /// Names for addresses, e.g. from `symbol_map::parse`. These take precedence over the
+    /// program's own symbols, and name the functions that don't have one.
+    pub symbol_overrides: Option<&'a FxHashMap<u64, String>>,
+    /// Called with each function as soon as it's loaded, e.g. to show progress. Returning
+    /// `ControlFlow::Break` stops loading with `LoadError::Cancelled`.
+    pub on_function: Option<LoadCallback<'a>>,
+    /// Decode the code as 16, 32 or 64-bit instead of going by the pointer size, e.g. for 32-bit
+    /// code in a 64-bit binary or 16-bit boot code.
+    pub bitness: Option<u32>,
+}
+
+/// Where a function was found, before it's matched up with a symbol.
+struct FunctionBounds {
+    begin: u64,
+    length: u64,
+    // Only known up front if addresses can't be mapped back to a section
+    section_idx: Option<SectionIndex>,
+}
+
+/// How much of the program's code is described by its unwind info. Code outside of every FDE
+/// isn't part of any function, so it's never compared.
+#[derive(Default)]
+pub struct CodeCoverage {
+    /// Size of the executable sections, in bytes.
+    pub code_size: u64,
+    /// Ranges of code that no FDE covers, with the section they're in, sorted by address.
+    /// Alignment padding between functions isn't included.
+    pub uncovered: Vec<(SectionIndex, Range<u64>)>,
+}
+
+impl CodeCoverage {
+    pub fn uncovered_size(&self) -> u64 {
+        self.uncovered
+            .iter()
+            .map(|(_, range)| range.end - range.start)
+            .sum()
+    }
 }
 
 pub struct Program {
+    pub format: BinaryFormat,
+    pub architecture: Architecture,
     pub pointer_size: usize,
+    /// Bitness the code is decoded with, the pointer size unless `LoadOptions::bitness` says
+    /// otherwise.
+    pub bitness: u32,
     pub functions: FxHashMap<String, Function>,
     pub symbol_map: FxHashMap<u64, String>,
     pub sections: FxHashMap<SectionIndex, Vec<u8>>,
+    pub section_names: FxHashMap<SectionIndex, String>,
+    /// (address, name) of every function, sorted by address. See `function_at`.
+    pub function_index: Vec<(u64, String)>,
+    /// Symbol that each relocated location refers to, keyed by the address of the location.
+    pub relocations: FxHashMap<u64, Relocation>,
+    /// Imported function that each PLT stub jumps to, keyed by the address of the stub.
+    pub plt_entries: FxHashMap<u64, String>,
+    pub build_stamp: BuildStamp,
+    /// Hash of every section's contents with `build_stamp` masked out, see `content_hash`.
+    pub content_hash: u64,
+    pub coverage: CodeCoverage,
 }
 
 impl Program {
+    pub fn build_function_index(functions: &FxHashMap<String, Function>) -> Vec<(u64, String)> {
+        let mut index: Vec<_> = functions
+            .iter()
+            .map(|(name, function)| (function.address, name.clone()))
+            .collect();
+        index.sort();
+
+        index
+    }
+
+    /// Finds the function containing `address`.
+    pub fn function_at(&self, address: u64) -> Option<(&str, &Function)> {
+        let idx = self
+            .function_index
+            .partition_point(|(function_address, _)| *function_address <= address)
+            .checked_sub(1)?;
+
+        let name = &self.function_index[idx].1;
+        let function = &self.functions[name];
+
+        (address < function.address.saturating_add(function.length as u64))
+            .then_some((name, function))
+    }
+
+    /// The other names of the function `name`, when several functions were folded into one by the
+    /// linker's identical code folding, or the function has aliases.
+    pub fn folded_with(&self, name: &str) -> Vec<&str> {
+        let Some(function) = self.functions.get(name) else {
+            return vec![];
+        };
+
+        let start = self
+            .function_index
+            .partition_point(|(address, _)| *address < function.address);
+        self.function_index[start..]
+            .iter()
+            .take_while(|(address, _)| *address == function.address)
+            .map(|(_, folded_name)| folded_name.as_str())
+            .filter(|folded_name| *folded_name != name)
+            .collect()
+    }
+
+    /// Looks up a function by its symbol, or by its demangled name if no symbol matches.
+    pub fn find_function(&self, name: &str) -> Option<(&str, &Function)> {
+        if let Some((name, function)) = self.functions.get_key_value(name) {
+            return Some((name, function));
+        }
+
+        self.functions
+            .iter()
+            .find(|(symbol, _)| crate::util::demangle_symbol(symbol).as_deref() == Some(name))
+            .map(|(symbol, function)| (symbol.as_str(), function))
+    }
+
+    /// Describes an address relative to the symbol it's in, as (symbol address, symbol name).
+    /// Unlike the raw address, this stays the same when code is moved around between builds.
+    pub fn symbolize(&self, address: u64) -> Option<(u64, &str)> {
+        if let Some(name) = self.symbol_map.get(&address) {
+            return Some((address, name));
+        }
+
+        self.function_at(address)
+            .map(|(name, function)| (function.address(), name))
+    }
+
+    /// The relocation that patches in `operand` of `instr`, and the address it patches.
+    pub fn operand_relocation(
+        &self,
+        instr: &Instruction,
+        operand: u32,
+    ) -> Option<(u64, &Relocation)> {
+        // Finding the operand's bytes takes decoding the instruction again, most don't need it
+        if !(instr.ip()..instr.next_ip()).any(|location| self.relocations.contains_key(&location)) {
+            return None;
+        }
+
+        let code = self.code_bytes(instr.ip(), instr.len())?;
+        let mut decoder = Decoder::with_ip(self.bitness, code, instr.ip(), DecoderOptions::NONE);
+        let decoded = decoder.decode();
+        let offsets = decoder.get_constant_offsets(&decoded);
+        let offset = match instr.op_kind(operand) {
+            OpKind::Memory if offsets.has_displacement() => offsets.displacement_offset(),
+            OpKind::Immediate8_2nd if offsets.has_immediate2() => offsets.immediate_offset2(),
+            // Branch displacements count as immediates
+            OpKind::NearBranch16
+            | OpKind::NearBranch32
+            | OpKind::NearBranch64
+            | OpKind::FarBranch16
+            | OpKind::FarBranch32
+            | OpKind::Immediate8
+            | OpKind::Immediate16
+            | OpKind::Immediate32
+            | OpKind::Immediate64
+            | OpKind::Immediate8to16
+            | OpKind::Immediate8to32
+            | OpKind::Immediate8to64
+            | OpKind::Immediate32to64
+                if offsets.has_immediate() =>
+            {
+                offsets.immediate_offset()
+            }
+            _ => return None,
+        };
+
+        let location = instr.ip() + offset as u64;
+        self.relocations
+            .get(&location)
+            .map(|relocation| (location, relocation))
+    }
+
+    /// The part of a relocation's addend that's stored in the `size` bytes it patches at `offset`
+    /// in `data`.
+    fn implicit_addend(data: &[u8], offset: u64, size: usize, endianness: Endianness) -> i64 {
+        fn read<E: ByteOrder>(mut field: &[u8]) -> Option<i64> {
+            match field.len() {
+                1 => field.read_i8().ok().map(i64::from),
+                2 => field.read_i16::<E>().ok().map(i64::from),
+                4 => field.read_i32::<E>().ok().map(i64::from),
+                8 => field.read_i64::<E>().ok(),
+                _ => None,
+            }
+        }
+
+        let Some(field) = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| data.get(offset..offset.checked_add(size)?))
+        else {
+            return 0;
+        };
+        match endianness {
+            Endianness::Little => read::<LittleEndian>(field),
+            Endianness::Big => read::<BigEndian>(field),
+        }
+        .unwrap_or(0)
+    }
+
+    fn collect_relocations(
+        object: &object::File<'_>,
+        symbol_map: &FxHashMap<u64, String>,
+    ) -> FxHashMap<u64, Relocation> {
+        // Dynamic relocations refer to the dynamic symbol table, the others to the regular one
+        let target_name = |target: RelocationTarget, symbols: Option<&SymbolTable<'_, '_>>| {
+            let RelocationTarget::Symbol(symbol_idx) = target else {
+                return None;
+            };
+
+            symbols?
+                .symbol_by_index(symbol_idx)
+                .ok()
+                .and_then(|symbol| symbol.name().ok())
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+        };
+        let symbol_table = object.symbol_table();
+        let dynamic_symbol_table = object.dynamic_symbol_table();
+
+        // Relocations are only kept in the sections of relocatable objects (or with --emit-relocs),
+        // dynamic relocations are mostly imports
+        let section_relocations = object
+            .sections()
+            .filter(|section| section.kind() == SectionKind::Text)
+            .flat_map(|section| {
+                let section_address = section.address();
+                let data = section.data().unwrap_or_default();
+                section.relocations().map(move |(offset, relocation)| {
+                    let implicit_addend = if relocation.has_implicit_addend() {
+                        let size = usize::from(relocation.size()) / 8;
+                        Self::implicit_addend(data, offset, size, object.endianness())
+                    } else {
+                        0
+                    };
+                    (section_address + offset, relocation, implicit_addend)
+                })
+            });
+
+        // The addends of dynamic relocations are only kept in the patched bytes on 32-bit
+        // systems, where those relocations patch data rather than code
+        let dynamic_relocations = object
+            .dynamic_relocations()
+            .into_iter()
+            .flatten()
+            .map(|(address, relocation)| (address, relocation, 0, dynamic_symbol_table.as_ref()));
+
+        section_relocations
+            .map(|(address, relocation, implicit_addend)| {
+                (address, relocation, implicit_addend, symbol_table.as_ref())
+            })
+            .chain(dynamic_relocations)
+            .filter_map(|(address, relocation, implicit_addend, symbols)| {
+                let size = usize::from(relocation.size()) / 8;
+                let relocation = match target_name(relocation.target(), symbols) {
+                    Some(symbol) => Relocation {
+                        symbol,
+                        size,
+                        addend: relocation.addend().wrapping_add(implicit_addend),
+                        relative: matches!(
+                            relocation.kind(),
+                            RelocationKind::Relative
+                                | RelocationKind::GotRelative
+                                | RelocationKind::PltRelative
+                        ),
+                    },
+                    // e.g. R_X86_64_RELATIVE, which only has an addend
+                    None => {
+                        let target = u64::try_from(relocation.addend()).ok()?;
+                        Relocation {
+                            symbol: symbol_map.get(&target)?.clone(),
+                            size,
+                            addend: 0,
+                            relative: false,
+                        }
+                    }
+                };
+
+                Some((address, relocation))
+            })
+            .collect()
+    }
+
+    /// Finds the PLT stubs that jump through a GOT slot, e.g. `jmp [rip+x]` or
+    /// `endbr64; bnd jmp [rip+x]`, and names them after the import the slot is relocated to.
+    fn collect_plt_entries(
+        object: &object::File<'_>,
+        pointer_size: usize,
+        relocations: &FxHashMap<u64, Relocation>,
+    ) -> FxHashMap<u64, String> {
+        if !matches!(
+            object.architecture(),
+            Architecture::I386 | Architecture::X86_64 | Architecture::X86_64_X32
+        ) {
+            return FxHashMap::default();
+        }
+
+        let mut plt_entries = FxHashMap::default();
+        let plt_sections = object
+            .sections()
+            .filter(|section| section.name().is_ok_and(|name| name.starts_with(".plt")));
+        for section in plt_sections {
+            let Ok(data) = section.data() else {
+                continue;
+            };
+
+            let mut decoder = Decoder::with_ip(
+                (pointer_size * 8) as u32,
+                data,
+                section.address(),
+                DecoderOptions::NONE,
+            );
+            let mut previous: Option<Instruction> = None;
+            for instr in &mut decoder {
+                let is_got_jump = instr.flow_control() == FlowControl::IndirectBranch
+                    && instr.op0_kind() == OpKind::Memory
+                    && instr.memory_index() == Register::None
+                    && matches!(
+                        instr.memory_base(),
+                        Register::RIP | Register::EIP | Register::None
+                    );
+                let import = is_got_jump
+                    .then(|| relocations.get(&instr.memory_displacement64()))
+                    .flatten();
+
+                if let Some(import) = import {
+                    // The stub starts at the endbr, if there is one
+                    let stub = match previous {
+                        Some(previous)
+                            if matches!(
+                                previous.mnemonic(),
+                                Mnemonic::Endbr64 | Mnemonic::Endbr32
+                            ) && previous.next_ip() == instr.ip() =>
+                        {
+                            previous.ip()
+                        }
+                        _ => instr.ip(),
+                    };
+                    plt_entries.insert(stub, import.symbol.clone());
+                }
+
+                previous = Some(instr);
+            }
+        }
+
+        log::debug!("found {} PLT entries", plt_entries.len());
+        plt_entries
+    }
+
+    /// Disassembles a function, with one `address\tinstruction` line per instruction and operands
+    /// resolved to symbols. Returns `None` if there's no function called `name`.
+    pub fn disassemble_function(&self, name: &str) -> Option<Vec<String>> {
+        let function = self.functions.get(name)?;
+        let instructions: Vec<_> =
+            crate::compare::create_instruction_iter(self, function, Default::default()).collect();
+
+        let mut formatter = ProgramInstructionFormatter::new(self);
+        formatter.set_function(&instructions);
+
+        Some(
+            instructions
+                .iter()
+                .map(|instr| format!("{:08x}\t{}", instr.get().ip(), formatter.format(instr)))
+                .collect(),
+        )
+    }
+
     pub fn get_data_for_function(&self, function: &Function) -> Option<&[u8]> {
         let section = self
             .sections
             .get(&function.section_idx)
             .expect("Section Index should never be invalid");
 
+        // Don't trust the length to stay within the section, it comes from the unwind info
         let relative_address = (function.address - function.section_base) as usize;
+        let end = relative_address
+            .saturating_add(function.length)
+            .min(section.len());
 
-        Some(&section[relative_address..relative_address + function.length])
+        section.get(relative_address..end)
+    }
+
+    /// The code at `address`, e.g. the bytes of a single instruction. Only covers functions.
+    pub fn code_bytes(&self, address: u64, length: usize) -> Option<&[u8]> {
+        let (_, function) = self.function_at(address)?;
+        let data = self.get_data_for_function(function)?;
+        let start = (address - function.address()) as usize;
+
+        data.get(start..start.saturating_add(length))
     }
 
     fn get_section_for_data(
@@ -61,65 +573,690 @@ impl Program {
         None
     }
 
-    pub fn load(data: &[u8]) -> Self {
-        let object = object::File::parse(data).unwrap();
+    /// Whether the program's code can be disassembled. Functions of other programs can still be
+    /// compared, but only by their bytes.
+    pub fn can_disassemble(&self) -> bool {
+        matches!(
+            self.architecture,
+            Architecture::I386 | Architecture::X86_64 | Architecture::X86_64_X32
+        )
+    }
+
+    /// Decodes the `length` bytes of code at `address` with the decoder for the program's
+    /// architecture, comparing the instructions with `policy`. Returns `None` if the program
+    /// can't be disassembled.
+    pub fn decode<'a>(
+        &self,
+        address: u64,
+        code: &'a [u8],
+        length: usize,
+        policy: EqualityPolicy,
+    ) -> Option<Box<dyn InstructionStream + 'a>> {
+        match self.architecture {
+            Architecture::I386 | Architecture::X86_64 | Architecture::X86_64_X32 => Some(Box::new(
+                InstructionIter::new(address, code, length, self.bitness as usize / 8)
+                    .policy(policy),
+            )),
+            _ => None,
+        }
+    }
 
-        let pointer_size = if object.is_64() { 8 } else { 4 };
+    /// Functions in the symbol tables, for programs without unwind info. Symbols without a size
+    /// are left out, since where they end isn't known.
+    fn functions_from_symbols(object: &object::File<'_>) -> Vec<FunctionBounds> {
+        let mut functions: Vec<_> = object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .filter(|symbol| {
+                symbol.kind() == SymbolKind::Text && symbol.is_definition() && symbol.size() != 0
+            })
+            .filter_map(|symbol| {
+                Some(FunctionBounds {
+                    begin: symbol.address(),
+                    length: symbol.size(),
+                    section_idx: Some(symbol.section_index()?),
+                })
+            })
+            .collect();
+        // Aliases, and functions in both symbol tables, would be loaded twice
+        functions.sort_by_key(|bounds| (bounds.section_idx.map(|idx| idx.0), bounds.begin));
+        functions.dedup_by_key(|bounds| (bounds.section_idx, bounds.begin));
 
-        let eh_frame = object.section_by_name(".eh_frame").unwrap();
-        let eh_frame_data = eh_frame.uncompressed_data().unwrap();
+        functions
+    }
 
-        // FIXME: not that it actually matters, but endian shouldn't be hardcoded
-        let fdes = get_fdes::<LittleEndian, _>(
-            &mut Cursor::new(eh_frame_data),
-            pointer_size,
-            eh_frame.address(),
+    /// Functions in `.eh_frame`, which ELF and Mach-O files have.
+    fn functions_from_eh_frame(
+        object: &object::File<'_>,
+        eh_frame: &object::Section<'_, '_>,
+        pointer_size: usize,
+    ) -> Result<Vec<FunctionBounds>, LoadError> {
+        let eh_frame_data =
+            eh_frame
+                .uncompressed_data()
+                .map_err(|error| LoadError::Decompress {
+                    section: ".eh_frame".to_string(),
+                    error,
+                })?;
+
+        let mut data = Cursor::new(eh_frame_data);
+        let bases = EhFrameBases::new(object, eh_frame.address());
+        Ok(match object.endianness() {
+            Endianness::Little => get_fdes::<LittleEndian, _>(&mut data, pointer_size, &bases),
+            Endianness::Big => get_fdes::<BigEndian, _>(&mut data, pointer_size, &bases),
+        }
+        .map_err(LoadError::EhFrame)?
+        .fdes
+        .into_iter()
+        .map(|fde| FunctionBounds {
+            begin: fde.begin,
+            length: fde.length,
+            section_idx: None,
+        })
+        .collect())
+    }
+
+    /// Functions in the exception directory of an x64 PE file.
+    fn functions_from_pdata(
+        object: &object::File<'_>,
+        pdata: &object::Section<'_, '_>,
+    ) -> Result<Vec<FunctionBounds>, LoadError> {
+        let pdata_data = pdata
+            .uncompressed_data()
+            .map_err(|error| LoadError::Decompress {
+                section: ".pdata".to_string(),
+                error,
+            })?;
+
+        Ok(
+            get_runtime_functions(&pdata_data, object.relative_address_base())
+                .into_iter()
+                .map(|function| FunctionBounds {
+                    begin: function.begin,
+                    length: function.end - function.begin,
+                    section_idx: None,
+                })
+                .collect(),
         )
-        .unwrap();
+    }
+
+    fn discover_functions(
+        object: &object::File<'_>,
+        pointer_size: usize,
+    ) -> Result<Vec<FunctionBounds>, LoadError> {
+        let unwind_functions = match object.format() {
+            // WASM has no unwind info, but every function body is a symbol in the code section.
+            // Addresses are offsets into that section.
+            BinaryFormat::Wasm => return Ok(Self::functions_from_symbols(object)),
+            // x64 Windows keeps its unwind info in .pdata. 32-bit Windows has none, unless it was
+            // built by MinGW, which uses .eh_frame
+            BinaryFormat::Pe if object.architecture() == Architecture::X86_64 => object
+                .section_by_name(".pdata")
+                .map(|pdata| Self::functions_from_pdata(object, &pdata))
+                .transpose()?,
+            _ => object
+                .section_by_name(".eh_frame")
+                .map(|eh_frame| Self::functions_from_eh_frame(object, &eh_frame, pointer_size))
+                .transpose()?,
+        };
+        if let Some(functions) = unwind_functions {
+            return Ok(functions);
+        }
+
+        // Stripped of unwind info, or built with -fno-asynchronous-unwind-tables
+        let functions = Self::functions_from_symbols(object);
+        if functions.is_empty() {
+            return Err(LoadError::MissingEhFrame);
+        }
+        log::info!(
+            "no unwind info, using the {} functions in the symbol table",
+            functions.len()
+        );
+
+        Ok(functions)
+    }
+
+    /// Whether `code` at `address` is only there to align the next function: zeroes, or on x86,
+    /// nothing but `nop`s and `int3`s.
+    fn is_padding(architecture: Architecture, bitness: u32, address: u64, code: &[u8]) -> bool {
+        if code.iter().all(|byte| *byte == 0) {
+            return true;
+        }
+        if !matches!(
+            architecture,
+            Architecture::I386 | Architecture::X86_64 | Architecture::X86_64_X32
+        ) {
+            return false;
+        }
+
+        // A padding instruction that's cut off decodes as invalid, so that's not padding either
+        Decoder::with_ip(bitness, code, address, DecoderOptions::NONE)
+            .into_iter()
+            .all(|instr| matches!(instr.mnemonic(), Mnemonic::Nop | Mnemonic::Int3))
+    }
+
+    /// Finds the code in executable sections that isn't covered by any of `function_bounds`.
+    fn find_coverage(
+        object: &object::File<'_>,
+        function_bounds: &[FunctionBounds],
+        code_sections: &[String],
+        bitness: u32,
+    ) -> CodeCoverage {
+        let mut coverage = CodeCoverage::default();
+
+        for section in object.sections() {
+            let name = section.name().unwrap_or_default();
+            if section.kind() != SectionKind::Text
+                || section.file_range().is_none()
+                || (!code_sections.is_empty() && !code_sections.iter().any(|code| code == name))
+            {
+                continue;
+            }
+            let Ok(data) = section.uncompressed_data() else {
+                log::warn!("can't check the coverage of {name}, it failed to decompress");
+                continue;
+            };
+
+            let start = section.address();
+            let end = start + section.size();
+            let mut covered: Vec<_> = function_bounds
+                .iter()
+                .filter(|bounds| match bounds.section_idx {
+                    Some(section_idx) => section_idx == section.index(),
+                    None => bounds.begin >= start && bounds.begin < end,
+                })
+                // The length comes from the unwind info, so it can be anything
+                .map(|bounds| bounds.begin..bounds.begin.saturating_add(bounds.length).min(end))
+                .collect();
+            covered.sort_by_key(|range| range.start);
+
+            let mut gaps = vec![];
+            let mut cursor = start;
+            for range in covered {
+                if range.start > cursor {
+                    gaps.push(cursor..range.start);
+                }
+                cursor = cursor.max(range.end);
+            }
+            if cursor < end {
+                gaps.push(cursor..end);
+            }
+
+            coverage.code_size += section.size();
+            coverage.uncovered.extend(
+                gaps.into_iter()
+                    .filter(|gap| {
+                        let offset = (gap.start - start) as usize;
+                        let code = data.get(offset..offset + (gap.end - gap.start) as usize);
+                        !code.is_some_and(|code| {
+                            Self::is_padding(object.architecture(), bitness, gap.start, code)
+                        })
+                    })
+                    .map(|gap| (section.index(), gap)),
+            );
+        }
+        coverage.uncovered.sort_by_key(|(_, range)| range.start);
+
+        coverage
+    }
+
+    pub fn load(data: &[u8]) -> Result<Self, LoadError> {
+        Self::load_with_options(data, &LoadOptions::default())
+    }
+
+    /// Reads and loads the program at `path`.
+    pub fn load_path(path: &Path, options: &LoadOptions) -> Result<Self, TfbindiffError> {
+        let data = fs::read(path)?;
+        Ok(Self::load_with_options(&data, options)?)
+    }
+
+    pub fn load_with_options(data: &[u8], options: &LoadOptions) -> Result<Self, LoadError> {
+        let object = object::File::parse(data).map_err(LoadError::Parse)?;
+
+        let pointer_size = if object.is_64() { 8 } else { 4 };
+        let bitness = options.bitness.unwrap_or(pointer_size as u32 * 8);
+        let function_bounds = Self::discover_functions(&object, pointer_size)?;
+        let coverage =
+            Self::find_coverage(&object, &function_bounds, options.code_sections, bitness);
 
         let mut functions: FxHashMap<String, Function> = FxHashMap::default();
-        let symbol_map: FxHashMap<u64, String> = object
+        let mut symbol_map: FxHashMap<u64, String> = object
             .symbol_map()
             .symbols()
             .iter()
             .map(|s| (s.address(), s.name().to_string()))
             .collect();
+        // Functions folded by the linker (--icf), and aliases, have several symbols. Each of them
+        // is loaded as a function of its own, so it can be matched by any of its names.
+        // `symbol_map` only keeps one of them.
+        let mut folded_names: FxHashMap<u64, Vec<String>> = FxHashMap::default();
+        let function_symbols = object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.is_definition());
+        for symbol in function_symbols {
+            let Ok(name) = symbol.name() else {
+                continue;
+            };
+            let names = folded_names.entry(symbol.address()).or_default();
+            if !name.is_empty() && !names.iter().any(|known| known == name) {
+                names.push(name.to_string());
+            }
+        }
+        folded_names.retain(|_, names| names.len() > 1);
 
-        let mut sections = FxHashMap::default();
-        for fde in fdes {
-            if let Some(name) = symbol_map.get(&fde.begin) {
-                let (section_base, section_idx) =
-                    Self::get_section_for_data(&object, fde.begin).unwrap();
+        if let Some(symbols) = options.symbols {
+            let symbol_object = object::File::parse(symbols).map_err(LoadError::Parse)?;
+            for symbol in symbol_object.symbol_map().symbols() {
+                symbol_map
+                    .entry(symbol.address())
+                    .or_insert_with(|| symbol.name().to_string());
+            }
+        }
+        if let Some(overrides) = options.symbol_overrides {
+            folded_names.retain(|address, _| !overrides.contains_key(address));
+            symbol_map.extend(
+                overrides
+                    .iter()
+                    .map(|(address, name)| (*address, name.clone())),
+            );
+        }
+
+        let section_names: FxHashMap<SectionIndex, String> = object
+            .sections()
+            .map(|section| {
+                (
+                    section.index(),
+                    section.name().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+
+        // Windows programs usually keep their symbols in a separate PDB, but every function in
+        // .pdata is worth comparing, so the ones without a symbol get named after their address
+        if object.format() == BinaryFormat::Pe {
+            for bounds in &function_bounds {
+                symbol_map
+                    .entry(bounds.begin)
+                    .or_insert_with(|| synthetic_name(bounds.begin));
+            }
+        }
 
-                if !sections.contains_key(&section_idx) {
-                    sections.insert(
+        let mut sections = FxHashMap::default();
+        let mut unnamed = 0;
+        let total = function_bounds.len();
+        for (idx, bounds) in function_bounds.into_iter().enumerate() {
+            if let Some(name) = symbol_map.get(&bounds.begin) {
+                let (section_base, section_idx) = match bounds.section_idx {
+                    Some(section_idx) => (
+                        object.section_by_index(section_idx).unwrap().address(),
                         section_idx,
-                        object
-                            .section_by_index(section_idx)
-                            .unwrap()
-                            .uncompressed_data()
-                            .unwrap()
-                            .to_vec(),
-                    );
+                    ),
+                    None => match Self::get_section_for_data(&object, bounds.begin) {
+                        Some(section) => section,
+                        // Corrupt unwind info, or a section `object` doesn't know about
+                        None => {
+                            log::warn!(
+                                "skipping {name}, its FDE starts at {:08x} which isn't in any section",
+                                bounds.begin
+                            );
+                            continue;
+                        }
+                    },
                 };
 
-                functions.insert(
-                    name.to_string(),
-                    Function::new(section_idx, section_base, fde.begin, fde.length),
-                );
+                // .bss and the like only have a size, their "code" would be all zeroes
+                let section = object.section_by_index(section_idx).unwrap();
+                if section.file_range().is_none() {
+                    log::warn!(
+                        "skipping {name}, it's in {} which has no data in the file",
+                        section_names[&section_idx]
+                    );
+                    continue;
+                }
+
+                if !options.code_sections.is_empty() {
+                    let section_name = &section_names[&section_idx];
+                    if !options
+                        .code_sections
+                        .iter()
+                        .any(|code_section| code_section == section_name)
+                    {
+                        log::debug!("skipping {name}, it's in {section_name}");
+                        continue;
+                    }
+                }
+
+                if let Entry::Vacant(entry) = sections.entry(section_idx) {
+                    let data =
+                        section
+                            .uncompressed_data()
+                            .map_err(|error| LoadError::Decompress {
+                                section: section_names[&section_idx].clone(),
+                                error,
+                            })?;
+                    entry.insert(data.to_vec());
+                }
+
+                let function =
+                    Function::new(section_idx, section_base, bounds.begin, bounds.length);
+                if let Some(on_function) = options.on_function {
+                    let progress = LoadProgress {
+                        name,
+                        function: &function,
+                        discovered: idx + 1,
+                        total,
+                    };
+                    if on_function(progress).is_break() {
+                        return Err(LoadError::Cancelled);
+                    }
+                }
+                functions.insert(name.to_string(), function);
+
+                for folded_name in folded_names.get(&bounds.begin).into_iter().flatten() {
+                    if folded_name != name {
+                        functions.insert(
+                            folded_name.clone(),
+                            Function::new(section_idx, section_base, bounds.begin, bounds.length),
+                        );
+                    }
+                }
             } else {
-                println!(
+                log::debug!(
                     "function {:08x} (length {:08x}) has no symbol",
-                    fde.begin, fde.length
+                    bounds.begin,
+                    bounds.length
                 );
+                unnamed += 1;
             }
         }
+        if unnamed > 0 {
+            log::warn!("skipped {unnamed} functions that have no symbol");
+        }
 
-        Self {
+        log::debug!(
+            "loaded {} functions from {} sections",
+            functions.len(),
+            sections.len()
+        );
+
+        let function_index = Self::build_function_index(&functions);
+        let relocations = Self::collect_relocations(&object, &symbol_map);
+        let plt_entries = Self::collect_plt_entries(&object, pointer_size, &relocations);
+        let build_stamp = BuildStamp::read(&object, data);
+        let content_hash = content_hash(&object, &build_stamp);
+
+        Ok(Self {
+            format: object.format(),
+            architecture: object.architecture(),
             pointer_size,
+            bitness,
             functions,
             sections,
+            section_names,
             symbol_map,
+            function_index,
+            relocations,
+            plt_entries,
+            build_stamp,
+            content_hash,
+            coverage,
+        })
+    }
+}
+
+/// Name for a function that has no symbol.
+fn synthetic_name(address: u64) -> String {
+    format!("sub_{address:08x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program with a single function `work` at 0x1000, in a section that holds `code`.
+    fn program(length: u64, code: Vec<u8>) -> Program {
+        let section_idx = SectionIndex(1);
+        let functions: FxHashMap<_, _> = [(
+            "work".to_string(),
+            Function::new(section_idx, 0x1000, 0x1000, length),
+        )]
+        .into_iter()
+        .collect();
+        let function_index = Program::build_function_index(&functions);
+
+        Program {
+            format: BinaryFormat::Elf,
+            architecture: Architecture::X86_64,
+            pointer_size: 8,
+            bitness: 64,
+            functions,
+            symbol_map: FxHashMap::default(),
+            sections: [(section_idx, code)].into_iter().collect(),
+            section_names: [(section_idx, ".text".to_string())].into_iter().collect(),
+            function_index,
+            relocations: FxHashMap::default(),
+            plt_entries: FxHashMap::default(),
+            build_stamp: BuildStamp::default(),
+            content_hash: 0,
+            coverage: CodeCoverage::default(),
         }
     }
+
+    #[test]
+    fn huge_lengths_dont_overflow() {
+        // The length comes from the unwind info, so it can be anything
+        let program = program(u64::MAX, vec![0x90; 0x10]);
+
+        let (name, function) = program.function_at(0xffff_ffff_0000_0000).unwrap();
+        assert_eq!(name, "work");
+        assert_eq!(program.get_data_for_function(function).unwrap().len(), 0x10);
+        assert_eq!(program.code_bytes(0x1008, usize::MAX), None);
+        assert_eq!(program.code_bytes(0x1008, 2), Some(&[0x90, 0x90][..]));
+
+        // Decoding stops at the end of the section instead
+        let instructions =
+            crate::compare::create_instruction_iter(&program, function, Default::default());
+        assert_eq!(instructions.count(), 0x10);
+    }
+
+    #[test]
+    fn disassembles_borrowed_program() {
+        // mov eax, [rip+0xa]; call work; ret, with `data` after it
+        let code = [
+            &[
+                0x8B, 0x05, 0x0A, 0x00, 0x00, 0x00, 0xE8, 0xF5, 0xFF, 0xFF, 0xFF, 0xC3,
+            ][..],
+            &[0; 4],
+            &[0; 4],
+        ]
+        .concat();
+        let mut program = program(0xC, code);
+        program.symbol_map.insert(0x1010, "data".to_string());
+
+        assert_eq!(
+            program.disassemble_function("work").unwrap(),
+            [
+                "00001000\tmov eax,[data]",
+                "00001006\tcall work",
+                "0000100b\tret"
+            ]
+        );
+        assert_eq!(program.disassemble_function("main"), None);
+
+        // The same operand with a 32-bit absolute address
+        program.bitness = 32;
+        program.pointer_size = 4;
+        program.sections.insert(
+            SectionIndex(1),
+            [&[0xA1, 0x10, 0x10, 0x00, 0x00, 0xC3][..], &[0; 10], &[0; 4]].concat(),
+        );
+        program.functions.get_mut("work").unwrap().length = 6;
+        assert_eq!(
+            program.disassemble_function("work").unwrap(),
+            ["00001000\tmov eax,[data]", "00001005\tret"]
+        );
+    }
+
+    #[test]
+    fn relocations_name_their_operand() {
+        let code = [
+            // mov [rip+0], ebx
+            &[0x89, 0x1D, 0x00, 0x00, 0x00, 0x00][..],
+            // mov dword [rip+0], 7
+            &[0xC7, 0x05, 0x00, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00],
+            // call 0
+            &[0xE8, 0x00, 0x00, 0x00, 0x00],
+            // lea rax, [rip+0]
+            &[0x48, 0x8D, 0x05, 0x00, 0x00, 0x00, 0x00],
+            // ret
+            &[0xC3],
+        ]
+        .concat();
+        let mut program = program(code.len() as u64, code);
+        let relative = |symbol: &str, addend| Relocation {
+            symbol: symbol.to_string(),
+            size: 4,
+            addend,
+            relative: true,
+        };
+        program.relocations = [
+            (0x1002, relative("table", 8)),
+            // The immediate that follows is part of the distance to the next instruction
+            (0x1008, relative("other", 0)),
+            (0x1011, relative("callee", -4)),
+            (0x1018, relative("table", -8)),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            program.disassemble_function("work").unwrap(),
+            [
+                "00001000\tmov [table+0Ch],ebx",
+                "00001006\tmov dword ptr [other+8],7",
+                "00001010\tcall callee",
+                "00001015\tlea rax,[table-4]",
+                "0000101c\tret"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_implicit_addends() {
+        let data = [0x90, 0xFC, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x10];
+        assert_eq!(
+            Program::implicit_addend(&data, 1, 4, Endianness::Little),
+            -4
+        );
+        assert_eq!(Program::implicit_addend(&data, 5, 4, Endianness::Big), 0x10);
+        // Cut off, or a size that isn't a whole integer
+        assert_eq!(Program::implicit_addend(&data, 6, 4, Endianness::Little), 0);
+        assert_eq!(Program::implicit_addend(&data, 1, 3, Endianness::Little), 0);
+    }
+
+    /// An x64 PE image based at 0x1_4000_0000 with no symbols, and a function at 0x1000 and
+    /// 0x1010 in `.pdata`.
+    fn stripped_pe() -> Vec<u8> {
+        fn section(name: &[u8; 8], address: u32, offset: u32, characteristics: u32) -> Vec<u8> {
+            [
+                name.to_vec(),
+                // Size and address in memory, then in the file
+                [0x200, address, 0x200, offset, 0, 0]
+                    .iter()
+                    .flat_map(|value: &u32| value.to_le_bytes())
+                    .collect(),
+                // No relocations or line numbers
+                vec![0; 4],
+                characteristics.to_le_bytes().to_vec(),
+            ]
+            .concat()
+        }
+
+        let mut dos_header = vec![0; 0x40];
+        dos_header[..2].copy_from_slice(b"MZ");
+        dos_header[0x3C] = 0x40;
+
+        let mut file_header = vec![];
+        file_header.extend(0x8664u16.to_le_bytes()); // x64
+        file_header.extend(2u16.to_le_bytes()); // Sections
+        file_header.extend([0; 12]); // Timestamp and symbol table
+        file_header.extend(240u16.to_le_bytes()); // Optional header size
+        file_header.extend(0x22u16.to_le_bytes()); // Executable, large address aware
+
+        let mut optional_header = vec![];
+        optional_header.extend(0x20Bu16.to_le_bytes()); // PE32+
+        optional_header.extend([0; 22]); // Linker version, sizes, entry point
+        optional_header.extend(0x1_4000_0000u64.to_le_bytes());
+        optional_header.extend(0x1000u32.to_le_bytes()); // Section alignment
+        optional_header.extend(0x200u32.to_le_bytes()); // File alignment
+        optional_header.extend([0; 16]); // Versions
+        optional_header.extend(0x3000u32.to_le_bytes()); // Image size
+        optional_header.extend(0x200u32.to_le_bytes()); // Headers size
+        optional_header.extend([0; 4]); // Checksum
+        optional_header.extend(3u16.to_le_bytes()); // Console subsystem
+        optional_header.extend([0; 38]); // DLL characteristics, stack and heap, loader flags
+        optional_header.extend(16u32.to_le_bytes()); // Data directories, all empty
+        optional_header.extend([0; 16 * 8]);
+
+        let mut headers = [
+            dos_header,
+            b"PE\0\0".to_vec(),
+            file_header,
+            optional_header,
+            section(b".text\0\0\0", 0x1000, 0x200, 0x6000_0020),
+            section(b".pdata\0\0", 0x2000, 0x400, 0x4000_0040),
+        ]
+        .concat();
+        headers.resize(0x200, 0);
+
+        // xor eax, eax; ret, padded with int3
+        let mut text = vec![0xCC; 0x200];
+        text[..3].copy_from_slice(&[0x31, 0xC0, 0xC3]);
+        text[0x10..0x13].copy_from_slice(&[0x31, 0xC0, 0xC3]);
+
+        let mut pdata: Vec<u8> = [0x1000u32, 0x1003, 0x2100, 0x1010, 0x1013, 0x2100]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        pdata.resize(0x200, 0);
+
+        [headers, text, pdata].concat()
+    }
+
+    #[test]
+    fn pdata_functions_without_symbols_are_named() {
+        let program = Program::load(&stripped_pe()).unwrap();
+
+        let mut names: Vec<_> = program.functions.keys().collect();
+        names.sort();
+        assert_eq!(names, ["sub_140001000", "sub_140001010"]);
+
+        let (name, function) = program.function_at(0x1_4000_1011).unwrap();
+        assert_eq!(name, "sub_140001010");
+        assert_eq!(
+            program.get_data_for_function(function),
+            Some(&[0x31, 0xC0, 0xC3][..])
+        );
+    }
+
+    #[test]
+    fn coverage_of_huge_lengths() {
+        let data = stripped_pe();
+        let object = object::File::parse(&*data).unwrap();
+        let bounds = [FunctionBounds {
+            begin: 0x1_4000_1010,
+            length: u64::MAX,
+            section_idx: None,
+        }];
+
+        let coverage = Program::find_coverage(&object, &bounds, &[], 64);
+        assert_eq!(coverage.code_size, 0x200);
+        let uncovered: Vec<_> = coverage
+            .uncovered
+            .iter()
+            .map(|(_, range)| (range.start, range.end))
+            .collect();
+        assert_eq!(uncovered, [(0x1_4000_1000, 0x1_4000_1010)]);
+    }
 }
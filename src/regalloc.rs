@@ -0,0 +1,123 @@
+use crate::instruction_wrapper::{EqualityPolicy, InstructionWrapper};
+use iced_x86::{InstructionInfoFactory, OpKind, Register};
+use rustc_hash::FxHashMap;
+
+/// A consistent bijection between the registers used by two functions.
+///
+/// Registers are mapped by their full register (e.g. `al`, `ax`, `eax` and `rax` all map through
+/// `rax`), so a function that uses `ebx` where the other uses `esi` is only considered equal if it
+/// does so everywhere.
+#[derive(Default)]
+pub struct RegisterMapping {
+    forward: FxHashMap<Register, Register>,
+    backward: FxHashMap<Register, Register>,
+}
+
+impl RegisterMapping {
+    fn is_renameable(register: Register) -> bool {
+        let full = register.full_register();
+
+        // The stack and instruction pointers are never chosen by the register allocator
+        (register.is_gpr() || register.is_vector_register())
+            && !matches!(
+                full,
+                Register::RSP | Register::ESP | Register::RIP | Register::EIP
+            )
+    }
+
+    fn is_high_byte(register: Register) -> bool {
+        matches!(
+            register,
+            Register::AH | Register::BH | Register::CH | Register::DH
+        )
+    }
+
+    fn bind(&mut self, register1: Register, register2: Register) -> bool {
+        if register1 == register2 && !Self::is_renameable(register1) {
+            return true;
+        }
+
+        if !Self::is_renameable(register1)
+            || !Self::is_renameable(register2)
+            || register1.size() != register2.size()
+            || Self::is_high_byte(register1) != Self::is_high_byte(register2)
+        {
+            return false;
+        }
+
+        let (full1, full2) = (register1.full_register(), register2.full_register());
+        match (self.forward.get(&full1), self.backward.get(&full2)) {
+            (Some(&mapped2), Some(&mapped1)) => mapped2 == full2 && mapped1 == full1,
+            (None, None) => {
+                self.forward.insert(full1, full2);
+                self.backward.insert(full2, full1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn bind_instructions(
+        &mut self,
+        factory: &mut InstructionInfoFactory,
+        policy: &EqualityPolicy,
+        instr1: &InstructionWrapper,
+        instr2: &InstructionWrapper,
+    ) -> bool {
+        // Renaming registers doesn't change constants, offsets and the like
+        if !instr1.values_eq_with(instr2, policy) {
+            return false;
+        }
+        let (instr1, instr2) = (instr1.get(), instr2.get());
+
+        for op_idx in 0..instr1.op_count() {
+            let bound = match instr1.op_kind(op_idx) {
+                OpKind::Register => {
+                    self.bind(instr1.op_register(op_idx), instr2.op_register(op_idx))
+                }
+                OpKind::Memory => {
+                    instr1.memory_index_scale() == instr2.memory_index_scale()
+                        && self.bind(instr1.memory_base(), instr2.memory_base())
+                        && self.bind(instr1.memory_index(), instr2.memory_index())
+                }
+                _ => true,
+            };
+
+            if !bound {
+                return false;
+            }
+        }
+
+        // Implicit register uses (e.g. `mul` writing to edx:eax) have to be consistent with the
+        // mapping as well, otherwise renaming would change which value they operate on.
+        let used1: Vec<_> = factory.info(instr1).used_registers().to_vec();
+        let used2 = factory.info(instr2).used_registers();
+        used1.len() == used2.len()
+            && used1.iter().zip(used2).all(|(used1, used2)| {
+                used1.access() == used2.access() && self.bind(used1.register(), used2.register())
+            })
+    }
+
+    /// Tries to build a mapping that makes every instruction pair equal under `policy`. Returns
+    /// `None` if the instruction sequences differ in anything other than a consistent register
+    /// renaming.
+    pub fn build(
+        instructions1: &[InstructionWrapper],
+        instructions2: &[InstructionWrapper],
+        policy: &EqualityPolicy,
+    ) -> Option<Self> {
+        if instructions1.len() != instructions2.len() {
+            return None;
+        }
+
+        let mut mapping = Self::default();
+        let mut factory = InstructionInfoFactory::new();
+        for (instr1, instr2) in instructions1.iter().zip(instructions2) {
+            if !mapping.bind_instructions(&mut factory, policy, instr1, instr2) {
+                return None;
+            }
+        }
+
+        Some(mapping)
+    }
+}
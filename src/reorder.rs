@@ -0,0 +1,200 @@
+//! Experimental: treating functions that only differ by instruction scheduling as equal.
+
+use crate::basic_block;
+use crate::instruction_wrapper::InstructionWrapper;
+use iced_x86::{FlowControl, InstructionInfoFactory, OpAccess, Register};
+
+/// Everything an instruction reads or writes, as far as reordering is concerned.
+struct Effects {
+    reads: Vec<Register>,
+    writes: Vec<Register>,
+    reads_memory: bool,
+    writes_memory: bool,
+    flags_read: u32,
+    flags_written: u32,
+}
+
+impl Effects {
+    fn new(factory: &mut InstructionInfoFactory, instruction: &InstructionWrapper) -> Self {
+        let instr = instruction.get();
+        let info = factory.info(instr);
+
+        let mut reads = vec![];
+        let mut writes = vec![];
+        for used in info.used_registers() {
+            let register = used.register().full_register();
+            match used.access() {
+                OpAccess::Read | OpAccess::CondRead => reads.push(register),
+                OpAccess::Write | OpAccess::CondWrite => writes.push(register),
+                OpAccess::ReadWrite | OpAccess::ReadCondWrite => {
+                    reads.push(register);
+                    writes.push(register);
+                }
+                OpAccess::None | OpAccess::NoMemAccess => (),
+            }
+        }
+
+        let mut reads_memory = false;
+        let mut writes_memory = false;
+        for used in info.used_memory() {
+            match used.access() {
+                OpAccess::Read | OpAccess::CondRead => reads_memory = true,
+                OpAccess::Write | OpAccess::CondWrite => writes_memory = true,
+                OpAccess::ReadWrite | OpAccess::ReadCondWrite => {
+                    reads_memory = true;
+                    writes_memory = true;
+                }
+                OpAccess::None | OpAccess::NoMemAccess => (),
+            }
+        }
+
+        Self {
+            reads,
+            writes,
+            reads_memory,
+            writes_memory,
+            flags_read: instr.rflags_read(),
+            flags_written: instr.rflags_modified(),
+        }
+    }
+
+    /// True if executing the instructions in either order has the same result. Any memory write
+    /// is assumed to alias every other memory access.
+    fn independent_of(&self, other: &Self) -> bool {
+        let conflicts = |writes: &[Register], other: &[Register]| {
+            writes.iter().any(|register| other.contains(register))
+        };
+
+        !conflicts(&self.writes, &other.reads)
+            && !conflicts(&self.writes, &other.writes)
+            && !conflicts(&other.writes, &self.reads)
+            && !(self.writes_memory && (other.reads_memory || other.writes_memory))
+            && !(other.writes_memory && self.reads_memory)
+            && self.flags_written & (other.flags_read | other.flags_written) == 0
+            && other.flags_written & self.flags_read == 0
+    }
+}
+
+/// Checks whether `block2` is `block1` with some independent instructions swapped.
+fn block_equal_up_to_reordering(
+    factory: &mut InstructionInfoFactory,
+    block1: &[InstructionWrapper],
+    block2: &[InstructionWrapper],
+) -> bool {
+    if block1.len() != block2.len() {
+        return false;
+    }
+    if block1 == block2 {
+        return true;
+    }
+
+    // Pair every instruction in block1 with the first unused equal instruction in block2
+    let mut used = vec![false; block2.len()];
+    let mut positions = Vec::with_capacity(block1.len());
+    for instr1 in block1 {
+        let Some(position) = (0..block2.len()).find(|&idx| !used[idx] && block2[idx] == *instr1)
+        else {
+            return false;
+        };
+
+        used[position] = true;
+        positions.push(position);
+    }
+
+    let effects: Vec<_> = block1
+        .iter()
+        .map(|instr| Effects::new(factory, instr))
+        .collect();
+
+    for i in 0..block1.len() {
+        for j in i + 1..block1.len() {
+            if positions[i] < positions[j] {
+                continue;
+            }
+
+            // Branches, calls and returns have to stay where they are
+            let moves_control_flow = [&block1[i], &block1[j]]
+                .iter()
+                .any(|instr| instr.get().flow_control() != FlowControl::Next);
+            if moves_control_flow || !effects[i].independent_of(&effects[j]) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Returns true if both functions have the same basic blocks, and every block only differs by the
+/// order of instructions that don't depend on each other. This is conservative: any memory write
+/// prevents reordering across other memory accesses.
+pub fn equal_up_to_reordering(
+    instructions1: &[InstructionWrapper],
+    instructions2: &[InstructionWrapper],
+) -> bool {
+    let blocks1 = basic_block::split(instructions1);
+    let blocks2 = basic_block::split(instructions2);
+    if blocks1.len() != blocks2.len() {
+        return false;
+    }
+
+    let mut factory = InstructionInfoFactory::new();
+    blocks1.into_iter().zip(blocks2).all(|(block1, block2)| {
+        block_equal_up_to_reordering(&mut factory, &instructions1[block1], &instructions2[block2])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction_wrapper::InstructionIter;
+
+    fn decode(code: &[u8]) -> Vec<InstructionWrapper> {
+        InstructionIter::new(0x1000, code, code.len(), 8).collect()
+    }
+
+    // mov eax, ecx
+    const MOV_EAX_ECX: &[u8] = &[0x89, 0xC8];
+    // mov edx, esi
+    const MOV_EDX_ESI: &[u8] = &[0x89, 0xF2];
+    // mov ecx, edx
+    const MOV_ECX_EDX: &[u8] = &[0x89, 0xD1];
+    // mov [rdi], eax
+    const STORE_EAX: &[u8] = &[0x89, 0x07];
+    // mov ebx, [rsi]
+    const LOAD_EBX: &[u8] = &[0x8B, 0x1E];
+    const RET: &[u8] = &[0xC3];
+
+    #[test]
+    fn independent_swap_is_equal() {
+        let code1 = [MOV_EAX_ECX, MOV_EDX_ESI, RET].concat();
+        let code2 = [MOV_EDX_ESI, MOV_EAX_ECX, RET].concat();
+
+        assert!(equal_up_to_reordering(&decode(&code1), &decode(&code2)));
+    }
+
+    #[test]
+    fn dependent_swap_differs() {
+        // mov eax, ecx reads ecx, which mov ecx, edx writes
+        let code1 = [MOV_EAX_ECX, MOV_ECX_EDX, RET].concat();
+        let code2 = [MOV_ECX_EDX, MOV_EAX_ECX, RET].concat();
+
+        assert!(!equal_up_to_reordering(&decode(&code1), &decode(&code2)));
+    }
+
+    #[test]
+    fn memory_write_is_not_reordered() {
+        let code1 = [STORE_EAX, LOAD_EBX, RET].concat();
+        let code2 = [LOAD_EBX, STORE_EAX, RET].concat();
+
+        assert!(!equal_up_to_reordering(&decode(&code1), &decode(&code2)));
+    }
+
+    #[test]
+    fn different_instructions_differ() {
+        let code1 = [MOV_EAX_ECX, MOV_EDX_ESI, RET].concat();
+        let code2 = [MOV_EDX_ESI, MOV_ECX_EDX, RET].concat();
+
+        assert!(!equal_up_to_reordering(&decode(&code1), &decode(&code2)));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::compare::ProgramComparison;
+use crate::program::Program;
+use rustc_hash::FxHashMap;
+
+/// How much changed in one section, to see whether a change is confined to one part of the
+/// binary. Functions are counted in the section they're in in the second program, removed ones in
+/// the section they were in in the first.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct SectionStats {
+    pub section: String,
+    /// Functions in the section in the second program.
+    pub functions: usize,
+    pub changed: usize,
+    /// Functions that are only in the second program.
+    pub added: usize,
+    /// Functions that are only in the first program.
+    pub removed: usize,
+    /// Average similarity of the functions in both programs, counting unchanged ones as 1.0.
+    pub similarity: f32,
+}
+
+fn section_of<'a>(program: &'a Program, name: &str) -> &'a str {
+    program
+        .functions
+        .get(name)
+        .map_or("", |function| function.section_name(program))
+}
+
+/// Breaks `comparison` of `program1` and `program2` down by section, sorted by the number of
+/// changed functions and then by name.
+pub fn section_stats(
+    program1: &Program,
+    program2: &Program,
+    comparison: &ProgramComparison,
+) -> Vec<SectionStats> {
+    let mut sections: FxHashMap<&str, SectionStats> = FxHashMap::default();
+    let mut changed_similarity: FxHashMap<&str, f32> = FxHashMap::default();
+
+    for function in program2.functions.values() {
+        sections
+            .entry(function.section_name(program2))
+            .or_default()
+            .functions += 1;
+    }
+    for change in &comparison.changes {
+        let section = change.sections().1;
+        sections.entry(section).or_default().changed += 1;
+        *changed_similarity.entry(section).or_default() += change.similarity();
+    }
+    for function in &comparison.only_in_secondary {
+        let section = section_of(program2, function.name());
+        sections.entry(section).or_default().added += 1;
+    }
+    for function in &comparison.only_in_primary {
+        let section = section_of(program1, function.name());
+        sections.entry(section).or_default().removed += 1;
+    }
+
+    let mut stats: Vec<_> = sections
+        .into_iter()
+        .map(|(section, mut stats)| {
+            let matched = stats.functions.saturating_sub(stats.added);
+            let unchanged = matched.saturating_sub(stats.changed);
+            let similarity_sum =
+                changed_similarity.get(section).copied().unwrap_or_default() + unchanged as f32;
+            stats.similarity = if matched == 0 {
+                0.0
+            } else {
+                similarity_sum / matched as f32
+            };
+            stats.section = section.to_string();
+
+            stats
+        })
+        .collect();
+    stats.sort_by(|a, b| b.changed.cmp(&a.changed).then(a.section.cmp(&b.section)));
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_id::BuildStamp;
+    use crate::compare::{compare_programs, CompareOptions};
+    use crate::program::{CodeCoverage, Function};
+    use object::{Architecture, BinaryFormat, SectionIndex};
+
+    const TEXT: SectionIndex = SectionIndex(1);
+    const HOT: SectionIndex = SectionIndex(2);
+
+    /// A program with the given functions, each as (name, section, code). Every function gets its
+    /// own 0x10 bytes of its section.
+    fn program(functions: &[(&str, SectionIndex, &[u8])]) -> Program {
+        let mut sections: FxHashMap<SectionIndex, Vec<u8>> = FxHashMap::default();
+        let mut function_map = FxHashMap::default();
+        let mut symbol_map = FxHashMap::default();
+        for (name, section_idx, code) in functions {
+            let base = section_idx.0 as u64 * 0x1000;
+            let data = sections.entry(*section_idx).or_default();
+            let address = base + data.len() as u64;
+            data.extend_from_slice(code);
+            data.resize(data.len() + 0x10 - code.len(), 0xCC);
+
+            function_map.insert(
+                name.to_string(),
+                Function::new(*section_idx, base, address, code.len() as u64),
+            );
+            symbol_map.insert(address, name.to_string());
+        }
+        let function_index = Program::build_function_index(&function_map);
+
+        Program {
+            format: BinaryFormat::Elf,
+            architecture: Architecture::X86_64,
+            pointer_size: 8,
+            bitness: 64,
+            functions: function_map,
+            symbol_map,
+            sections,
+            section_names: [(TEXT, ".text".to_string()), (HOT, ".text.hot".to_string())]
+                .into_iter()
+                .collect(),
+            function_index,
+            relocations: FxHashMap::default(),
+            plt_entries: FxHashMap::default(),
+            build_stamp: BuildStamp::default(),
+            content_hash: 0,
+            coverage: CodeCoverage::default(),
+        }
+    }
+
+    #[test]
+    fn breaks_changes_down_by_section() {
+        // mov eax, ecx; ret / add eax, ecx; ret / ret
+        let (mov, add, ret): (&[u8], &[u8], &[u8]) =
+            (&[0x89, 0xC8, 0xC3], &[0x01, 0xC8, 0xC3], &[0xC3]);
+        let program1 = program(&[
+            ("a", TEXT, mov),
+            ("b", TEXT, ret),
+            ("c", HOT, mov),
+            ("removed", HOT, ret),
+        ]);
+        let program2 = program(&[
+            ("a", TEXT, mov),
+            ("b", TEXT, ret),
+            ("c", HOT, add),
+            ("added", HOT, ret),
+        ]);
+
+        let comparison =
+            compare_programs(&program1, &program2, &CompareOptions::default(), None).unwrap();
+        let stats = section_stats(&program1, &program2, &comparison);
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats[1],
+            SectionStats {
+                section: ".text".to_string(),
+                functions: 2,
+                similarity: 1.0,
+                ..Default::default()
+            }
+        );
+        let hot = &stats[0];
+        assert_eq!(hot.section, ".text.hot");
+        assert_eq!(
+            (hot.functions, hot.changed, hot.added, hot.removed),
+            (2, 1, 1, 1)
+        );
+        assert_eq!(hot.similarity, comparison.changes[0].similarity());
+    }
+}
@@ -0,0 +1,167 @@
+//! Fuzzy hashes of functions, for following a function across builds when its symbol doesn't
+//! stay the same.
+
+use crate::compare::collect_instructions;
+use crate::instruction_wrapper::EqualityPolicy;
+use crate::program::{Function, Program};
+use iced_x86::{FlowControl, Instruction, OpKind};
+use rustc_hash::FxHasher;
+use std::hash::Hasher;
+use std::ops::Range;
+
+/// Symbol of the function that `instr` calls or jumps to, unless it stays within `function_range`.
+fn call_target<'a>(
+    program: &'a Program,
+    instr: &Instruction,
+    function_range: &Range<u64>,
+) -> Option<&'a str> {
+    // A relocation names the target even if its address hasn't been filled in yet
+    if let Some(relocation) =
+        (instr.ip()..instr.next_ip()).find_map(|location| program.relocations.get(&location))
+    {
+        return Some(&relocation.symbol);
+    }
+
+    if matches!(
+        instr.op0_kind(),
+        OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+    ) {
+        let target = instr.near_branch_target();
+        if function_range.contains(&target) {
+            return None;
+        }
+        if let Some(import) = program.plt_entries.get(&target) {
+            return Some(import);
+        }
+        return program.symbolize(target).map(|(_, name)| name);
+    }
+
+    // `call [rip+x]` through the GOT
+    if instr.is_ip_rel_memory_operand() {
+        return program
+            .relocations
+            .get(&instr.ip_rel_memory_address())
+            .map(|relocation| relocation.symbol.as_str());
+    }
+
+    None
+}
+
+/// Hashes the mnemonics of `function`'s instructions and the symbols of the functions it calls.
+/// Registers, immediates, displacements and addresses are left out, so the hash survives
+/// recompiles that only move things around or pick different registers, which the exact
+/// comparison doesn't. Unrelated functions can hash the same, especially small ones.
+///
+/// Hashes only stay comparable between runs of the same tfbindiff build, since they depend on
+/// how the decoder numbers mnemonics. Returns `None` if the program can't be disassembled.
+pub fn semantic_hash(program: &Program, function: &Function) -> Option<u64> {
+    if !program.can_disassemble() {
+        return None;
+    }
+
+    let (instructions, _) =
+        collect_instructions(program, function, None, EqualityPolicy::default());
+    let function_range = function.address()..function.address() + function.length() as u64;
+
+    let mut hasher = FxHasher::default();
+    for instr in &instructions {
+        let instr = instr.get();
+        hasher.write_u32(instr.mnemonic() as u32);
+
+        if matches!(
+            instr.flow_control(),
+            FlowControl::Call
+                | FlowControl::IndirectCall
+                | FlowControl::UnconditionalBranch
+                | FlowControl::IndirectBranch
+        ) {
+            if let Some(name) = call_target(program, instr, &function_range) {
+                hasher.write(name.as_bytes());
+                // Keeps the name from running into the next mnemonic
+                hasher.write_u8(0xff);
+            }
+        }
+    }
+
+    Some(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_id::BuildStamp;
+    use crate::program::{CodeCoverage, Relocation};
+    use object::{Architecture, BinaryFormat, SectionIndex};
+    use rustc_hash::FxHashMap;
+
+    // push rbp; mov rbp, rsp; sub rsp, <depth>
+    fn prologue(depth: u8) -> Vec<u8> {
+        vec![0x55, 0x48, 0x89, 0xE5, 0x48, 0x83, 0xEC, depth]
+    }
+
+    // call <rel32>, relocated at its displacement
+    const CALL: &[u8] = &[0xE8, 0x00, 0x00, 0x00, 0x00];
+    const LEAVE_RET: &[u8] = &[0xC9, 0xC3];
+
+    /// Hash of a function `f` at 0x1000 whose call is relocated against `callee`.
+    fn hash(code: Vec<u8>, callee: &str) -> Option<u64> {
+        let section_idx = SectionIndex(1);
+        let address = 0x1000;
+        let function = Function::new(section_idx, address, address, code.len() as u64);
+        let call_offset = code
+            .windows(CALL.len())
+            .position(|window| window == CALL)
+            .unwrap();
+
+        let mut functions = FxHashMap::default();
+        functions.insert("f".to_string(), function);
+        let function_index = Program::build_function_index(&functions);
+        let relocation = Relocation {
+            symbol: callee.to_string(),
+            size: 4,
+            addend: -4,
+            relative: true,
+        };
+
+        let program = Program {
+            format: BinaryFormat::Elf,
+            architecture: Architecture::X86_64,
+            pointer_size: 8,
+            bitness: 64,
+            functions,
+            symbol_map: [(address, "f".to_string())].into_iter().collect(),
+            sections: [(section_idx, code)].into_iter().collect(),
+            section_names: [(section_idx, ".text".to_string())].into_iter().collect(),
+            function_index,
+            relocations: [(address + call_offset as u64 + 1, relocation)]
+                .into_iter()
+                .collect(),
+            plt_entries: FxHashMap::default(),
+            build_stamp: BuildStamp::default(),
+            content_hash: 0,
+            coverage: CodeCoverage::default(),
+        };
+
+        semantic_hash(&program, &program.functions["f"])
+    }
+
+    #[test]
+    fn ignores_registers_and_immediates() {
+        // mov eax, ecx / mov eax, edx
+        let code1 = [&prologue(0x10), &[0x89, 0xC8][..], CALL, LEAVE_RET].concat();
+        let code2 = [&prologue(0x20), &[0x89, 0xD0][..], CALL, LEAVE_RET].concat();
+
+        assert!(hash(code1.clone(), "g").is_some());
+        assert_eq!(hash(code1, "g"), hash(code2, "g"));
+    }
+
+    #[test]
+    fn depends_on_mnemonics_and_callees() {
+        let code = [&prologue(0x10), CALL, LEAVE_RET].concat();
+        // add eax, ecx
+        let added = [&prologue(0x10), &[0x01, 0xC8][..], CALL, LEAVE_RET].concat();
+
+        assert_ne!(hash(code.clone(), "g"), hash(code.clone(), "h"));
+        assert_ne!(hash(code, "g"), hash(added, "g"));
+    }
+}
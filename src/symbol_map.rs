@@ -0,0 +1,50 @@
+use rustc_hash::FxHashMap;
+
+/// Parses a symbol map with one `address name` pair per line, e.g. exported from a disassembler
+/// after identifying the functions of a stripped binary. Addresses are hex, with or without a
+/// `0x` prefix. Anything after the name (like a size column) is ignored, as are empty lines and
+/// lines starting with `#`.
+pub fn parse(text: &str) -> Result<FxHashMap<u64, String>, String> {
+    let mut symbols = FxHashMap::default();
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(address), Some(name)) = (fields.next(), fields.next()) else {
+            return Err(format!("line {}: expected `address name`", line_idx + 1));
+        };
+        let address = address
+            .strip_prefix("0x")
+            .or_else(|| address.strip_prefix("0X"))
+            .unwrap_or(address);
+        let address = u64::from_str_radix(address, 16)
+            .map_err(|_| format!("line {}: invalid address {address}", line_idx + 1))?;
+
+        symbols.insert(address, name.to_string());
+    }
+
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses_and_names() {
+        let symbols = parse("# from ghidra\n0x1129 helper\n\n113b api 0x2d\n").unwrap();
+
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[&0x1129], "helper");
+        assert_eq!(symbols[&0x113b], "api");
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        assert!(parse("0x1000\n").is_err());
+        assert!(parse("main 0x1000\n").is_err());
+    }
+}
@@ -1,7 +1,10 @@
 use crate::instruction_wrapper::InstructionWrapper;
 use crate::program::Program;
 use cpp_demangle::DemangleOptions;
-use iced_x86::Formatter;
+use iced_x86::{FlowControl, Formatter, OpKind, Register};
+use rustc_hash::FxHashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 pub fn demangle_symbol(name: &str) -> Option<String> {
     let sym = cpp_demangle::Symbol::new(name).ok()?;
@@ -10,45 +13,210 @@ pub fn demangle_symbol(name: &str) -> Option<String> {
     sym.demangle(&options).ok()
 }
 
-struct ProgramSymbolResolver {
-    // Why does this have a static lifetime? Because the iced formatter api is stupid and takes an
-    // owned box, instead of a reference.
-    program: &'static Program,
+/// Answers iced's symbol lookups with the names that `ProgramInstructionFormatter` resolved for
+/// the instruction it's formatting. The program can't be looked up from here, since iced wants
+/// an owned resolver that outlives any borrow.
+struct OperandSymbolResolver {
+    // Shared with the owning ProgramInstructionFormatter
+    operands: Rc<RefCell<Vec<Option<OperandSymbol>>>>,
 }
 
-impl iced_x86::SymbolResolver for ProgramSymbolResolver {
+/// The name an operand refers to.
+struct OperandSymbol {
+    /// The address the operand is formatted from, see `operand_address`
+    address: u64,
+    /// Address of the symbol, which is less than `address` for operands shown as `symbol+offset`
+    symbol_address: u64,
+    name: String,
+}
+
+impl iced_x86::SymbolResolver for OperandSymbolResolver {
     fn symbol(
         &mut self,
         _instruction: &iced_x86::Instruction,
         _operand: u32,
-        _instruction_operand: Option<u32>,
+        instruction_operand: Option<u32>,
         address: u64,
         _address_size: u32,
     ) -> Option<iced_x86::SymbolResult<'_>> {
-        let mangled_name = self.program.symbol_map.get(&address)?;
-        let name = demangle_symbol(mangled_name).unwrap_or_else(|| mangled_name.clone());
+        let operands = self.operands.borrow();
+        let symbol = operands.get(instruction_operand? as usize)?.as_ref()?;
 
-        Some(iced_x86::SymbolResult::with_string(address, name))
+        (symbol.address == address).then(|| {
+            iced_x86::SymbolResult::with_string(symbol.symbol_address, symbol.name.clone())
+        })
     }
 }
 
-pub struct ProgramInstructionFormatter {
+/// The address iced looks up a symbol for when it formats `operand`.
+fn operand_address(instruction: &iced_x86::Instruction, operand: u32) -> Option<u64> {
+    let immediate = || instruction.immediate(operand);
+
+    Some(match instruction.op_kind(operand) {
+        OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+            instruction.near_branch_target()
+        }
+        OpKind::FarBranch16 => instruction.far_branch16().into(),
+        OpKind::FarBranch32 => instruction.far_branch32().into(),
+        OpKind::Immediate8 | OpKind::Immediate8_2nd => immediate() as u8 as u64,
+        OpKind::Immediate16 | OpKind::Immediate8to16 => immediate() as u16 as u64,
+        OpKind::Immediate32 | OpKind::Immediate8to32 => immediate() as u32 as u64,
+        OpKind::Immediate64 | OpKind::Immediate8to64 | OpKind::Immediate32to64 => immediate(),
+        OpKind::Memory => {
+            // Sized like the registers, or the displacement if there are none
+            let address_size = [instruction.memory_base(), instruction.memory_index()]
+                .into_iter()
+                .find(|register| *register != Register::None)
+                .map_or(instruction.memory_displ_size() as usize, Register::size);
+            if address_size == 8 {
+                instruction.memory_displacement64()
+            } else {
+                instruction.memory_displacement32().into()
+            }
+        }
+        _ => return None,
+    })
+}
+
+pub struct ProgramInstructionFormatter<'a> {
     formatter: iced_x86::IntelFormatter,
+    // None for code that isn't from a program, which only gets labels
+    program: Option<&'a Program>,
+    // Branch targets inside the function currently being formatted
+    labels: FxHashMap<u64, String>,
+    operands: Rc<RefCell<Vec<Option<OperandSymbol>>>>,
 }
 
-impl ProgramInstructionFormatter {
-    pub fn new(program: &'static Program) -> Self {
+impl<'a> ProgramInstructionFormatter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self::with_program(Some(program))
+    }
+
+    /// A formatter for code that isn't part of a program, which shows operands by their
+    /// addresses. Branches within the function still get labels.
+    pub fn without_symbols() -> Self {
+        Self::with_program(None)
+    }
+
+    fn with_program(program: Option<&'a Program>) -> Self {
+        let operands = Rc::new(RefCell::new(vec![]));
+
         Self {
             formatter: iced_x86::IntelFormatter::with_options(
-                Some(Box::new(ProgramSymbolResolver { program })),
+                Some(Box::new(OperandSymbolResolver {
+                    operands: operands.clone(),
+                })),
                 None,
             ),
+            program,
+            labels: FxHashMap::default(),
+            operands,
+        }
+    }
+
+    /// Finds the name that `operand` of `instruction` refers to.
+    fn resolve_operand(
+        &self,
+        instruction: &iced_x86::Instruction,
+        operand: u32,
+    ) -> Option<OperandSymbol> {
+        let address = operand_address(instruction, operand)?;
+        let symbol = |symbol_address, name| {
+            Some(OperandSymbol {
+                address,
+                symbol_address,
+                name,
+            })
+        };
+        let is_branch = matches!(
+            instruction.op_kind(operand),
+            OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+        );
+
+        if is_branch {
+            if let Some(label) = self.labels.get(&address) {
+                return symbol(address, label.clone());
+            }
+        }
+
+        let program = self.program?;
+
+        // A relocation says what the operand refers to even if the address hasn't been filled in
+        // yet, or points to a different location in each build
+        if let Some((location, relocation)) = program.operand_relocation(instruction, operand) {
+            let mangled_name = &relocation.symbol;
+            let name = demangle_symbol(mangled_name).unwrap_or_else(|| mangled_name.clone());
+            let offset = relocation.symbol_offset(location, instruction.next_ip());
+            return symbol(address.wrapping_sub(offset as u64), name);
         }
+
+        // Calls into shared libraries go through a PLT stub, which isn't much use by its address
+        if let Some(import) = program.plt_entries.get(&address) {
+            let name = demangle_symbol(import).unwrap_or_else(|| import.clone());
+            return symbol(address, format!("{name}@plt"));
+        }
+        // Or straight through the GOT, e.g. `call [rip+x]` with -fno-plt
+        if !is_branch {
+            if let Some(import) = program.relocations.get(&address) {
+                let import = &import.symbol;
+                let name = demangle_symbol(import).unwrap_or_else(|| import.clone());
+                return symbol(address, format!("{name}@got"));
+            }
+        }
+
+        // Returning the symbol's own address makes iced display the operand as `symbol+offset`
+        let (symbol_address, mangled_name) = program.symbolize(address)?;
+        let name = demangle_symbol(mangled_name).unwrap_or_else(|| mangled_name.to_string());
+
+        symbol(symbol_address, name)
+    }
+
+    /// Assigns labels to the branch targets within a function, so that branches are displayed as
+    /// `jmp .L3` instead of by their absolute address. Labels are numbered in address order, which
+    /// means identical control flow is formatted identically even if the code around it moved.
+    pub fn set_function(&mut self, instructions: &[InstructionWrapper]) {
+        self.labels.clear();
+
+        let (Some(first), Some(last)) = (instructions.first(), instructions.last()) else {
+            return;
+        };
+        let function_range = first.get().ip()..last.get().next_ip();
+
+        let mut targets: Vec<u64> = instructions
+            .iter()
+            .map(InstructionWrapper::get)
+            .filter(|instr| {
+                matches!(
+                    instr.flow_control(),
+                    FlowControl::UnconditionalBranch | FlowControl::ConditionalBranch
+                ) && matches!(
+                    instr.op0_kind(),
+                    OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+                )
+            })
+            .map(|instr| instr.near_branch_target())
+            .filter(|target| function_range.contains(target))
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+
+        self.labels.extend(
+            targets
+                .into_iter()
+                .enumerate()
+                .map(|(idx, target)| (target, format!(".L{idx}"))),
+        );
     }
 
     pub fn format(&mut self, instruction: &InstructionWrapper) -> String {
+        let instruction = instruction.get();
+        let operands = (0..instruction.op_count())
+            .map(|operand| self.resolve_operand(instruction, operand))
+            .collect();
+        *self.operands.borrow_mut() = operands;
+
         let mut out = String::new();
-        self.formatter.format(instruction.get(), &mut out);
+        self.formatter.format(instruction, &mut out);
 
         out
     }